@@ -0,0 +1,83 @@
+use crate::audio::encode_opus;
+use crate::config::ArchiveConfig;
+use jiff::Zoned;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, error};
+
+fn archive_dir() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join("wayvoice").join("audio"))
+}
+
+/// Save a recording as `<timestamp>.ogg`, or `.wav` when opusenc is
+/// unavailable, then prune old ones. Returns where it was written.
+pub async fn save(wav: &[u8], config: &ArchiveConfig, opus_bitrate: u32) -> Option<PathBuf> {
+    let dir = archive_dir()?;
+    let start = std::time::Instant::now();
+    // Milliseconds, so clips finished in the same second don't collide
+    let name = Zoned::now().strftime("%Y-%m-%d_%H-%M-%S-%3f").to_string();
+    let (path, data) = match encode_opus(wav, opus_bitrate).await {
+        Ok(opus) => (dir.join(format!("{name}.ogg")), opus),
+        Err(e) => {
+            debug!("archive: {e}, keeping WAV");
+            (dir.join(format!("{name}.wav")), wav.to_vec())
+        }
+    };
+
+    // Voice recordings are nobody else's business
+    let written = async {
+        tokio::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)
+            .await?;
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .await?;
+        file.write_all(&data).await
+    };
+    if let Err(e) = written.await {
+        error!("Failed to archive recording to {path:?}: {e}");
+        return None;
+    }
+    if let Err(e) = prune(&dir, &path, config).await {
+        error!("Failed to prune {dir:?}: {e}");
+    }
+    debug!("archive: {path:?} in {:?}", start.elapsed());
+    Some(path)
+}
+
+/// Delete recordings older than `max_age_days`, then the oldest ones until
+/// the rest fit in `max_size_mb`. `keep` is never deleted.
+async fn prune(dir: &Path, keep: &Path, config: &ArchiveConfig) -> std::io::Result<()> {
+    let mut files = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if metadata.is_file() {
+            files.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+    }
+    // Newest first
+    files.sort_by_key(|(modified, ..)| std::cmp::Reverse(*modified));
+
+    let max_age = Duration::from_secs(config.max_age_days.saturating_mul(24 * 60 * 60));
+    let max_bytes = config.max_size_mb.saturating_mul(1024 * 1024);
+    let now = SystemTime::now();
+    let mut total = 0;
+    for (modified, len, path) in files {
+        total += len;
+        let too_old =
+            config.max_age_days > 0 && now.duration_since(modified).unwrap_or_default() > max_age;
+        let too_big = config.max_size_mb > 0 && total > max_bytes;
+        if (too_old || too_big) && path != keep {
+            debug!("archive: removing {path:?}");
+            tokio::fs::remove_file(&path).await?;
+        }
+    }
+    Ok(())
+}
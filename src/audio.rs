@@ -0,0 +1,237 @@
+use crate::error::WayvoiceError;
+use crate::recorder::{SAMPLE_RATE, wav_bytes};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::debug;
+
+/// Speech is never boosted by more than this; a clip that quiet is
+/// probably just noise.
+const MAX_GAIN_DB: f32 = 30.0;
+/// Boosting stops short of clipping the loudest sample.
+const PEAK_CEILING_DB: f32 = -1.0;
+/// 20 ms frames quieter than this are left out of the loudness measurement,
+/// so pauses between words don't make speech look quieter than it is.
+const GATE_DB: f32 = -50.0;
+const FRAME_LEN: usize = SAMPLE_RATE as usize / 50;
+/// `compress` squashes peaks above the threshold by the ratio, so quiet
+/// syllables can be raised further.
+const COMPRESS_THRESHOLD_DB: f32 = -20.0;
+const COMPRESS_RATIO: f32 = 4.0;
+/// How fast the compressor lets go after a peak, in samples (100 ms).
+const COMPRESS_RELEASE: f32 = SAMPLE_RATE as f32 / 10.0;
+
+/// File name and MIME type to upload `audio_data` under, sniffed from its header.
+pub fn upload_format(audio_data: &[u8]) -> (&'static str, &'static str) {
+    let mp3_frame = audio_data.len() >= 2 && audio_data[0] == 0xff && audio_data[1] & 0xe0 == 0xe0;
+    if audio_data.starts_with(b"OggS") {
+        ("audio.ogg", "audio/ogg")
+    } else if audio_data.starts_with(b"fLaC") {
+        ("audio.flac", "audio/flac")
+    } else if audio_data.starts_with(b"ID3") || mp3_frame {
+        ("audio.mp3", "audio/mpeg")
+    } else {
+        ("audio.wav", "audio/wav")
+    }
+}
+
+/// Compress a WAV recording to Ogg/Opus with `opusenc`.
+pub async fn encode_opus(wav: &[u8], bitrate_kbps: u32) -> Result<Vec<u8>, WayvoiceError> {
+    let start = std::time::Instant::now();
+    let mut cmd = Command::new("opusenc");
+    cmd.args(["--quiet", "--bitrate", &bitrate_kbps.to_string(), "-", "-"]);
+    let opus = pipe_through(cmd, "opusenc", wav).await?;
+    debug!(
+        "opus: {} -> {} bytes in {:?}",
+        wav.len(),
+        opus.len(),
+        start.elapsed()
+    );
+    Ok(opus)
+}
+
+/// Whether `audio_data` is a RIFF/WAV file.
+pub fn is_wav(audio_data: &[u8]) -> bool {
+    audio_data.starts_with(b"RIFF")
+}
+
+/// A WAV written to a pipe (`sox ... -t wav -`) can't go back to fill in
+/// its sizes, so set them from the bytes that actually arrived.
+pub fn fix_wav_sizes(wav: &mut [u8]) {
+    if !is_wav(wav) || wav.len() < 12 {
+        return;
+    }
+    let len = wav.len();
+    wav[4..8].copy_from_slice(&((len - 8) as u32).to_le_bytes());
+    let mut at = 12;
+    while at + 8 <= len {
+        let size = u32::from_le_bytes(wav[at + 4..at + 8].try_into().expect("4 bytes")) as usize;
+        if &wav[at..at + 4] == b"data" {
+            wav[at + 4..at + 8].copy_from_slice(&((len - at - 8) as u32).to_le_bytes());
+            return;
+        }
+        // Chunks are padded to an even length
+        at += 8 + size + size % 2;
+    }
+}
+
+/// Run the recording through ffmpeg's RNNoise filter (`arnndn`) when a model
+/// is given, or its built-in FFT denoiser otherwise.
+pub async fn denoise(audio_data: &[u8], rnnoise_model: &str) -> Result<Vec<u8>, WayvoiceError> {
+    let filter = if rnnoise_model.is_empty() {
+        "afftdn".to_string()
+    } else {
+        // ffmpeg filter args need ':' and '\' escaped
+        let model = rnnoise_model.replace('\\', "\\\\").replace(':', "\\:");
+        format!("arnndn=m={model}")
+    };
+
+    let start = std::time::Instant::now();
+    let wav = ffmpeg_to_wav(audio_data, Some(&filter)).await?;
+    debug!("denoise ({filter}): {:?}", start.elapsed());
+    Ok(wav)
+}
+
+/// Bring the speech in a recording to `target_db` RMS (dBFS), optionally
+/// compressing peaks first. `None` for anything but the recorder's 16 kHz
+/// mono WAV, or a clip with no speech-level audio to measure.
+pub fn normalize(wav: &[u8], target_db: f32, compress: bool) -> Option<Vec<u8>> {
+    let mut samples = pcm_samples(wav)?;
+    if compress {
+        compress_peaks(&mut samples);
+    }
+
+    let loud_frames: Vec<f32> = samples
+        .chunks(FRAME_LEN)
+        .map(mean_square)
+        .filter(|&ms| to_db(ms.sqrt()) > GATE_DB)
+        .collect();
+    if loud_frames.is_empty() {
+        return None;
+    }
+    let rms = (loud_frames.iter().sum::<f32>() / loud_frames.len() as f32).sqrt();
+    let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    let gain_db = (target_db - to_db(rms))
+        .min(MAX_GAIN_DB)
+        .min(PEAK_CEILING_DB - to_db(peak));
+    debug!("normalize: {:.1} dB RMS, gain {gain_db:+.1} dB", to_db(rms));
+
+    let gain = 10f32.powf(gain_db / 20.0);
+    let samples: Vec<i16> = samples
+        .iter()
+        .map(|s| (s * gain * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+        .collect();
+    Some(wav_bytes(&samples))
+}
+
+/// Samples of a 16-bit 16 kHz mono WAV with the plain 44-byte header
+/// `wav_bytes` writes, scaled to -1..1.
+fn pcm_samples(wav: &[u8]) -> Option<Vec<f32>> {
+    let field = |at: usize, len: usize| wav.get(at..at + len);
+    let u16_at = |at| field(at, 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |at| field(at, 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let plain = is_wav(wav)
+        && u16_at(22)? == 1
+        && u32_at(24)? == SAMPLE_RATE
+        && u16_at(34)? == 16
+        && field(36, 4)? == b"data";
+    plain.then(|| {
+        wav[44..]
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / i16::MAX as f32)
+            .collect()
+    })
+}
+
+/// A feed-forward compressor with instant attack: the envelope jumps to
+/// each peak and decays over `COMPRESS_RELEASE`.
+fn compress_peaks(samples: &mut [f32]) {
+    let release = (-1.0 / COMPRESS_RELEASE).exp();
+    let mut envelope = 0.0f32;
+    for sample in samples {
+        envelope = sample.abs().max(envelope * release);
+        let over = to_db(envelope) - COMPRESS_THRESHOLD_DB;
+        if over > 0.0 {
+            *sample *= 10f32.powf(-over * (1.0 - 1.0 / COMPRESS_RATIO) / 20.0);
+        }
+    }
+}
+
+fn mean_square(frame: &[f32]) -> f32 {
+    frame.iter().map(|s| s * s).sum::<f32>() / frame.len().max(1) as f32
+}
+
+fn to_db(amplitude: f32) -> f32 {
+    20.0 * amplitude.max(1e-9).log10()
+}
+
+/// Decode any format ffmpeg understands (mp3, m4a, ogg, ...) to the 16 kHz
+/// mono WAV the recorder produces.
+pub async fn decode_to_wav(audio_data: &[u8]) -> Result<Vec<u8>, WayvoiceError> {
+    let start = std::time::Instant::now();
+    let wav = ffmpeg_to_wav(audio_data, None).await?;
+    debug!(
+        "decode: {} -> {} bytes in {:?}",
+        audio_data.len(),
+        wav.len(),
+        start.elapsed()
+    );
+    Ok(wav)
+}
+
+async fn ffmpeg_to_wav(audio_data: &[u8], filter: Option<&str>) -> Result<Vec<u8>, WayvoiceError> {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-hide_banner", "-loglevel", "error", "-i", "pipe:0"]);
+    if let Some(filter) = filter {
+        cmd.args(["-af", filter]);
+    }
+    cmd.args(["-ar", &SAMPLE_RATE.to_string(), "-ac", "1"])
+        .args(["-f", "s16le", "pipe:1"]);
+    let pcm = pipe_through(cmd, "ffmpeg", audio_data).await?;
+
+    let samples: Vec<i16> = pcm
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    Ok(wav_bytes(&samples))
+}
+
+/// Feed `input` to `cmd` on stdin and collect its stdout.
+async fn pipe_through(
+    mut cmd: Command,
+    name: &str,
+    input: &[u8],
+) -> Result<Vec<u8>, WayvoiceError> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| WayvoiceError::Audio(format!("Failed to start {name}: {e}")))?;
+
+    // Feed stdin concurrently so a full stdout pipe can't deadlock us
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| WayvoiceError::Audio(format!("{name} has no stdin")))?;
+    let input = input.to_vec();
+    let writer = tokio::spawn(async move {
+        let _ = stdin.write_all(&input).await;
+    });
+
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(|e| WayvoiceError::Audio(format!("{name} failed: {e}")))?;
+    let _ = writer.await;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(WayvoiceError::Audio(format!(
+            "{name} failed ({}): {}",
+            output.status,
+            stderr.trim()
+        )));
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,288 @@
+use crate::notify::notify;
+use std::collections::HashMap;
+use std::process::Stdio;
+use tokio::process::Command;
+use tracing::{debug, error};
+
+/// `[commands]`, compiled once when the config is loaded: spoken phrases and
+/// the shell command each one runs in command mode.
+#[derive(Debug, Clone, Default)]
+pub struct Grammar {
+    /// Most literal words first, so "open terminal" wins over "open {app}".
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    phrase: String,
+    tokens: Vec<Token>,
+    command: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    /// `{name}`: one or more words, passed to the command as `$name`.
+    Capture(String),
+}
+
+impl Grammar {
+    /// Invalid phrases are reported and skipped rather than failing the
+    /// whole config.
+    pub fn new(commands: &HashMap<String, String>) -> Self {
+        let mut rules: Vec<Rule> = commands
+            .iter()
+            .filter_map(|(phrase, command)| match parse_phrase(phrase) {
+                Ok(tokens) => Some(Rule {
+                    phrase: phrase.clone(),
+                    tokens,
+                    command: command.clone(),
+                }),
+                Err(e) => {
+                    error!("Invalid command phrase {phrase:?}: {e}");
+                    None
+                }
+            })
+            .collect();
+        rules.sort_by(|a, b| {
+            b.literal_words()
+                .cmp(&a.literal_words())
+                .then_with(|| a.phrase.cmp(&b.phrase))
+        });
+        Self { rules }
+    }
+
+    /// The first rule matching the whole of `text`, with its captures.
+    fn lookup(&self, text: &str) -> Option<(&Rule, Vec<(String, String)>)> {
+        let words = words(text);
+        self.rules.iter().find_map(|rule| {
+            let mut captures = Vec::new();
+            match_words(&rule.tokens, &words, &mut captures).then_some((rule, captures))
+        })
+    }
+}
+
+impl Rule {
+    fn literal_words(&self) -> usize {
+        self.tokens
+            .iter()
+            .filter(|token| matches!(token, Token::Word(_)))
+            .count()
+    }
+}
+
+/// Why a `[commands]` key can't be used, for `config validate`.
+pub(crate) fn check_phrase(phrase: &str) -> Result<(), String> {
+    parse_phrase(phrase).map(|_| ())
+}
+
+/// Split a `[commands]` key into words and `{name}` captures. Names become
+/// environment variables, so they're limited to lowercase letters, digits
+/// and underscores.
+fn parse_phrase(phrase: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    for part in phrase.split_whitespace() {
+        if let Some(name) = part.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+            let valid = name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+            if !valid {
+                return Err(format!(
+                    "capture name {name:?} must be lowercase_snake_case"
+                ));
+            }
+            tokens.push(Token::Capture(name.to_string()));
+        } else if part.contains(['{', '}']) {
+            return Err(format!("{part:?} is not a {{name}} capture"));
+        } else {
+            tokens.extend(words(part).into_iter().map(Token::Word));
+        }
+    }
+    if tokens.is_empty() {
+        return Err("phrase is empty".to_string());
+    }
+    Ok(tokens)
+}
+
+/// Lowercase words without punctuation, so "Open terminal." matches
+/// "open terminal".
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .map(|word| word.trim_matches('\''))
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Match `tokens` against all of `words`. A capture takes as few words as
+/// it can and grows only when the rest of the phrase fails to match.
+fn match_words(tokens: &[Token], words: &[String], captures: &mut Vec<(String, String)>) -> bool {
+    match tokens.split_first() {
+        None => words.is_empty(),
+        Some((Token::Word(word), rest)) => {
+            words.first() == Some(word) && match_words(rest, &words[1..], captures)
+        }
+        Some((Token::Capture(name), rest)) => {
+            for taken in 1..=words.len() {
+                captures.push((name.clone(), words[..taken].join(" ")));
+                if match_words(rest, &words[taken..], captures) {
+                    return true;
+                }
+                captures.pop();
+            }
+            false
+        }
+    }
+}
+
+/// Run the command whose phrase matches `text`, in the background with the
+/// captures as environment variables and the text in `$WAYVOICE_TEXT`.
+pub async fn run(grammar: &Grammar, text: &str) {
+    let Some((rule, captures)) = grammar.lookup(text) else {
+        debug!("no command matches");
+        notify(&format!("No command for \"{text}\"")).await;
+        return;
+    };
+    debug!("command {:?}: {}", rule.phrase, rule.command);
+
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", &rule.command])
+        .env("WAYVOICE_TEXT", text)
+        .envs(captures)
+        .stdin(Stdio::null());
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Failed to run command for {:?}: {e}", rule.phrase);
+            notify("Command failed").await;
+            return;
+        }
+    };
+    let phrase = rule.phrase.clone();
+    tokio::spawn(async move {
+        match child.wait().await {
+            Ok(status) if !status.success() => {
+                error!("Command for {phrase:?} failed ({status})");
+            }
+            Ok(_) => {}
+            Err(e) => error!("Command for {phrase:?} failed: {e}"),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grammar(commands: &[(&str, &str)]) -> Grammar {
+        Grammar::new(
+            &commands
+                .iter()
+                .map(|(phrase, command)| (phrase.to_string(), command.to_string()))
+                .collect(),
+        )
+    }
+
+    /// The command `text` would run and its captures.
+    fn lookup(grammar: &Grammar, text: &str) -> Option<(String, Vec<(String, String)>)> {
+        grammar
+            .lookup(text)
+            .map(|(rule, captures)| (rule.command.clone(), captures))
+    }
+
+    fn captures(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn literal_beats_capture() {
+        let g = grammar(&[("open {app}", "launch"), ("open terminal", "foot")]);
+        assert_eq!(lookup(&g, "open terminal"), Some(("foot".into(), vec![])));
+        assert_eq!(
+            lookup(&g, "open firefox"),
+            Some(("launch".into(), captures(&[("app", "firefox")])))
+        );
+    }
+
+    #[test]
+    fn capture_spans_words() {
+        let g = grammar(&[("search for {query} on {site}", "search")]);
+        assert_eq!(
+            lookup(&g, "search for rust async traits on docs rs"),
+            Some((
+                "search".into(),
+                captures(&[("query", "rust async traits"), ("site", "docs rs")])
+            ))
+        );
+    }
+
+    #[test]
+    fn no_match() {
+        let g = grammar(&[("open {app}", "launch"), ("lock screen", "swaylock")]);
+        assert_eq!(lookup(&g, "please open firefox"), None);
+        assert_eq!(lookup(&g, "lock screen now"), None);
+        // A capture needs at least one word
+        assert_eq!(lookup(&g, "open"), None);
+        assert_eq!(lookup(&g, ""), None);
+    }
+
+    #[test]
+    fn ignores_case_and_punctuation() {
+        let g = grammar(&[("Lock Screen!", "swaylock"), ("open {app}", "launch")]);
+        assert_eq!(
+            lookup(&g, "Lock screen."),
+            Some(("swaylock".into(), vec![]))
+        );
+        assert_eq!(
+            lookup(&g, "Open, Firefox!"),
+            Some(("launch".into(), captures(&[("app", "firefox")])))
+        );
+        assert_eq!(
+            lookup(&g, "open don't panic"),
+            Some(("launch".into(), captures(&[("app", "don't panic")])))
+        );
+    }
+
+    #[test]
+    fn ambiguous_phrases_resolve_predictably() {
+        // The first capture takes as few words as it can
+        let g = grammar(&[("{a} and {b}", "both")]);
+        assert_eq!(
+            lookup(&g, "salt and pepper and vinegar"),
+            Some((
+                "both".into(),
+                captures(&[("a", "salt"), ("b", "pepper and vinegar")])
+            ))
+        );
+        // Equally literal phrases are tried in alphabetical order
+        let g = grammar(&[("play {song}", "mpc"), ("{verb} music", "music")]);
+        assert_eq!(
+            lookup(&g, "play music"),
+            Some(("mpc".into(), captures(&[("song", "music")])))
+        );
+        // More literal words win regardless of order
+        let g = grammar(&[("{x} {y}", "any"), ("play {song}", "mpc")]);
+        assert_eq!(
+            lookup(&g, "play jazz"),
+            Some(("mpc".into(), captures(&[("song", "jazz")])))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_phrases() {
+        assert!(check_phrase("open {App}").is_err());
+        assert!(check_phrase("open {app").is_err());
+        assert!(check_phrase("{}").is_err());
+        assert!(check_phrase("  ").is_err());
+        assert!(check_phrase("open {app_2}").is_ok());
+        // Invalid phrases are skipped, the rest still work
+        let g = grammar(&[("open {App}", "bad"), ("lock screen", "swaylock")]);
+        assert_eq!(lookup(&g, "open firefox"), None);
+        assert_eq!(lookup(&g, "lock screen"), Some(("swaylock".into(), vec![])));
+    }
+}
@@ -1,7 +1,16 @@
-use log::debug;
+use crate::commands::{Grammar, check_phrase};
+use crate::error::WayvoiceError;
+use crate::http::build_client;
+use crate::text::Replacements;
+use crate::transcription::language_code;
+use regex::RegexBuilder;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
 use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, error, warn};
 
 #[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
 #[serde(rename_all = "lowercase")]
@@ -9,9 +18,516 @@ pub enum Provider {
     Openai,
     #[default]
     Groq,
+    Deepgram,
+    #[serde(rename = "whisper-cpp")]
+    WhisperCpp,
+    Vosk,
 }
 
-#[derive(Debug, Deserialize, Default)]
+impl Provider {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "groq" => Some(Provider::Groq),
+            "openai" => Some(Provider::Openai),
+            "deepgram" => Some(Provider::Deepgram),
+            "whisper-cpp" => Some(Provider::WhisperCpp),
+            "vosk" => Some(Provider::Vosk),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Provider::Groq => "groq",
+            Provider::Openai => "openai",
+            Provider::Deepgram => "deepgram",
+            Provider::WhisperCpp => "whisper-cpp",
+            Provider::Vosk => "vosk",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadFormat {
+    #[default]
+    Wav,
+    Opus,
+}
+
+/// What to do when the detected language isn't one of `expected_languages`.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LanguageMismatch {
+    #[default]
+    Ignore,
+    /// Notify, but inject the text anyway.
+    Flag,
+    /// Drop the transcript.
+    Reject,
+}
+
+/// What to do with a recording that hits `max_recording_secs`.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitAction {
+    #[default]
+    Transcribe,
+    Discard,
+}
+
+/// Which selection `clipboard_prompt` adds to the prompt.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardPrompt {
+    #[default]
+    Off,
+    Clipboard,
+    Primary,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecorderBackend {
+    #[serde(rename = "pw-record")]
+    PwRecord,
+    Pipewire,
+}
+
+impl Default for RecorderBackend {
+    fn default() -> Self {
+        if cfg!(feature = "pipewire") {
+            RecorderBackend::Pipewire
+        } else {
+            RecorderBackend::PwRecord
+        }
+    }
+}
+
+/// Backend for status notifications.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotifierKind {
+    #[default]
+    NotifySend,
+    /// `org.freedesktop.Notifications` over D-Bus, without spawning.
+    Dbus,
+    /// Print to stderr, e.g. when running under a terminal.
+    Stdout,
+}
+
+/// Screen edge the `wayvoice osd` indicator sits at, horizontally centered.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OsdPosition {
+    #[default]
+    Top,
+    Bottom,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OsdConfig {
+    #[serde(default)]
+    pub position: OsdPosition,
+    /// Distance from the screen edge in pixels.
+    #[serde(default = "default_osd_margin")]
+    pub margin: i32,
+}
+
+impl Default for OsdConfig {
+    fn default() -> Self {
+        Self {
+            position: OsdPosition::default(),
+            margin: default_osd_margin(),
+        }
+    }
+}
+
+/// Earcons for dictating without looking at the screen. Each is a sound
+/// theme id (played with `canberra-gtk-play`) or a file path (`pw-play`);
+/// empty mutes that one.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SoundConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_start_sound")]
+    pub start: String,
+    #[serde(default = "default_stop_sound")]
+    pub stop: String,
+    #[serde(default = "default_error_sound")]
+    pub error: String,
+}
+
+impl Default for SoundConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start: default_start_sound(),
+            stop: default_stop_sound(),
+            error: default_error_sound(),
+        }
+    }
+}
+
+/// Where transcribed text goes.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sink {
+    /// Type or paste into the focused window, per `[inject]`.
+    Inject,
+    /// Copy to the clipboard without pasting.
+    Clipboard,
+    /// Append a line to `output.file`.
+    File,
+    /// Write a line to the named pipe `output.fifo`, if something reads it.
+    Fifo,
+    /// Append a timestamped entry to the markdown file `output.notes`.
+    Notes,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OutputConfig {
+    #[serde(default = "default_sinks")]
+    pub sinks: Vec<Sink>,
+    #[serde(default)]
+    pub file: String,
+    #[serde(default)]
+    pub fifo: String,
+    /// strftime path, e.g. "~/Notes/Daily/%Y-%m-%d.md" for a daily note.
+    #[serde(default)]
+    pub notes: String,
+    /// One entry; strftime codes are filled in, then `{text}`.
+    #[serde(default = "default_notes_entry")]
+    pub notes_entry: String,
+    /// File given with `--output`; replaces every sink for that recording.
+    #[serde(skip)]
+    pub target: Option<PathBuf>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            sinks: default_sinks(),
+            file: String::new(),
+            fifo: String::new(),
+            notes: String::new(),
+            notes_entry: default_notes_entry(),
+            target: None,
+        }
+    }
+}
+
+/// Shell commands run at points in a dictation, e.g. to feed espanso or a
+/// log. Empty runs nothing.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct HooksConfig {
+    #[serde(default)]
+    pub on_start: String,
+    #[serde(default)]
+    pub on_stop: String,
+    /// Gets the final text on stdin and in `$WAYVOICE_TEXT`.
+    #[serde(default)]
+    pub on_text: String,
+    /// Gets the message on stdin and in `$WAYVOICE_ERROR`.
+    #[serde(default)]
+    pub on_error: String,
+}
+
+/// Keep each recording so a bad transcription can be re-run later. 0
+/// disables a limit.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ArchiveConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_archive_days")]
+    pub max_age_days: u64,
+    #[serde(default = "default_archive_mb")]
+    pub max_size_mb: u64,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_age_days: default_archive_days(),
+            max_size_mb: default_archive_mb(),
+        }
+    }
+}
+
+/// Estimated spend on cloud transcription, from the minutes of audio sent.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct CostConfig {
+    /// USD per minute of audio by provider, over the built-in list prices.
+    #[serde(default)]
+    pub prices: HashMap<String, f64>,
+    /// USD per calendar month; 0 means no budget.
+    #[serde(default)]
+    pub monthly_budget: f64,
+    #[serde(default)]
+    pub over_budget: OverBudget,
+}
+
+impl CostConfig {
+    /// USD per minute of audio for `provider`; unknown ones are free.
+    pub fn price(&self, provider: &str) -> f64 {
+        if let Some(&price) = self.prices.get(provider) {
+            return price;
+        }
+        match provider {
+            // whisper-large-v3-turbo, the default model
+            "groq" => 0.04 / 60.0,
+            "openai" => 0.006,
+            "deepgram" => 0.0043,
+            _ => 0.0,
+        }
+    }
+}
+
+/// What happens once `monthly_budget` is spent.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverBudget {
+    /// Notify, but keep transcribing.
+    #[default]
+    Warn,
+    /// Refuse to record with a paid provider until next month.
+    Block,
+}
+
+/// What `wayvoice meeting` records.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MeetingSource {
+    Mic,
+    /// What's playing, e.g. the other side of a call.
+    System,
+    #[default]
+    Both,
+}
+
+impl MeetingSource {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "mic" => Some(MeetingSource::Mic),
+            "system" => Some(MeetingSource::System),
+            "both" => Some(MeetingSource::Both),
+            _ => None,
+        }
+    }
+}
+
+/// Long recordings of calls, transcribed in chunks to a file.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MeetingConfig {
+    #[serde(default)]
+    pub source: MeetingSource,
+    /// Sink whose output is recorded; empty for the default one.
+    #[serde(default)]
+    pub monitor_device: String,
+    /// Label who said what (Deepgram only).
+    #[serde(default)]
+    pub diarize: bool,
+    /// Audio per transcription request.
+    #[serde(default = "default_meeting_chunk_secs")]
+    pub chunk_secs: u64,
+    /// Where transcripts are written; empty for
+    /// `~/.local/share/wayvoice/meetings`.
+    #[serde(default)]
+    pub dir: String,
+}
+
+impl Default for MeetingConfig {
+    fn default() -> Self {
+        Self {
+            source: MeetingSource::default(),
+            monitor_device: String::new(),
+            diarize: false,
+            chunk_secs: default_meeting_chunk_secs(),
+            dir: String::new(),
+        }
+    }
+}
+
+/// Optional LLM clean-up of the transcript before replacements.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct PostprocessConfig {
+    /// System prompt; empty disables post-processing.
+    #[serde(default)]
+    pub prompt: String,
+    /// Chat endpoint to use; defaults to the transcription provider, or
+    /// Groq when transcribing locally.
+    #[serde(default)]
+    pub provider: Option<Provider>,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub base_url: String,
+}
+
+/// Named overrides selected with `--profile` or by `[[profile_rules]]`.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct Profile {
+    #[serde(default)]
+    pub postprocess: Option<PostprocessConfig>,
+    #[serde(default)]
+    pub prompt: Option<String>,
+    /// Merged over the top-level `[replacements]`.
+    #[serde(default)]
+    pub replacements: HashMap<String, String>,
+    #[serde(default)]
+    pub regex_replacements: HashMap<String, String>,
+    #[serde(default)]
+    pub confirm: Option<String>,
+    #[serde(default)]
+    pub command_mode: Option<bool>,
+    #[serde(default)]
+    pub commit_message: Option<bool>,
+}
+
+/// Selects a profile for recordings started while a matching window is
+/// focused. Both patterns are case-insensitive regexes; an empty one
+/// matches anything.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ProfileRule {
+    #[serde(default)]
+    pub app_id: String,
+    #[serde(default)]
+    pub title: String,
+    pub profile: String,
+}
+
+impl ProfileRule {
+    fn matches(&self, app_id: Option<&str>, title: Option<&str>) -> bool {
+        let matches = |pattern: &str, value: Option<&str>| {
+            if pattern.is_empty() {
+                return true;
+            }
+            match RegexBuilder::new(pattern).case_insensitive(true).build() {
+                Ok(re) => value.is_some_and(|value| re.is_match(value)),
+                Err(e) => {
+                    warn!("Invalid profile rule pattern {pattern:?}: {e}");
+                    false
+                }
+            }
+        };
+        matches(&self.app_id, app_id) && matches(&self.title, title)
+    }
+}
+
+/// How transcribed text is typed into the focused window.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InjectMode {
+    #[default]
+    Clipboard,
+    Wtype,
+    Wayland,
+    Ydotool,
+    Portal,
+}
+
+impl InjectMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "clipboard" => Some(InjectMode::Clipboard),
+            "wtype" => Some(InjectMode::Wtype),
+            "wayland" => Some(InjectMode::Wayland),
+            "ydotool" => Some(InjectMode::Ydotool),
+            "portal" => Some(InjectMode::Portal),
+            _ => None,
+        }
+    }
+}
+
+/// Per-application overrides, keyed by Wayland app id (or X11 class).
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct InjectRule {
+    pub mode: Option<InjectMode>,
+    /// Paste shortcut for clipboard mode, e.g. "ctrl+v".
+    pub paste_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct InjectConfig {
+    /// Type the text at all; when off, it only goes to history and
+    /// `subscribe` clients.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub mode: InjectMode,
+    /// Delay before pasting/typing; defaults depend on the mode.
+    #[serde(default)]
+    pub delay_ms: Option<u64>,
+    #[serde(default = "default_key_delay_ms")]
+    pub key_delay_ms: u64,
+    /// How long after pasting to put the previous clipboard back; 0 keeps
+    /// the dictated text on the clipboard.
+    #[serde(default = "default_clipboard_restore_ms")]
+    pub clipboard_restore_ms: u64,
+    /// Hold text back while the screen is locked, for `replay` after
+    /// unlocking, instead of typing it into the lock screen.
+    #[serde(default = "default_true")]
+    pub hold_while_locked: bool,
+    #[serde(default)]
+    pub rules: HashMap<String, InjectRule>,
+}
+
+impl Default for InjectConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            mode: InjectMode::default(),
+            delay_ms: None,
+            key_delay_ms: default_key_delay_ms(),
+            clipboard_restore_ms: default_clipboard_restore_ms(),
+            hold_while_locked: true,
+            rules: HashMap::new(),
+        }
+    }
+}
+
+impl InjectConfig {
+    /// App ids are matched case-insensitively.
+    pub fn rule_for(&self, app_id: &str) -> Option<&InjectRule> {
+        self.rules
+            .iter()
+            .find(|(app, _)| app.eq_ignore_ascii_case(app_id))
+            .map(|(_, rule)| rule)
+    }
+
+    pub fn delay_ms(&self, mode: InjectMode) -> u64 {
+        self.delay_ms.unwrap_or(match mode {
+            InjectMode::Clipboard => 50,
+            _ => 100,
+        })
+    }
+
+    /// `VOICE_*` variables from before `[inject]` existed still win over the file.
+    fn apply_env_overrides(&mut self) {
+        if let Ok(mode) = std::env::var("VOICE_INJECT_MODE") {
+            match InjectMode::from_name(&mode) {
+                Some(mode) => self.mode = mode,
+                None => warn!("Unknown VOICE_INJECT_MODE {mode:?}, using {:?}", self.mode),
+            }
+        }
+        if let Some(ms) = env_u64("VOICE_WTYPE_DELAY_MS") {
+            self.delay_ms = Some(ms);
+        }
+        if let Some(ms) = env_u64("VOICE_WTYPE_KEY_DELAY_MS") {
+            self.key_delay_ms = ms;
+        }
+        if let Some(ms) = env_u64("VOICE_CLIPBOARD_RESTORE_MS") {
+            self.clipboard_restore_ms = ms;
+        }
+    }
+}
+
+fn env_u64(name: &str) -> Option<u64> {
+    std::env::var(name).ok()?.parse().ok()
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
 pub struct Config {
     #[serde(default)]
     pub provider: Provider,
@@ -20,27 +536,518 @@ pub struct Config {
     #[serde(default)]
     pub groq_api_key: String,
     #[serde(default)]
+    pub deepgram_api_key: String,
+    /// Label speakers; set by `transcribe --diarize` and `[meeting] diarize`
+    /// rather than for dictation.
+    #[serde(skip)]
+    pub diarize: bool,
+    /// Shell command printing the key for `$WAYVOICE_PROVIDER`, e.g.
+    /// `pass show $WAYVOICE_PROVIDER/api`, for providers without a key in
+    /// the file. Run once per provider, when it's first used.
+    #[serde(default)]
+    pub api_key_command: String,
+    #[serde(default)]
+    pub streaming: bool,
+    #[serde(default)]
+    pub recorder: RecorderBackend,
+    /// PipeWire node name or serial to record from; empty for the default.
+    #[serde(default)]
+    pub audio_device: String,
+    /// Sources to record from when plugged in, most preferred first;
+    /// `audio_device` is the fallback.
+    #[serde(default)]
+    pub preferred_devices: Vec<String>,
+    #[serde(default)]
+    pub preroll_ms: u64,
+    #[serde(default)]
+    pub noise_suppression: bool,
+    #[serde(default)]
+    pub rnnoise_model: String,
+    /// Bring recordings to `normalize_target_db` RMS before upload.
+    #[serde(default)]
+    pub normalize: bool,
+    #[serde(default = "default_normalize_target_db")]
+    pub normalize_target_db: f64,
+    /// Squash peaks before normalizing, so quiet speech can be raised more.
+    #[serde(default)]
+    pub compress: bool,
+    #[serde(default = "default_max_recording_secs")]
+    pub max_recording_secs: u64,
+    #[serde(default)]
+    pub max_recording_action: LimitAction,
+    /// Pause MPRIS media players while recording.
+    #[serde(default)]
+    pub pause_media: bool,
+    #[serde(default)]
+    pub upload_format: UploadFormat,
+    #[serde(default = "default_opus_bitrate")]
+    pub opus_bitrate: u32,
+    #[serde(default)]
     pub prompt: String,
+    /// Append the focused window's app id and title to `prompt`, so file
+    /// names and project terms on screen are recognized.
+    #[serde(default)]
+    pub window_prompt: bool,
+    /// Append the start of the clipboard or primary selection to `prompt`,
+    /// so names that were just copied are recognized.
+    #[serde(default)]
+    pub clipboard_prompt: ClipboardPrompt,
     #[serde(default)]
     pub language: String,
+    /// Languages a transcript may be in; empty means just `language`.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub expected_languages: Vec<String>,
+    #[serde(default)]
+    pub language_mismatch: LanguageMismatch,
+    /// Discard transcripts the provider is less sure of than this (0-1).
+    #[serde(default)]
+    pub min_confidence: f64,
+    /// Mark words less likely than this (0-1) with `uncertain_word_format`.
+    #[serde(default)]
+    pub min_word_confidence: f64,
+    /// Drop what Whisper makes up for silence ("Thank you.", "you you you").
+    #[serde(default = "default_true")]
+    pub filter_hallucinations: bool,
+    #[serde(default = "default_uncertain_word_format")]
+    pub uncertain_word_format: String,
     #[serde(default)]
     pub model: String,
+    /// Faster model whose text is typed as a draft while `model` finishes,
+    /// then replaced; empty transcribes once.
+    #[serde(default)]
+    pub draft_model: String,
+    /// Also send each recording to this provider and keep whichever text
+    /// comes back first; empty sends it once.
+    #[serde(default)]
+    pub race_provider: String,
+    /// Model for `race_provider`; empty uses its default.
+    #[serde(default)]
+    pub race_model: String,
+    #[serde(default)]
+    pub base_url: String,
+    /// Extra fields for the groq/openai transcription request, e.g.
+    /// `temperature`; these win over the ones wayvoice sets itself.
+    #[serde(default)]
+    pub provider_options: HashMap<String, toml::Value>,
+    /// Extra attempts for rate limits, server errors and timeouts.
+    #[serde(default = "default_request_retries")]
+    pub request_retries: u32,
+    /// Give up on a single API request after this long; 0 waits forever.
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    /// Give up on a whole transcription, retries and post-processing
+    /// included, after this long; 0 waits forever.
+    #[serde(default = "default_transcribe_timeout_secs")]
+    pub transcribe_timeout_secs: u64,
+    /// `http://`, `https://` or `socks5://` proxy for API requests;
+    /// overrides the proxy environment variables.
+    #[serde(default)]
+    pub proxy: String,
+    #[serde(default)]
+    pub whisper_cpp_bin: String,
+    #[serde(default)]
+    pub whisper_cpp_model: String,
+    #[serde(default)]
+    pub whisper_cpp_threads: u32,
+    #[serde(default)]
+    pub vosk_bin: String,
+    #[serde(default)]
+    pub vosk_model: String,
     #[serde(default = "default_true")]
     pub use_default_replacements: bool,
+    #[serde(default, deserialize_with = "replacement_tables")]
+    pub replacements: ReplacementTables,
+    /// Extra dictionaries with `[replacements]` / `[regex_replacements]`
+    /// tables, merged under the ones in this file.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub replacements_file: Vec<String>,
+    /// Regex pattern -> replacement, applied after `replacements`.
     #[serde(default)]
-    pub replacements: HashMap<String, String>,
+    pub regex_replacements: HashMap<String, String>,
+    /// Spoken trigger -> text to insert, expanded after the replacements.
+    /// `{date}`, `{time}` and `{now:<strftime>}` are filled in.
+    #[serde(default)]
+    pub snippets: HashMap<String, String>,
+    /// `replacements`, `regex_replacements` and `snippets`, compiled by
+    /// `load_config`.
+    #[serde(skip)]
+    pub replacement_rules: Replacements,
+    /// `replacement_rules` with each `[replacements.<language>]` merged in,
+    /// by language code.
+    #[serde(skip)]
+    pub language_rules: HashMap<String, Replacements>,
+    /// Built by `load_config` and shared by clones, so connections are reused.
+    #[serde(skip)]
+    pub http: reqwest::Client,
+    #[serde(default)]
+    pub postprocess: PostprocessConfig,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    /// First match picks the profile when no `--profile` is given.
+    #[serde(default)]
+    pub profile_rules: Vec<ProfileRule>,
+    /// Run the `[commands]` entry matching each transcript instead of
+    /// delivering it; usually set in a profile.
+    #[serde(default)]
+    pub command_mode: bool,
+    /// Spoken phrase (with `{name}` captures) -> shell command.
+    #[serde(default)]
+    pub commands: HashMap<String, String>,
+    /// `commands`, compiled by `load_config`.
+    #[serde(skip)]
+    pub command_grammar: Grammar,
+    /// Rewrite transcripts into a conventional commit message (subject of
+    /// at most 72 characters, wrapped body) instead of using `postprocess`.
+    #[serde(default)]
+    pub commit_message: bool,
+    /// Command that shows each transcript for approval before it's
+    /// delivered; empty delivers right away.
+    #[serde(default)]
+    pub confirm: String,
+    /// Keep every transcription in the XDG state dir for `wayvoice history`.
+    #[serde(default = "default_true")]
+    pub history: bool,
+    /// Start the daemon when `toggle` or `start` finds none running.
+    #[serde(default)]
+    pub autostart_daemon: bool,
+    /// Keep recordings that fail for lack of network and send them once
+    /// the provider can be reached again.
+    #[serde(default)]
+    pub offline_queue: bool,
+    /// False keeps transcribed text out of logs and turns off `history`.
+    #[serde(default = "default_true")]
+    pub log_transcripts: bool,
+    /// Copy daemon log lines here, e.g. when stderr ends up in journald.
+    #[serde(default)]
+    pub log_file: String,
+    /// Size at which `log_file` is moved to `<log_file>.1`; 0 never rotates.
+    #[serde(default = "default_log_max_mb")]
+    pub log_max_mb: u64,
+    /// Serve Prometheus metrics on this localhost port; 0 disables it.
+    #[serde(default)]
+    pub metrics_port: u16,
+    /// Show "Recording...", errors and the like.
+    #[serde(default = "default_true")]
+    pub notifications: bool,
+    #[serde(default)]
+    pub notifier: NotifierKind,
+    #[serde(default)]
+    pub inject: InjectConfig,
+    #[serde(default)]
+    pub output: OutputConfig,
+    #[serde(default)]
+    pub osd: OsdConfig,
+    #[serde(default)]
+    pub sounds: SoundConfig,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    #[serde(default)]
+    pub meeting: MeetingConfig,
+    #[serde(default)]
+    pub cost: CostConfig,
+}
+
+impl Config {
+    /// The profile the first matching `profile_rules` entry picks for a
+    /// window.
+    pub fn profile_for(&self, app_id: Option<&str>, title: Option<&str>) -> Option<&str> {
+        self.profile_rules
+            .iter()
+            .find(|rule| rule.matches(app_id, title))
+            .map(|rule| rule.profile.as_str())
+    }
+
+    /// This config with the named profile's overrides applied.
+    pub fn with_profile(&self, name: &str) -> Result<Config, WayvoiceError> {
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| WayvoiceError::Config(format!("Unknown profile: {name}")))?;
+        let mut config = self.clone();
+        if let Some(postprocess) = &profile.postprocess {
+            config.postprocess = postprocess.clone();
+        }
+        if let Some(confirm) = &profile.confirm {
+            config.confirm = confirm.clone();
+        }
+        if let Some(command_mode) = profile.command_mode {
+            config.command_mode = command_mode;
+        }
+        if let Some(commit_message) = profile.commit_message {
+            config.commit_message = commit_message;
+        }
+        if let Some(prompt) = &profile.prompt {
+            config.prompt = prompt.clone();
+        }
+        if !profile.replacements.is_empty() || !profile.regex_replacements.is_empty() {
+            config
+                .replacements
+                .common
+                .extend(profile.replacements.clone());
+            config
+                .regex_replacements
+                .extend(profile.regex_replacements.clone());
+            compile_replacements(&mut config);
+        }
+        Ok(config)
+    }
 }
 
-fn config_path() -> PathBuf {
+impl Config {
+    /// Replacements for a transcript in `detected`, or failing that the
+    /// configured `language`.
+    pub fn replacements_for(&self, detected: Option<&str>) -> &Replacements {
+        let language = detected.unwrap_or(&self.language);
+        if self.language_rules.is_empty() || language.is_empty() {
+            return &self.replacement_rules;
+        }
+        self.language_rules
+            .get(&language_code(language))
+            .unwrap_or(&self.replacement_rules)
+    }
+}
+
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use `path` instead of the default config file (the `--config` flag).
+pub fn set_config_path(path: PathBuf) {
+    let _ = CONFIG_PATH.set(path);
+}
+
+pub(crate) fn config_path() -> PathBuf {
+    if let Some(path) = CONFIG_PATH.get() {
+        return path.clone();
+    }
+    if let Ok(path) = std::env::var("WAYVOICE_CONFIG")
+        && !path.is_empty()
+    {
+        return PathBuf::from(path);
+    }
     dirs::config_dir()
         .unwrap_or_else(|| PathBuf::from("~/.config"))
         .join("wayvoice.toml")
 }
 
+/// Accept either `key = "a"` or `key = ["a", "b"]`.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
+
+/// `[replacements]`: fixes for every language, plus `[replacements.<language>]`
+/// tables used only when transcribing in that language.
+#[derive(Debug, Default, Clone)]
+pub struct ReplacementTables {
+    pub common: HashMap<String, String>,
+    /// By language code.
+    pub languages: HashMap<String, HashMap<String, String>>,
+}
+
+impl ReplacementTables {
+    fn extend(&mut self, other: ReplacementTables) {
+        self.common.extend(other.common);
+        for (language, table) in other.languages {
+            self.languages.entry(language).or_default().extend(table);
+        }
+    }
+}
+
+/// Accept `"from" = "to"` entries and `[replacements.<language>]` tables
+/// side by side; languages can be codes or names.
+fn replacement_tables<'de, D>(deserializer: D) -> Result<ReplacementTables, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(
+        untagged,
+        expecting = "a replacement string or a [replacements.<language>] table"
+    )]
+    enum Entry {
+        Text(String),
+        Language(HashMap<String, String>),
+    }
+    let mut tables = ReplacementTables::default();
+    for (key, entry) in HashMap::<String, Entry>::deserialize(deserializer)? {
+        match entry {
+            Entry::Text(to) => {
+                tables.common.insert(key, to);
+            }
+            Entry::Language(table) => {
+                tables
+                    .languages
+                    .entry(language_code(&key))
+                    .or_default()
+                    .extend(table);
+            }
+        }
+    }
+    Ok(tables)
+}
+
+/// `~/` expands to the home directory; relative paths are resolved against
+/// the config directory.
+pub(crate) fn resolve_path(path: &str) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/")
+        && let Some(home) = dirs::home_dir()
+    {
+        return home.join(rest);
+    }
+    let path = PathBuf::from(path);
+    match config_path().parent() {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path,
+    }
+}
+
+/// Per-user directory for the socket and temp files: `$XDG_RUNTIME_DIR`, or
+/// a private `/tmp/wayvoice-<uid>` when that isn't set. A `/tmp` directory
+/// that isn't ours alone is an error rather than a place for the socket.
+pub(crate) fn runtime_dir() -> Result<PathBuf, WayvoiceError> {
+    if let Ok(dir) = std::env::var("XDG_RUNTIME_DIR")
+        && !dir.is_empty()
+    {
+        return Ok(PathBuf::from(dir));
+    }
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    let dir = std::env::temp_dir().join(format!("wayvoice-{uid}"));
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => {
+            return Err(WayvoiceError::Config(format!(
+                "Failed to create {dir:?}: {e}"
+            )));
+        }
+    }
+    // Someone else could have created it first to read our audio or
+    // plant a socket
+    let metadata = std::fs::symlink_metadata(&dir)
+        .map_err(|e| WayvoiceError::Config(format!("Failed to read {dir:?}: {e}")))?;
+    if !metadata.is_dir() || metadata.uid() != uid {
+        return Err(WayvoiceError::Config(format!(
+            "{dir:?} is not a directory owned by you; remove it or set XDG_RUNTIME_DIR"
+        )));
+    }
+    if metadata.mode() & 0o777 != 0o700 {
+        return Err(WayvoiceError::Config(format!(
+            "{dir:?} is accessible to other users; chmod 700 it or set XDG_RUNTIME_DIR"
+        )));
+    }
+    Ok(dir)
+}
+
+#[derive(Deserialize)]
+struct ReplacementsFile {
+    #[serde(default, deserialize_with = "replacement_tables")]
+    replacements: ReplacementTables,
+    #[serde(default)]
+    regex_replacements: HashMap<String, String>,
+}
+
+fn load_replacements_file(path: &str) -> Result<ReplacementsFile, String> {
+    let path = resolve_path(path);
+    let content =
+        std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {path:?}: {e}"))?;
+    toml::from_str(&content).map_err(|e| format!("Failed to parse {path:?}: {e}"))
+}
+
 fn default_true() -> bool {
     true
 }
 
+fn default_uncertain_word_format() -> String {
+    "{word}[?]".to_string()
+}
+
+fn default_key_delay_ms() -> u64 {
+    5
+}
+
+fn default_clipboard_restore_ms() -> u64 {
+    300
+}
+
+fn default_sinks() -> Vec<Sink> {
+    vec![Sink::Inject]
+}
+
+fn default_notes_entry() -> String {
+    "- %H:%M {text}".to_string()
+}
+
+fn default_start_sound() -> String {
+    "device-added".to_string()
+}
+
+fn default_stop_sound() -> String {
+    "device-removed".to_string()
+}
+
+fn default_error_sound() -> String {
+    "dialog-error".to_string()
+}
+
+fn default_osd_margin() -> i32 {
+    48
+}
+
+fn default_log_max_mb() -> u64 {
+    10
+}
+
+fn default_meeting_chunk_secs() -> u64 {
+    300
+}
+
+fn default_archive_days() -> u64 {
+    30
+}
+
+fn default_archive_mb() -> u64 {
+    500
+}
+
+fn default_request_retries() -> u32 {
+    2
+}
+
+fn default_request_timeout_secs() -> u64 {
+    60
+}
+
+/// Room for every retry of a request that hits `request_timeout_secs`.
+fn default_transcribe_timeout_secs() -> u64 {
+    300
+}
+
+fn default_opus_bitrate() -> u32 {
+    24
+}
+
+/// Ten minutes of 16 kHz WAV stays well under the providers' 25 MB upload cap.
+fn default_max_recording_secs() -> u64 {
+    600
+}
+
+fn default_normalize_target_db() -> f64 {
+    -20.0
+}
+
 fn default_prompt() -> String {
     "I'm working on the NixOS configuration with Home Manager. \
      Let me check the Neovim setup in LazyVim. \
@@ -52,7 +1059,7 @@ fn default_prompt() -> String {
         .to_string()
 }
 
-fn default_replacements() -> HashMap<String, String> {
+pub(crate) fn default_replacements() -> HashMap<String, String> {
     [
         // Wayland compositors
         ("hyperland", "Hyprland"),
@@ -89,39 +1096,396 @@ fn default_replacements() -> HashMap<String, String> {
 }
 
 pub fn load_config() -> Config {
+    try_load_config().unwrap_or_else(|e| {
+        error!("{e}");
+        finish_config(default_config())
+    })
+}
+
+/// Keys printed by `api_key_command`, by command and provider, so a
+/// password manager is asked once per provider rather than per request.
+static COMMAND_KEYS: Mutex<Vec<(String, Provider, String)>> = Mutex::new(Vec::new());
+
+/// How long `api_key_command` may take, long enough to type a passphrase
+/// into a pinentry prompt.
+const API_KEY_COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The key `api_key_command` prints for `provider`, which it gets in
+/// `$WAYVOICE_PROVIDER`. Run the first time a provider without a key in the
+/// file is used, not when the config is loaded.
+pub(crate) async fn command_api_key(config: &Config, provider: Provider) -> Option<String> {
+    let command = &config.api_key_command;
+    if command.is_empty() {
+        return None;
+    }
+    let cached = COMMAND_KEYS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(c, p, _)| c == command && *p == provider)
+        .map(|(_, _, key)| key.clone());
+    if cached.is_some() {
+        return cached;
+    }
+
+    let start = std::time::Instant::now();
+    let output = tokio::process::Command::new("sh")
+        .args(["-c", command])
+        .env("WAYVOICE_PROVIDER", provider.as_str())
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::inherit())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(API_KEY_COMMAND_TIMEOUT, output).await;
+    debug!("api_key_command: {:?}", start.elapsed());
+    let Ok(output) = output else {
+        error!("api_key_command timed out after {API_KEY_COMMAND_TIMEOUT:?}");
+        return None;
+    };
+    match output {
+        Ok(output) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            // pass and friends put the secret on the first line
+            let key = stdout.lines().next().unwrap_or_default().trim().to_string();
+            if key.is_empty() {
+                warn!("api_key_command printed nothing");
+                return None;
+            }
+            COMMAND_KEYS
+                .lock()
+                .unwrap()
+                .push((command.clone(), provider, key.clone()));
+            Some(key)
+        }
+        Ok(output) => {
+            error!("api_key_command failed ({})", output.status);
+            None
+        }
+        Err(e) => {
+            error!("Failed to run api_key_command: {e}");
+            None
+        }
+    }
+}
+
+/// An empty file's config, so `#[serde(default = ...)]` values apply
+/// (unlike `Config::default()`).
+fn default_config() -> Config {
+    toml::from_str("").expect("empty config parses")
+}
+
+/// Like `load_config`, but a config file that doesn't parse is an error
+/// instead of falling back to defaults.
+pub fn try_load_config() -> Result<Config, WayvoiceError> {
     let path = config_path();
-    let mut config = if let Ok(content) = std::fs::read_to_string(&path) {
-        match toml::from_str(&content) {
-            Ok(c) => c,
-            Err(e) => {
-                eprintln!("Failed to parse {path:?}: {e}");
-                Config::default()
+    let config = match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            let (config, unknown) = parse_config(&content)
+                .map_err(|e| WayvoiceError::Config(format!("Failed to parse {path:?}: {e}")))?;
+            for key in unknown {
+                warn!("Unknown key {key:?} in {path:?}");
             }
+            config
         }
-    } else {
-        Config::default()
+        Err(_) => default_config(),
     };
+    Ok(finish_config(config))
+}
+
+/// Parse a config file, also returning the keys serde skipped over so typos
+/// don't silently fall back to defaults.
+fn parse_config(content: &str) -> Result<(Config, Vec<String>), toml::de::Error> {
+    let mut unknown = Vec::new();
+    let config = serde_ignored::deserialize(toml::Deserializer::new(content), |path| {
+        unknown.push(path.to_string().replace(".?", ""))
+    })?;
+    Ok((config, unknown))
+}
+
+/// Request fields that have their own config key, so don't belong in
+/// `provider_options`.
+const SET_BY_KEY: &[&str] = &["file", "model", "language", "prompt"];
+
+/// Check the config file for problems that loading would only warn about
+/// (or silently ignore), returning its path if there are none.
+pub fn validate_config() -> Result<PathBuf, WayvoiceError> {
+    let path = config_path();
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| WayvoiceError::Config(format!("Failed to read {path:?}: {e}")))?;
+    let (config, unknown) = parse_config(&content)
+        .map_err(|e| WayvoiceError::Config(format!("Failed to parse {path:?}: {e}")))?;
 
+    let mut problems: Vec<String> = unknown
+        .into_iter()
+        .map(|key| format!("unknown key {key:?}"))
+        .collect();
+    for pattern in config.regex_replacements.keys() {
+        if let Err(e) = regex::Regex::new(pattern) {
+            problems.push(format!("invalid regex replacement {pattern:?}: {e}"));
+        }
+    }
+    for file in &config.replacements_file {
+        if let Err(e) = load_replacements_file(file) {
+            problems.push(e);
+        }
+    }
+    for rule in &config.profile_rules {
+        for pattern in [&rule.app_id, &rule.title] {
+            if let Err(e) = regex::Regex::new(pattern) {
+                problems.push(format!("invalid profile rule pattern {pattern:?}: {e}"));
+            }
+        }
+        if !config.profiles.contains_key(&rule.profile) {
+            problems.push(format!(
+                "profile rule uses unknown profile {:?}",
+                rule.profile
+            ));
+        }
+    }
+    for (name, profile) in &config.profiles {
+        for pattern in profile.regex_replacements.keys() {
+            if let Err(e) = regex::Regex::new(pattern) {
+                problems.push(format!(
+                    "invalid regex replacement {pattern:?} in profile {name:?}: {e}"
+                ));
+            }
+        }
+    }
+    for (key, value) in [
+        ("min_confidence", config.min_confidence),
+        ("min_word_confidence", config.min_word_confidence),
+    ] {
+        if !(0.0..=1.0).contains(&value) {
+            problems.push(format!("{key} must be between 0 and 1, not {value}"));
+        }
+    }
+    if !(-60.0..=0.0).contains(&config.normalize_target_db) {
+        problems.push(format!(
+            "normalize_target_db must be between -60 and 0 dBFS, not {}",
+            config.normalize_target_db
+        ));
+    }
+    for (key, value) in &config.provider_options {
+        if SET_BY_KEY.contains(&key.as_str()) {
+            problems.push(format!(
+                "provider_options.{key} is set with the top-level {key} key"
+            ));
+        }
+        let scalar = |v: &toml::Value| {
+            !matches!(
+                v,
+                toml::Value::Array(_) | toml::Value::Table(_) | toml::Value::Datetime(_)
+            )
+        };
+        let valid = match value {
+            toml::Value::Array(items) => items.iter().all(scalar),
+            value => scalar(value),
+        };
+        if !valid {
+            problems.push(format!(
+                "provider_options.{key} must be a string, number, boolean or a list of them"
+            ));
+        }
+    }
+    if config.cost.monthly_budget < 0.0 {
+        problems.push("cost.monthly_budget can't be negative".to_string());
+    }
+    for (provider, price) in &config.cost.prices {
+        if Provider::from_name(provider).is_none() {
+            problems.push(format!("cost.prices has unknown provider {provider:?}"));
+        } else if *price < 0.0 {
+            problems.push(format!("cost.prices.{provider} can't be negative"));
+        }
+    }
+    if !config.race_provider.is_empty() && Provider::from_name(&config.race_provider).is_none() {
+        problems.push(format!(
+            "race_provider {:?} is not a provider",
+            config.race_provider
+        ));
+    }
+    if config.meeting.chunk_secs == 0 {
+        problems.push("meeting.chunk_secs must be at least 1".to_string());
+    }
+    if !config.uncertain_word_format.contains("{word}") {
+        problems.push("uncertain_word_format must contain {word}".to_string());
+    }
+    for phrase in config.commands.keys() {
+        if let Err(e) = check_phrase(phrase) {
+            problems.push(format!("invalid command phrase {phrase:?}: {e}"));
+        }
+    }
+    if problems.is_empty() {
+        return Ok(path);
+    }
+    let problems: Vec<String> = problems
+        .into_iter()
+        .map(|problem| format!("{}: {problem}", path.display()))
+        .collect();
+    Err(WayvoiceError::Config(problems.join("\n")))
+}
+
+/// Write a commented default config, refusing to replace an existing one
+/// unless `force` is set.
+pub fn init_config(force: bool) -> Result<PathBuf, WayvoiceError> {
+    let path = config_path();
+    if path.exists() && !force {
+        return Err(WayvoiceError::Config(format!(
+            "{path:?} already exists (use --force to overwrite)"
+        )));
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| WayvoiceError::Config(format!("Failed to create {dir:?}: {e}")))?;
+    }
+    std::fs::write(&path, DEFAULT_CONFIG)
+        .map_err(|e| WayvoiceError::Config(format!("Failed to write {path:?}: {e}")))?;
+    Ok(path)
+}
+
+const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
+
+/// Modification time of the config file, if it exists.
+pub fn config_modified() -> Option<SystemTime> {
+    std::fs::metadata(config_path()).ok()?.modified().ok()
+}
+
+/// `text` for a log line, or a placeholder when `log_transcripts` is off.
+pub(crate) fn loggable(text: &str, log_transcripts: bool) -> &str {
+    if log_transcripts { text } else { "[redacted]" }
+}
+
+/// Build `replacement_rules` and `language_rules` from the merged tables.
+fn compile_replacements(config: &mut Config) {
+    let common = &config.replacements.common;
+    config.replacement_rules =
+        Replacements::new(common, &config.regex_replacements).with_snippets(&config.snippets);
+    config.language_rules = config
+        .replacements
+        .languages
+        .iter()
+        .map(|(language, table)| {
+            let mut literals = common.clone();
+            literals.extend(table.clone());
+            let rules = Replacements::new(&literals, &config.regex_replacements)
+                .with_snippets(&config.snippets);
+            (language.clone(), rules)
+        })
+        .collect();
+}
+
+fn finish_config(mut config: Config) -> Config {
     // Allow env var to override provider
     if let Ok(provider) = std::env::var("VOICE_PROVIDER") {
-        config.provider = match provider.to_lowercase().as_str() {
-            "groq" => Provider::Groq,
-            "openai" => Provider::Openai,
-            _ => config.provider,
-        };
+        config.provider = Provider::from_name(&provider).unwrap_or(config.provider);
     }
 
+    config.inject.apply_env_overrides();
+
     if config.prompt.is_empty() {
         config.prompt = default_prompt();
     }
+    if !config.log_transcripts {
+        config.history = false;
+    }
 
-    // Merge user replacements on top of defaults unless disabled
+    // Merge user replacements on top of replacement files, and those on
+    // top of the defaults unless disabled
+    let mut replacements = ReplacementTables::default();
     if config.use_default_replacements {
-        let mut replacements = default_replacements();
-        replacements.extend(std::mem::take(&mut config.replacements));
-        config.replacements = replacements;
+        replacements.common = default_replacements();
     }
+    let mut regex_replacements = HashMap::new();
+    for path in &config.replacements_file {
+        match load_replacements_file(path) {
+            Ok(file) => {
+                replacements.extend(file.replacements);
+                regex_replacements.extend(file.regex_replacements);
+            }
+            Err(e) => error!("{e}"),
+        }
+    }
+    replacements.extend(std::mem::take(&mut config.replacements));
+    regex_replacements.extend(std::mem::take(&mut config.regex_replacements));
+    config.replacements = replacements;
+    config.regex_replacements = regex_replacements;
+    compile_replacements(&mut config);
+    config.command_grammar = Grammar::new(&config.commands);
+    config.http = build_client(&config);
 
     debug!("provider={:?}", config.provider);
     config
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::apply_replacements;
+
+    fn load(content: &str) -> Config {
+        finish_config(parse_config(content).unwrap().0)
+    }
+
+    const TABLES: &str = r#"
+use_default_replacements = false
+
+[replacements]
+"jus" = "just"
+"kuh" = "cow"
+
+[replacements.german]
+"kuh" = "Kuh"
+"strasse" = "Straße"
+
+[profiles.mail]
+replacements = { "cheers" = "Best regards" }
+"#;
+
+    #[test]
+    fn language_table_merges_over_common() {
+        let config = load(TABLES);
+        let german = config.replacements_for(Some("de"));
+        assert_eq!(
+            apply_replacements("so jus eine kuh in der strasse", german),
+            "so just eine Kuh in der Straße"
+        );
+        // Detected names work like codes
+        let german = config.replacements_for(Some("German"));
+        assert_eq!(apply_replacements("eine kuh", german), "eine Kuh");
+    }
+
+    #[test]
+    fn other_languages_get_common_replacements() {
+        let config = load(TABLES);
+        let english = config.replacements_for(Some("en"));
+        assert_eq!(
+            apply_replacements("so jus a kuh on the strasse", english),
+            "so just a cow on the strasse"
+        );
+        let undetected = config.replacements_for(None);
+        assert_eq!(apply_replacements("a kuh", undetected), "a cow");
+    }
+
+    #[test]
+    fn configured_language_applies_when_none_is_detected() {
+        let config = load(&format!("language = \"de\"\n{TABLES}"));
+        let undetected = config.replacements_for(None);
+        assert_eq!(apply_replacements("eine kuh", undetected), "eine Kuh");
+        let english = config.replacements_for(Some("en"));
+        assert_eq!(apply_replacements("a kuh", english), "a cow");
+    }
+
+    #[test]
+    fn profile_replacements_reach_language_tables() {
+        let config = load(TABLES).with_profile("mail").unwrap();
+        let german = config.replacements_for(Some("de"));
+        assert_eq!(
+            apply_replacements("eine kuh, cheers", german),
+            "eine Kuh, Best regards"
+        );
+        let undetected = config.replacements_for(None);
+        assert_eq!(
+            apply_replacements("a kuh, cheers", undetected),
+            "a cow, Best regards"
+        );
+    }
+}
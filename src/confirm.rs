@@ -0,0 +1,51 @@
+use crate::notify::notify;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, error};
+
+/// Show `text` through the `confirm` command before it's delivered.
+///
+/// The command gets the text on stdin and in `$WAYVOICE_TEXT`, like a hook,
+/// and prints what to deliver: dmenu-style pickers echo the line back, and
+/// prompts with an input field can return an edited version. A failing
+/// command or empty output rejects the text.
+pub async fn review(command: &str, text: &str) -> Option<String> {
+    debug!("confirm: {command}");
+    let child = Command::new("sh")
+        .args(["-c", command])
+        .env("WAYVOICE_TEXT", text)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        // Don't leave the prompt on screen when the daemon shuts down
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Failed to run confirm command: {e}");
+            notify("Confirm command failed").await;
+            return None;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // A prompt that only reads $WAYVOICE_TEXT closes stdin early
+        let _ = stdin.write_all(text.as_bytes()).await;
+    }
+
+    let output = match child.wait_with_output().await {
+        Ok(output) => output,
+        Err(e) => {
+            error!("confirm command failed: {e}");
+            return None;
+        }
+    };
+    if !output.status.success() {
+        debug!("confirm: rejected ({})", output.status);
+        return None;
+    }
+    let approved = String::from_utf8_lossy(&output.stdout)
+        .trim_end_matches('\n')
+        .to_string();
+    (!approved.trim().is_empty()).then_some(approved)
+}
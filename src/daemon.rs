@@ -1,11 +1,37 @@
-use crate::config::{Config, load_config};
-use crate::inject::{inject_text, notify};
-use crate::text::apply_replacements;
-use crate::transcription::transcribe_audio;
-use log::debug;
-use std::path::PathBuf;
-use std::process::Stdio;
-use tokio::process::{Child, Command};
+use crate::archive;
+use crate::commands;
+use crate::config::{
+    ClipboardPrompt, Config, LimitAction, OverBudget, Sink, config_modified, load_config, loggable,
+    try_load_config,
+};
+use crate::confirm;
+use crate::error::WayvoiceError;
+use crate::focus::{Window, focused_window};
+use crate::history::{self, Entry};
+use crate::hooks::{self, Hook};
+use crate::inject::{self, inject_text};
+use crate::ipc::Args;
+use crate::logging;
+use crate::media;
+use crate::metrics::Metrics;
+use crate::notify::{self, notify};
+use crate::offline;
+use crate::output;
+use crate::postprocess::apply_postprocess;
+use crate::recorder::{self, Recorder};
+use crate::sound::{self, Cue};
+use crate::stats;
+use crate::streaming::StreamingSession;
+use crate::text::{apply_replacements, prompt_context};
+use crate::transcription::{Transcript, transcribe_audio};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{Notify, broadcast, mpsc};
+use tracing::{Instrument, debug, debug_span, error, warn};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum State {
@@ -24,144 +50,1247 @@ impl State {
     }
 }
 
+/// Pushed to `subscribe` clients as things happen.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event {
+    Idle,
+    Recording,
+    Transcribing,
+    /// Text that was just transcribed, and injected unless `inject.enabled`
+    /// is off.
+    Text {
+        text: String,
+        /// Language the provider detected, if it reports one.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        language: Option<String>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl From<State> for Event {
+    fn from(state: State) -> Self {
+        match state {
+            State::Idle => Event::Idle,
+            State::Recording => Event::Recording,
+            State::Transcribing => Event::Transcribing,
+        }
+    }
+}
+
+/// Snapshot of the daemon reported by the `status` command.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    pub state: String,
+    /// Seconds since recording started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elapsed_secs: Option<u64>,
+    /// Current input level; absent when live streaming.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level_db: Option<f32>,
+    /// Recordings waiting for or in transcription.
+    #[serde(default)]
+    pub queued: usize,
+    /// Provider new recordings are sent to.
+    #[serde(default)]
+    pub provider: String,
+    /// Language new recordings are transcribed in; absent when detected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Profile of the current recording.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Most recent failure, until a transcription succeeds again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// When text was last transcribed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_text_at: Option<Timestamp>,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.state)?;
+        if let Some(secs) = self.elapsed_secs {
+            write!(f, " {:02}:{:02}", secs / 60, secs % 60)?;
+        }
+        if let Some(level) = self.level_db {
+            write!(f, " level={level:.0}dB")?;
+        }
+        if let Some(language) = &self.language {
+            write!(f, " lang={language}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a recording command didn't do what was asked. Each kind has its own
+/// error code, and the CLI its own exit code, so scripts can tell them apart.
+#[derive(Debug)]
+pub enum Refusal {
+    /// `start` while already recording.
+    Busy,
+    /// `stop` or `cancel` with nothing to stop.
+    Idle,
+    /// The microphone or streaming session couldn't be opened.
+    RecordingFailed(String),
+    /// The recording came out empty.
+    NoAudio,
+    /// The transcription a client waited for failed.
+    Failed(String),
+    /// `monthly_budget` is spent and `over_budget` is "block".
+    OverBudget,
+}
+
+impl Refusal {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Refusal::Busy => "busy",
+            Refusal::Idle => "not_recording",
+            Refusal::RecordingFailed(_) => "recording_failed",
+            Refusal::NoAudio => "no_audio",
+            Refusal::Failed(_) => "transcription_failed",
+            Refusal::OverBudget => "over_budget",
+        }
+    }
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refusal::Busy => write!(f, "Already recording"),
+            Refusal::Idle => write!(f, "Not recording"),
+            Refusal::RecordingFailed(message) | Refusal::Failed(message) => write!(f, "{message}"),
+            Refusal::NoAudio => write!(f, "No audio recorded"),
+            Refusal::OverBudget => write!(f, "Monthly transcription budget spent"),
+        }
+    }
+}
+
+/// Finished recordings waiting for the transcription worker, which handles
+/// them one at a time so text is injected in the order it was spoken.
+enum Job {
+    Clip {
+        audio_data: Vec<u8>,
+        config: Arc<Config>,
+        generation: u64,
+        /// False for retries, whose audio is already archived.
+        archive: bool,
+    },
+    Stream {
+        stream: StreamingSession,
+        config: Arc<Config>,
+        generation: u64,
+    },
+}
+
+impl Job {
+    fn generation(&self) -> u64 {
+        match self {
+            Job::Clip { generation, .. } | Job::Stream { generation, .. } => *generation,
+        }
+    }
+
+    fn config(&self) -> &Arc<Config> {
+        match self {
+            Job::Clip { config, .. } | Job::Stream { config, .. } => config,
+        }
+    }
+}
+
+/// Bookkeeping shared between the daemon and its transcription worker.
+struct Queue {
+    pending: AtomicUsize,
+    /// Jobs handed to the worker so far, which numbers them.
+    enqueued: AtomicU64,
+    /// Each job's number and text (or error) as the worker finishes it.
+    finished: broadcast::Sender<(u64, Option<Result<String, String>>)>,
+    /// Text or error of the job in progress.
+    outcome: Mutex<Option<Result<String, String>>>,
+    /// Bumped by `cancel` to drop queued and in-flight jobs.
+    generation: AtomicU64,
+    /// Woken alongside a generation bump so an in-flight request is
+    /// abandoned right away instead of when it returns.
+    cancelled: Notify,
+    events: broadcast::Sender<Event>,
+    /// Last state sent to subscribers, so each change is reported once.
+    published: Mutex<State>,
+    /// Outcome of the latest transcription, for `status`.
+    last_error: Mutex<Option<String>>,
+    last_text_at: Mutex<Option<Timestamp>>,
+    metrics: Metrics,
+}
+
+impl Queue {
+    fn new() -> Self {
+        Self {
+            pending: AtomicUsize::new(0),
+            enqueued: AtomicU64::new(0),
+            finished: broadcast::channel(16).0,
+            outcome: Mutex::new(None),
+            generation: AtomicU64::new(0),
+            cancelled: Notify::new(),
+            events: broadcast::channel(64).0,
+            published: Mutex::new(State::Idle),
+            last_error: Mutex::new(None),
+            last_text_at: Mutex::new(None),
+            metrics: Metrics::default(),
+        }
+    }
+
+    fn publish(&self, event: Event) {
+        match &event {
+            Event::Text { text, .. } => {
+                *self.last_text_at.lock().unwrap() = Some(Timestamp::now());
+                *self.last_error.lock().unwrap() = None;
+                *self.outcome.lock().unwrap() = Some(Ok(text.clone()));
+            }
+            Event::Error { message } => {
+                *self.last_error.lock().unwrap() = Some(message.clone());
+                *self.outcome.lock().unwrap() = Some(Err(message.clone()));
+            }
+            _ => {}
+        }
+        // No subscribers is not an error
+        let _ = self.events.send(event);
+    }
+
+    fn publish_state(&self, state: State) {
+        let mut published = self.published.lock().unwrap();
+        if *published != state {
+            *published = state;
+            self.publish(state.into());
+        }
+    }
+
+    /// Called by the worker as each job leaves the queue. Only the
+    /// transcribing -> idle edge happens here; the daemon reports the rest.
+    fn job_done(&self) {
+        let was = self.pending.fetch_sub(1, Ordering::AcqRel);
+        let transcribing = *self.published.lock().unwrap() == State::Transcribing;
+        if was == 1 && transcribing {
+            self.publish_state(State::Idle);
+        }
+    }
+}
+
 pub struct Daemon {
-    state: State,
-    config: Config,
-    recorder: Option<Child>,
-    audio_file: PathBuf,
+    config: Arc<Config>,
+    /// When the loaded config file was last modified, to notice edits.
+    config_modified: Option<SystemTime>,
+    recorder: Recorder,
+    stream: Option<StreamingSession>,
+    recording_started: Option<Instant>,
+    /// Overrides sent by the client that started the current recording.
+    overrides: Args,
+    jobs: mpsc::UnboundedSender<Job>,
+    queue: Arc<Queue>,
+    /// Most recently injected text, for `replay`.
+    last_text: Arc<Mutex<Option<String>>>,
+    /// Audio of the last recording and the config it was made with, so a
+    /// failed request can be retried without speaking again.
+    last_clip: Option<(Vec<u8>, Arc<Config>)>,
+    /// Focused window at the start of the recording, for `window_prompt`.
+    window: Option<Window>,
+    /// Selection at the start of the recording, for `clipboard_prompt`.
+    selection: Option<String>,
+    /// When `tick` last tried to send the offline queue.
+    queue_checked: Instant,
+    /// Players `pause_media` paused for the current recording.
+    paused_players: Vec<String>,
+    /// Source the recorder captures, picked from `preferred_devices`.
+    device: String,
+    /// When `tick` last looked for a more preferred device.
+    device_checked: Instant,
+    /// Already told that `monthly_budget` is spent, so it's said once.
+    budget_warned: bool,
+    /// Set with `lang`, in place of the config's `language` until the
+    /// daemon restarts; empty to detect it.
+    language: Option<String>,
 }
 
 impl Daemon {
+    /// Loads the config and spawns the worker, so this is deliberately not
+    /// `Default`.
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
-        let audio_file = std::env::temp_dir().join("voice-recording.wav");
+        let config = load_config();
+        logging::configure(&config);
+        notify::configure(&config);
+        stats::start_session();
+        let queue = Arc::new(Queue::new());
+        let (jobs, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_worker(rx, queue.clone()));
+        let last_text = Arc::new(Mutex::new(None));
+        tokio::spawn(track_last_text(queue.events.subscribe(), last_text.clone()));
         Self {
-            state: State::Idle,
-            config: load_config(),
-            recorder: None,
-            audio_file,
+            recorder: Recorder::new(&config),
+            device: config.audio_device.clone(),
+            config: Arc::new(config),
+            config_modified: config_modified(),
+            stream: None,
+            recording_started: None,
+            overrides: Args::default(),
+            window: None,
+            selection: None,
+            queue_checked: Instant::now(),
+            paused_players: Vec::new(),
+            device_checked: Instant::now(),
+            budget_warned: false,
+            language: None,
+            jobs,
+            queue,
+            last_text,
+            last_clip: None,
         }
     }
 
-    pub fn status(&self) -> &'static str {
-        self.state.as_str()
+    fn state(&self) -> State {
+        if self.recording_started.is_some() {
+            State::Recording
+        } else if self.queue.pending.load(Ordering::Acquire) > 0 {
+            State::Transcribing
+        } else {
+            State::Idle
+        }
+    }
+
+    pub fn subscribe(&self) -> (Event, broadcast::Receiver<Event>) {
+        (self.state().into(), self.queue.events.subscribe())
+    }
+
+    fn publish_state(&self) {
+        self.queue.publish_state(self.state());
+    }
+
+    pub fn status(&self) -> Status {
+        let elapsed_secs = self.recording_started.map(|t| t.elapsed().as_secs());
+        // Live streaming captures on its own, there's no level to report
+        let level_db =
+            (elapsed_secs.is_some() && self.stream.is_none()).then(|| self.recorder.level_db());
+        Status {
+            state: self.state().as_str().to_string(),
+            elapsed_secs,
+            level_db,
+            queued: self.queue.pending.load(Ordering::Acquire),
+            provider: self.config.provider.as_str().to_string(),
+            language: Some(self.language().to_string()).filter(|l| !l.is_empty()),
+            profile: elapsed_secs
+                .is_some()
+                .then(|| self.profile().map(str::to_string))
+                .flatten(),
+            last_error: self.queue.last_error.lock().unwrap().clone(),
+            last_text_at: *self.queue.last_text_at.lock().unwrap(),
+        }
+    }
+
+    /// Counters since the daemon started, in Prometheus text format.
+    pub fn metrics(&self) -> String {
+        self.queue.metrics.render()
+    }
+
+    /// Port for the metrics endpoint; read once at startup.
+    pub fn metrics_port(&self) -> u16 {
+        self.config.metrics_port
+    }
+
+    pub async fn toggle(&mut self, overrides: Args) -> Result<&'static str, Refusal> {
+        match self.state() {
+            State::Recording => self.stop().await,
+            State::Idle | State::Transcribing => self.start(overrides).await,
+        }
     }
 
-    pub async fn toggle(&mut self) -> &'static str {
-        match self.state {
-            State::Idle => {
-                self.start_recording().await;
-                "recording"
+    /// Begin recording; refused if already recording (e.g. key repeat).
+    /// Earlier clips keep transcribing in the background. `overrides` apply
+    /// to this recording only.
+    pub async fn start(&mut self, overrides: Args) -> Result<&'static str, Refusal> {
+        if self.state() == State::Recording {
+            return Err(Refusal::Busy);
+        }
+        self.overrides = overrides;
+        let started = self.start_recording().await;
+        self.publish_state();
+        started.map(|()| self.state().as_str())
+    }
+
+    /// Queue the current recording for transcription; refused unless
+    /// recording.
+    pub async fn stop(&mut self) -> Result<&'static str, Refusal> {
+        if self.state() != State::Recording {
+            return Err(Refusal::Idle);
+        }
+        let stopped = self.stop_and_transcribe().await;
+        self.publish_state();
+        stopped.map(|()| self.state().as_str())
+    }
+
+    /// Wait for the most recently queued recording to be transcribed, for
+    /// clients that want the text (or the error) rather than the state.
+    pub fn transcribed(&self) -> impl Future<Output = Result<String, Refusal>> + use<> {
+        let job = self.queue.enqueued.load(Ordering::Acquire);
+        let mut finished = self.queue.finished.subscribe();
+        async move {
+            loop {
+                match finished.recv().await {
+                    Ok((done, outcome)) if done >= job => {
+                        return match outcome {
+                            // Missed it, or it came out empty
+                            _ if done > job => Ok(String::new()),
+                            Some(Ok(text)) => Ok(text),
+                            Some(Err(message)) => Err(Refusal::Failed(message)),
+                            None => Ok(String::new()),
+                        };
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return Err(Refusal::Failed("Transcription worker is gone".to_string()));
+                    }
+                }
             }
-            State::Recording => {
-                self.stop_and_transcribe().await;
-                "transcribing"
+        }
+    }
+
+    /// Transcribe new recordings in `language` ("auto" to detect it), or
+    /// with `None` in the config's `language` again.
+    pub fn set_language(&mut self, language: Option<String>) -> String {
+        self.language = language.map(|l| if l == "auto" { String::new() } else { l });
+        debug!("language {:?}", self.language());
+        match self.language() {
+            "" => "auto".to_string(),
+            language => language.to_string(),
+        }
+    }
+
+    /// Language new recordings are transcribed in, before `--language`.
+    fn language(&self) -> &str {
+        self.language.as_deref().unwrap_or(&self.config.language)
+    }
+
+    /// Re-read `wayvoice.toml`. Recordings already queued keep the config
+    /// they were made with; a file that doesn't parse leaves the current
+    /// config in place.
+    pub fn reload(&mut self) -> Result<&'static str, WayvoiceError> {
+        self.config_modified = config_modified();
+        let config = try_load_config()?;
+        if config.recorder != self.config.recorder
+            || config.preroll_ms != self.config.preroll_ms
+            || config.audio_device != self.config.audio_device
+        {
+            warn!("recorder, preroll_ms and audio_device changes take effect after a restart");
+        }
+        logging::configure(&config);
+        notify::configure(&config);
+        self.config = Arc::new(config);
+        debug!("config reloaded");
+        Ok("reloaded")
+    }
+
+    /// Inject the most recent transcription again, e.g. after it landed in
+    /// the wrong window, or everything held back while the screen was
+    /// locked.
+    pub fn replay(&self) -> Result<&'static str, String> {
+        let text = inject::take_held().or_else(|| self.last_text.lock().unwrap().clone());
+        let Some(text) = text else {
+            return Err("Nothing to replay yet".to_string());
+        };
+        // Inject in the background so the socket answers right away
+        let inject = self.config.inject.clone();
+        tokio::spawn(async move { inject_text(&text, &inject).await });
+        Ok("replayed")
+    }
+
+    /// Erase the most recently injected text, e.g. after a mis-transcription.
+    pub fn undo(&self) -> Result<&'static str, String> {
+        let Some(injection) = inject::take_last_injection() else {
+            return Err("Nothing to undo".to_string());
+        };
+        let inject = self.config.inject.clone();
+        tokio::spawn(async move { inject::erase(injection, &inject).await });
+        Ok("undone")
+    }
+
+    /// Send the last recording to the provider again.
+    pub async fn retry(&mut self) -> Result<&'static str, String> {
+        let Some((audio_data, config)) = self.last_clip.clone() else {
+            return Err("No recording to retry".to_string());
+        };
+        notify("Retrying...").await;
+        let generation = self.queue.generation.load(Ordering::Acquire);
+        self.enqueue(Job::Clip {
+            audio_data,
+            config,
+            generation,
+            archive: false,
+        });
+        self.publish_state();
+        Ok(self.state().as_str())
+    }
+
+    /// Send recordings queued while offline now, instead of waiting for the
+    /// next automatic attempt.
+    pub fn flush(&self) -> Result<&'static str, String> {
+        if offline::queued().is_empty() {
+            return Err("Nothing queued".to_string());
+        }
+        tokio::spawn(flush_offline(self.config.clone(), self.queue.clone(), true));
+        Ok("flushing")
+    }
+
+    /// Periodic housekeeping, driven by the server once a second.
+    pub async fn tick(&mut self) {
+        if self.config.offline_queue && self.queue_checked.elapsed() >= OFFLINE_RETRY {
+            self.queue_checked = Instant::now();
+            if !offline::queued().is_empty() {
+                tokio::spawn(flush_offline(
+                    self.config.clone(),
+                    self.queue.clone(),
+                    false,
+                ));
             }
-            State::Transcribing => "busy",
         }
+
+        if config_modified() != self.config_modified {
+            match self.reload() {
+                Ok(_) => notify("Config reloaded").await,
+                Err(e) => {
+                    error!("{e}");
+                    notify("Config has errors, keeping the previous one").await;
+                }
+            }
+        }
+
+        let limit = self.config.max_recording_secs;
+        let Some(started) = self.recording_started else {
+            // A pre-roll recorder is already capturing, so follow hotplugs
+            if self.config.preroll_ms > 0 && self.device_checked.elapsed() >= DEVICE_CHECK {
+                self.select_device().await;
+            }
+            return;
+        };
+        if limit == 0 || started.elapsed() < Duration::from_secs(limit) {
+            return;
+        }
+
+        warn!("Recording hit the {limit}s limit");
+        match self.config.max_recording_action {
+            LimitAction::Transcribe => {
+                notify(&format!("Recording limit reached ({limit}s), transcribing")).await;
+                // An empty recording has been reported already
+                let _ = self.stop_and_transcribe().await;
+            }
+            LimitAction::Discard => {
+                self.discard().await;
+                notify(&format!("Recording limit reached ({limit}s), discarded")).await;
+            }
+        }
+        self.publish_state();
     }
 
-    pub async fn cancel(&mut self) -> &'static str {
-        if let Some(mut child) = self.recorder.take() {
-            let _ = child.kill().await;
+    /// Discard the current recording, or if not recording, drop every
+    /// queued transcription instead. Refused when there's neither.
+    pub async fn cancel(&mut self) -> Result<&'static str, Refusal> {
+        match self.state() {
+            State::Recording => self.discard().await,
+            State::Idle => return Err(Refusal::Idle),
+            State::Transcribing => {
+                self.queue.generation.fetch_add(1, Ordering::AcqRel);
+                self.queue.cancelled.notify_waiters();
+            }
         }
-        self.state = State::Idle;
+        self.publish_state();
         notify("Cancelled").await;
-        "cancelled"
-    }
-
-    async fn start_recording(&mut self) {
-        let _ = tokio::fs::remove_file(&self.audio_file).await;
-
-        let child = Command::new("pw-record")
-            .args([
-                "--format",
-                "s16",
-                "--rate",
-                "16000",
-                "--channels",
-                "1",
-                self.audio_file.to_str().unwrap(),
-            ])
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn();
-
-        match child {
-            Ok(child) => {
-                self.recorder = Some(child);
-                self.state = State::Recording;
+        Ok("cancelled")
+    }
+
+    /// Drop any recording in progress and abandon queued transcriptions
+    /// before the process exits.
+    pub async fn shutdown(&mut self) {
+        if self.state() == State::Recording {
+            self.discard().await;
+        }
+        self.queue.generation.fetch_add(1, Ordering::AcqRel);
+        self.queue.cancelled.notify_waiters();
+    }
+
+    async fn discard(&mut self) {
+        self.recorder.cancel().await;
+        if let Some(stream) = self.stream.take() {
+            stream.abort();
+        }
+        self.recording_started = None;
+        media::resume(std::mem::take(&mut self.paused_players));
+        self.overrides = Args::default();
+        self.window = None;
+        self.selection = None;
+    }
+
+    /// Switch the recorder to the first of `preferred_devices` that is
+    /// plugged in. Only called between recordings.
+    async fn select_device(&mut self) {
+        self.device_checked = Instant::now();
+        if self.config.preferred_devices.is_empty() {
+            return;
+        }
+        let device = recorder::pick_device(&self.config).await;
+        if device != self.device {
+            debug!("recording from {device:?} instead of {:?}", self.device);
+            let previous = std::mem::replace(
+                &mut self.recorder,
+                Recorder::for_device(&self.config, &device),
+            );
+            previous.close().await;
+            self.device = device;
+        }
+    }
+
+    async fn start_recording(&mut self) -> Result<(), Refusal> {
+        self.check_budget().await?;
+        self.select_device().await;
+        let started = self.open_recording().await;
+        if started.is_ok() {
+            // Checked alongside, so a slow wpctl doesn't delay recording
+            let device = self.device.clone();
+            let sounds = self.config.sounds.clone();
+            tokio::spawn(async move {
+                if recorder::is_muted(&device).await {
+                    warn!("Recording from a muted source");
+                    sound::play(&sounds, Cue::Error);
+                    notify("Mic is muted").await;
+                }
+            });
+        }
+        if started.is_ok() && self.config.pause_media {
+            self.paused_players = media::pause().await;
+        }
+        started
+    }
+
+    /// Warn once `monthly_budget` is spent, or with `over_budget = "block"`
+    /// refuse to record with a paid provider.
+    async fn check_budget(&mut self) -> Result<(), Refusal> {
+        let cost = &self.config.cost;
+        let provider = if self.config.streaming {
+            "deepgram"
+        } else {
+            self.config.provider.as_str()
+        };
+        if cost.monthly_budget <= 0.0 || cost.price(provider) <= 0.0 {
+            return Ok(());
+        }
+        let spent = stats::month_cost(cost);
+        let over = spent >= cost.monthly_budget;
+        let warned = std::mem::replace(&mut self.budget_warned, over);
+        if !over {
+            return Ok(());
+        }
+        let message = format!(
+            "${spent:.2} of the ${:.2} monthly budget spent",
+            cost.monthly_budget
+        );
+        if cost.over_budget == OverBudget::Block {
+            warn!("{message}, not recording");
+            sound::play(&self.config.sounds, Cue::Error);
+            notify(&format!("{message}, not recording")).await;
+            return Err(Refusal::OverBudget);
+        }
+        if !warned {
+            warn!("{message}");
+            notify(&message).await;
+        }
+        Ok(())
+    }
+
+    async fn open_recording(&mut self) -> Result<(), Refusal> {
+        if self.config.streaming {
+            return self.start_streaming().await;
+        }
+
+        match self.recorder.start().await {
+            Ok(()) => {
+                self.recording_started = Some(Instant::now());
+                sound::play(&self.config.sounds, Cue::Start);
+                hooks::run(&self.config.hooks, Hook::Start, "");
                 notify("Recording...").await;
+                if self.needs_window() {
+                    self.window = focused_window().await;
+                }
+                if self.config.clipboard_prompt != ClipboardPrompt::Off {
+                    let primary = self.config.clipboard_prompt == ClipboardPrompt::Primary;
+                    self.selection = inject::read_selection(primary).await;
+                }
+                Ok(())
             }
             Err(e) => {
-                eprintln!("Failed to start pw-record: {e}");
+                error!("{e}");
+                sound::play(&self.config.sounds, Cue::Error);
+                hooks::run(&self.config.hooks, Hook::Error, &e.to_string());
                 notify("Failed to start recording").await;
+                Err(Refusal::RecordingFailed(e.to_string()))
             }
         }
     }
 
-    async fn stop_and_transcribe(&mut self) {
-        let total_start = std::time::Instant::now();
+    async fn start_streaming(&mut self) -> Result<(), Refusal> {
+        // The session is configured up front, so look at the window first
+        if self.needs_window() {
+            self.window = focused_window().await;
+        }
+        let config = self.recording_config();
+        self.window = None;
+        self.overrides = Args::default();
+        match StreamingSession::start(&config, &self.device, self.queue.events.clone()).await {
+            Ok(stream) => {
+                self.stream = Some(stream);
+                self.recording_started = Some(Instant::now());
+                sound::play(&config.sounds, Cue::Start);
+                hooks::run(&config.hooks, Hook::Start, "");
+                notify("Recording (live)...").await;
+                Ok(())
+            }
+            Err(e) => {
+                error!("Failed to start streaming: {e}");
+                sound::play(&config.sounds, Cue::Error);
+                hooks::run(&config.hooks, Hook::Error, &e.to_string());
+                notify(&format!("Error: {e}")).await;
+                Err(Refusal::RecordingFailed(e.to_string()))
+            }
+        }
+    }
 
-        let stop_start = std::time::Instant::now();
-        if let Some(mut child) = self.recorder.take() {
-            let _ = child.kill().await;
-            let _ = child.wait().await;
+    async fn stop_and_transcribe(&mut self) -> Result<(), Refusal> {
+        self.recording_started = None;
+        let generation = self.queue.generation.load(Ordering::Acquire);
+
+        if let Some(stream) = self.stream.take() {
+            media::resume(std::mem::take(&mut self.paused_players));
+            sound::play(&self.config.sounds, Cue::Stop);
+            hooks::run(&self.config.hooks, Hook::Stop, "");
+            self.enqueue(Job::Stream {
+                stream,
+                config: self.config.clone(),
+                generation,
+            });
+            return Ok(());
         }
-        debug!("stop_recording: {:?}", stop_start.elapsed());
+
+        let audio_data = self.recorder.stop().instrument(debug_span!("record")).await;
+        media::resume(std::mem::take(&mut self.paused_players));
 
         // Check if we got any audio
-        match tokio::fs::metadata(&self.audio_file).await {
-            Ok(meta) if meta.len() < 1000 => {
-                eprintln!("No audio recorded");
-                notify("No audio recorded").await;
-                self.state = State::Idle;
-                return;
+        if audio_data.len() < 1000 {
+            warn!("No audio recorded");
+            sound::play(&self.config.sounds, Cue::Error);
+            hooks::run(&self.config.hooks, Hook::Error, "No audio recorded");
+            notify("No audio recorded").await;
+            return Err(Refusal::NoAudio);
+        }
+        debug!("audio bytes: {}", audio_data.len());
+
+        let config = self.recording_config();
+        self.overrides = Args::default();
+        self.window = None;
+        self.selection = None;
+        self.last_clip = Some((audio_data.clone(), config.clone()));
+
+        sound::play(&config.sounds, Cue::Stop);
+        hooks::run(&config.hooks, Hook::Stop, "");
+        notify("Transcribing...").await;
+        self.enqueue(Job::Clip {
+            audio_data,
+            config,
+            generation,
+            archive: true,
+        });
+        Ok(())
+    }
+
+    /// The config to transcribe the current recording with.
+    fn recording_config(&self) -> Arc<Config> {
+        if self.overrides.is_empty()
+            && self.language.is_none()
+            && self.window.is_none()
+            && self.selection.is_none()
+        {
+            return self.config.clone();
+        }
+
+        let profile = self.profile();
+        debug!("profile {profile:?}");
+        let mut config = match profile {
+            Some(name) => self.config.with_profile(name).unwrap_or_else(|e| {
+                error!("{e}");
+                (*self.config).clone()
+            }),
+            None => (*self.config).clone(),
+        };
+        if let Some(language) = self.overrides.language.as_ref().or(self.language.as_ref()) {
+            config.language = language.clone();
+        }
+        if let Some(inject) = self.overrides.inject {
+            config.inject.enabled = inject;
+        }
+        if let Some(commit) = self.overrides.commit {
+            config.commit_message = commit;
+        }
+        if let Some(path) = &self.overrides.output {
+            config.output.target = Some(path.clone());
+        }
+        if let Some(context) = self
+            .selection
+            .as_deref()
+            .and_then(|text| prompt_context(text, SELECTION_PROMPT_CHARS))
+        {
+            config.prompt = format!("{} Clipboard: {context}", config.prompt)
+                .trim_start()
+                .to_string();
+        }
+        if let Some(window) = &self.window
+            && config.window_prompt
+        {
+            config.prompt = window_prompt(&config.prompt, window);
+        }
+        Arc::new(config)
+    }
+
+    /// Profile for the current recording. An explicit --profile wins over
+    /// the window's.
+    fn profile(&self) -> Option<&str> {
+        self.overrides.profile.as_deref().or_else(|| {
+            let window = self.window.as_ref()?;
+            self.config
+                .profile_for(window.app_id.as_deref(), window.title.as_deref())
+        })
+    }
+
+    /// Whether recordings depend on the focused window.
+    fn needs_window(&self) -> bool {
+        self.config.window_prompt || !self.config.profile_rules.is_empty()
+    }
+
+    fn enqueue(&self, job: Job) {
+        self.queue.pending.fetch_add(1, Ordering::AcqRel);
+        self.queue.enqueued.fetch_add(1, Ordering::AcqRel);
+        if self.jobs.send(job).is_err() {
+            error!("Transcription worker is gone");
+            self.queue.pending.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// How often a pre-roll recorder checks for a more preferred device.
+const DEVICE_CHECK: Duration = Duration::from_secs(5);
+
+/// How often recordings queued while offline are tried again.
+const OFFLINE_RETRY: Duration = Duration::from_secs(30);
+
+/// Copied text is cut short like window titles, leaving room for the prompt.
+const SELECTION_PROMPT_CHARS: usize = 200;
+
+/// Whisper only looks at the last ~224 tokens of the prompt, so the window
+/// goes at the end and long titles are cut short.
+fn window_prompt(prompt: &str, window: &Window) -> String {
+    let title: String = window
+        .title
+        .as_deref()
+        .unwrap_or("")
+        .chars()
+        .take(100)
+        .collect();
+    let context = match window.app_id.as_deref() {
+        Some(app) if !title.is_empty() => format!("{app}: {title}"),
+        Some(app) => app.to_string(),
+        None if !title.is_empty() => title,
+        None => return prompt.to_string(),
+    };
+    format!("{prompt} Current window: {context}")
+        .trim_start()
+        .to_string()
+}
+
+/// Remember injected text from both clips and live-streamed segments.
+async fn track_last_text(
+    mut events: broadcast::Receiver<Event>,
+    last_text: Arc<Mutex<Option<String>>>,
+) {
+    loop {
+        match events.recv().await {
+            Ok(Event::Text { text, .. }) => *last_text.lock().unwrap() = Some(text),
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+async fn run_worker(mut jobs: mpsc::UnboundedReceiver<Job>, queue: Arc<Queue>) {
+    let mut done = 0;
+    while let Some(job) = jobs.recv().await {
+        done += 1;
+        *queue.outcome.lock().unwrap() = None;
+        let generation = job.generation();
+        if queue.generation.load(Ordering::Acquire) == generation {
+            // A request that hangs would otherwise hold up every later job
+            let config = job.config().clone();
+            let deadline = Duration::from_secs(config.transcribe_timeout_secs);
+            // A stalled upload usually ends here rather than in an HTTP
+            // timeout, so the recording is kept the same way
+            let offline_copy = match &job {
+                Job::Clip { audio_data, .. } if config.offline_queue => Some(audio_data.clone()),
+                _ => None,
+            };
+            let transcribed = AtomicBool::new(false);
+            let processing = process_job(
+                job,
+                &queue,
+                generation,
+                offline_copy.as_deref(),
+                &transcribed,
+            );
+            if deadline.is_zero() {
+                processing.await;
+            } else if tokio::time::timeout(deadline, processing).await.is_err() {
+                let message = format!("Transcription timed out after {}s", deadline.as_secs());
+                error!("{message}");
+                let queued = match &offline_copy {
+                    Some(audio_data) if !transcribed.load(Ordering::Acquire) => {
+                        offline::save(audio_data).await
+                    }
+                    _ => None,
+                };
+                queue.metrics.record_error(config.provider.as_str());
+                sound::play(&config.sounds, Cue::Error);
+                hooks::run(&config.hooks, Hook::Error, &message);
+                queue.publish(Event::Error {
+                    message: message.clone(),
+                });
+                match queued {
+                    Some(count) => {
+                        notify(&format!("{message}, recording queued ({count} waiting)")).await
+                    }
+                    None => notify(&message).await,
+                }
+            }
+        } else {
+            debug!("dropping cancelled job");
+        }
+        let outcome = queue.outcome.lock().unwrap().take();
+        let _ = queue.finished.send((done, outcome));
+        queue.job_done();
+    }
+}
+
+/// `offline_copy` is the audio to queue if the provider can't be reached.
+/// `transcribed` is set once it has answered, so a job the watchdog stops
+/// after that isn't queued to be sent again.
+async fn process_job(
+    job: Job,
+    queue: &Arc<Queue>,
+    generation: u64,
+    offline_copy: Option<&[u8]>,
+    transcribed: &AtomicBool,
+) {
+    let (audio_data, config, archive) = match job {
+        Job::Stream { stream, config, .. } => {
+            let start = std::time::Instant::now();
+            match stream.finish().await {
+                Ok(0) => {}
+                Ok(_) => queue.metrics.record_dictation("deepgram", None),
+                Err(e) => {
+                    error!("Streaming failed: {e}");
+                    queue.metrics.record_error("deepgram");
+                    sound::play(&config.sounds, Cue::Error);
+                    hooks::run(&config.hooks, Hook::Error, &e.to_string());
+                    queue.publish(Event::Error {
+                        message: e.to_string(),
+                    });
+                    notify(&format!("Error: {e}")).await;
+                }
             }
-            Err(_) => {
-                eprintln!("No audio file");
-                notify("Recording failed").await;
-                self.state = State::Idle;
+            debug!("stream_finish: {:?}", start.elapsed());
+            return;
+        }
+        Job::Clip {
+            audio_data,
+            config,
+            archive,
+            ..
+        } => (audio_data, config, archive),
+    };
+
+    let total_start = std::time::Instant::now();
+    let audio_secs = stats::wav_secs(&audio_data);
+    // Encode and save alongside the request, so it adds no latency
+    let archiving = (archive && config.archive.enabled).then(|| {
+        let (audio_data, config) = (audio_data.clone(), config.clone());
+        tokio::spawn(async move {
+            archive::save(&audio_data, &config.archive, config.opus_bitrate).await
+        })
+    });
+    let (result, draft) = tokio::select! {
+        result = transcribe_with_draft(audio_data, &config) => result,
+        _ = queue.cancelled.notified() => {
+            debug!("cancelled in-flight transcription");
+            return;
+        }
+    };
+    if result.is_ok() {
+        transcribed.store(true, Ordering::Release);
+    }
+    match result {
+        // A cancel that arrived mid-request still keeps the text out
+        Ok(_) if queue.generation.load(Ordering::Acquire) != generation => {
+            debug!("discarding cancelled transcription");
+        }
+        Ok(transcript) => {
+            let latency = total_start.elapsed();
+            let raw = transcript.text;
+            let language = transcript.language;
+            debug!("raw: {}", loggable(&raw, config.log_transcripts));
+            if raw.is_empty() {
+                if let Some(draft) = &draft
+                    && !inject::unhold(draft)
+                    && let Some(injection) = inject::take_last_injection()
+                {
+                    inject::erase(injection, &config.inject).await;
+                }
+                notify("No speech detected").await;
                 return;
             }
-            Ok(meta) => {
-                debug!("audio bytes: {}", meta.len());
+            let text = apply_postprocess(raw.clone(), &config).await;
+            let text = apply_replacements(&text, config.replacements_for(language.as_deref()));
+            debug!("replaced: {}", loggable(&text, config.log_transcripts));
+            // Kept before any review, so text isn't lost to a prompt that's
+            // dismissed or never answered
+            if config.history && !text.is_empty() {
+                let provider = config.provider.as_str();
+                let mut entry =
+                    Entry::new(provider, Some(latency), language.as_deref(), &raw, &text);
+                if let Some(archiving) = archiving {
+                    entry.audio = archiving.await.ok().flatten();
+                }
+                history::record(&entry).await;
+            }
+            if config.confirm.is_empty() || text.is_empty() {
+                let latency = total_start.elapsed();
+                deliver(text, draft, language, &config, queue, audio_secs, latency).await;
+            } else {
+                // The prompt can stay up as long as it likes without running
+                // into the watchdog or holding up later recordings
+                let (config, queue) = (config.clone(), queue.clone());
+                tokio::spawn(async move {
+                    let review_start = std::time::Instant::now();
+                    let Some(text) = confirm::review(&config.confirm, &text).await else {
+                        notify("Discarded").await;
+                        return;
+                    };
+                    // Time spent reading the prompt isn't latency
+                    let latency = total_start.elapsed() - review_start.elapsed();
+                    deliver(text, draft, language, &config, &queue, audio_secs, latency).await;
+                });
+            }
+            // Getting through means anything queued offline can go too
+            if config.offline_queue && !offline::queued().is_empty() {
+                tokio::spawn(flush_offline(config.clone(), queue.clone(), false));
             }
         }
+        Err(e) => {
+            error!("Transcription failed: {e}");
+            if draft.is_some() {
+                warn!("Keeping the draft");
+            }
+            let queued = match offline_copy {
+                Some(audio_data) if e.is_offline() => offline::save(audio_data).await,
+                _ => None,
+            };
+            queue.metrics.record_error(config.provider.as_str());
+            sound::play(&config.sounds, Cue::Error);
+            hooks::run(&config.hooks, Hook::Error, &e.to_string());
+            queue.publish(Event::Error {
+                message: e.to_string(),
+            });
+            match queued {
+                Some(count) => notify(&format!("{e}, recording queued ({count} waiting)")).await,
+                None => notify(&format!("Error: {e}")).await,
+            }
+        }
+    }
+    debug!("total: {:?}", total_start.elapsed());
+}
 
-        self.state = State::Transcribing;
-        notify("Transcribing...").await;
+/// Send the finished text wherever it goes: the focused window (replacing
+/// the draft if it was wrong), the other sinks, or `commands`.
+async fn deliver(
+    text: String,
+    draft: Option<String>,
+    language: Option<String>,
+    config: &Config,
+    queue: &Queue,
+    audio_secs: f64,
+    latency: Duration,
+) {
+    if text.is_empty() {
+        return;
+    }
+    if config.command_mode {
+        commands::run(&config.command_grammar, &text).await;
+    } else if draft.as_deref() == Some(text.as_str()) {
+        // The draft was right, so only the other sinks need it
+        let mut inject = config.inject.clone();
+        inject.enabled = false;
+        output::deliver(&text, false, &config.output, &inject).await;
+    } else {
+        // A draft held back while locked is dropped rather than erased
+        if let Some(draft) = &draft
+            && !inject::unhold(draft)
+            && let Some(injection) = inject::take_last_injection()
+        {
+            inject::erase(injection, &config.inject).await;
+        }
+        output::deliver(&text, false, &config.output, &config.inject).await;
+    }
+    hooks::run(&config.hooks, Hook::Text, &text);
+    queue
+        .metrics
+        .record_dictation(config.provider.as_str(), Some(latency));
+    stats::record(
+        config.provider.as_str(),
+        text.split_whitespace().count(),
+        audio_secs,
+        Some(latency),
+    )
+    .await;
+    queue.publish(Event::Text { text, language });
+}
 
-        let read_start = std::time::Instant::now();
-        let audio_data = match tokio::fs::read(&self.audio_file).await {
-            Ok(data) => data,
+/// Transcribe with `model`, typing the text from `draft_model` as soon as it
+/// arrives if that comes first. Returns the draft that was typed, so the
+/// caller can replace it.
+async fn transcribe_with_draft(
+    audio_data: Vec<u8>,
+    config: &Config,
+) -> (Result<Transcript, WayvoiceError>, Option<String>) {
+    if !wants_draft(config) {
+        return (transcribe_audio(audio_data, config).await, None);
+    }
+    let mut draft_config = config.clone();
+    draft_config.model = config.draft_model.clone();
+    // The draft is there to be fast already
+    draft_config.race_provider.clear();
+    let draft = transcribe_audio(audio_data.clone(), &draft_config);
+    let full = transcribe_audio(audio_data, config);
+    tokio::pin!(full);
+
+    let text = tokio::select! {
+        result = &mut full => return (result, None),
+        draft = draft => match draft {
+            Ok(draft) => apply_replacements(
+                &draft.text,
+                config.replacements_for(draft.language.as_deref()),
+            ),
             Err(e) => {
-                eprintln!("Failed to read audio file: {e}");
-                notify(&format!("Error: {e}")).await;
-                self.state = State::Idle;
-                return;
+                warn!("Draft failed: {e}");
+                return (full.await, None);
             }
-        };
-        debug!("file_read: {:?}", read_start.elapsed());
+        },
+    };
+    if text.is_empty() {
+        return (full.await, None);
+    }
+    debug!("draft: {}", loggable(&text, config.log_transcripts));
+    inject_text(&text, &config.inject).await;
+    (full.await, Some(text))
+}
 
-        match transcribe_audio(audio_data, &self.config).await {
-            Ok(text) => {
-                debug!("raw: {text}");
-                let text = apply_replacements(&text, &self.config.replacements);
-                debug!("replaced: {text}");
+/// Drafts are only typed where they can be taken back: straight into the
+/// focused window, with nothing else deciding what the final text does.
+fn wants_draft(config: &Config) -> bool {
+    !config.draft_model.is_empty()
+        && config.inject.enabled
+        && config.output.sinks.contains(&Sink::Inject)
+        && config.output.target.is_none()
+        && config.confirm.is_empty()
+        && !config.command_mode
+        && !config.commit_message
+}
+
+/// Send recordings queued while offline, oldest first, stopping at the
+/// first one that still can't get through. Their text goes to history and
+/// subscribers, where `replay` can pick it up, rather than into whatever
+/// window has focus by now.
+async fn flush_offline(config: Arc<Config>, queue: Arc<Queue>, manual: bool) {
+    if !offline::start_flush() {
+        return;
+    }
+    // Counts as a job, so `cancel` stops it like any other transcription
+    queue.pending.fetch_add(1, Ordering::AcqRel);
+    let generation = queue.generation.load(Ordering::Acquire);
+    let cancelled = || queue.generation.load(Ordering::Acquire) != generation;
+    let mut sent = 0;
+    let mut still_offline = false;
+    for path in offline::queued() {
+        if cancelled() {
+            break;
+        }
+        let audio_data = match tokio::fs::read(&path).await {
+            Ok(audio_data) => audio_data,
+            Err(e) => {
+                error!("Failed to read {path:?}: {e}");
+                continue;
+            }
+        };
+        let result = tokio::select! {
+            result = transcribe_audio(audio_data, &config) => result,
+            _ = queue.cancelled.notified() => break,
+        };
+        match result {
+            // Left in the queue for next time
+            Ok(_) if cancelled() => break,
+            Ok(transcript) => {
+                let text = apply_postprocess(transcript.text.clone(), &config).await;
+                let language = transcript.language;
+                let text = apply_replacements(&text, config.replacements_for(language.as_deref()));
+                if config.history && !text.is_empty() {
+                    let provider = config.provider.as_str();
+                    let entry =
+                        Entry::new(provider, None, language.as_deref(), &transcript.text, &text);
+                    history::record(&entry).await;
+                }
+                offline::remove(&path).await;
+                sent += 1;
+                // Straight to subscribers: `publish` would make it the
+                // outcome of whatever dictation is in flight
                 if !text.is_empty() {
-                    let inject_start = std::time::Instant::now();
-                    inject_text(&text).await;
-                    debug!("inject: {:?}", inject_start.elapsed());
+                    let _ = queue.events.send(Event::Text { text, language });
                 }
             }
+            Err(e) if e.is_offline() => {
+                debug!("still offline: {e}");
+                still_offline = true;
+                break;
+            }
             Err(e) => {
-                eprintln!("Transcription failed: {e}");
-                notify(&format!("Error: {e}")).await;
+                error!("Queued recording {path:?} failed: {e}");
+                offline::set_aside(&path).await;
             }
         }
+    }
+    offline::end_flush();
+    queue.job_done();
+    if cancelled() {
+        debug!("flush cancelled");
+        return;
+    }
 
-        debug!("total: {:?}", total_start.elapsed());
-        self.state = State::Idle;
+    let left = offline::queued().len();
+    if sent > 0 {
+        let mut message = format!("Transcribed {sent} queued, see `wayvoice history`");
+        if left > 0 {
+            message.push_str(&format!("; {left} still queued"));
+        }
+        notify(&message).await;
+    } else if manual && still_offline {
+        notify(&format!("Still offline, {left} queued")).await;
     }
 }
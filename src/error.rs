@@ -0,0 +1,115 @@
+use serde::Deserialize;
+use std::fmt::Display;
+use thiserror::Error;
+
+/// Everything that can go wrong between pressing the key and text appearing.
+/// `Display` is kept short enough for a notification; `Debug` has the details.
+#[derive(Debug, Error)]
+pub enum WayvoiceError {
+    /// Missing or invalid configuration, e.g. no API key.
+    #[error("{0}")]
+    Config(String),
+    /// Capturing, decoding or encoding audio.
+    #[error("{0}")]
+    Audio(String),
+    /// A provider could not be reached.
+    #[error("{}", describe_http(.0))]
+    Http(#[from] reqwest::Error),
+    /// A provider answered with an error status.
+    #[error("{service} error {status}: {message}")]
+    Api {
+        service: &'static str,
+        status: reqwest::StatusCode,
+        message: String,
+    },
+    /// A transcriber failed some other way (local binary, streaming socket).
+    #[error("{0}")]
+    Provider(String),
+    /// Typing or pasting into the focused window.
+    #[error("{0}")]
+    Inject(String),
+    /// Talking to the daemon over its socket.
+    #[error("{0}")]
+    Ipc(String),
+}
+
+impl WayvoiceError {
+    pub fn config(e: impl Display) -> Self {
+        Self::Config(e.to_string())
+    }
+
+    pub fn audio(e: impl Display) -> Self {
+        Self::Audio(e.to_string())
+    }
+
+    pub fn provider(e: impl Display) -> Self {
+        Self::Provider(e.to_string())
+    }
+
+    pub fn inject(e: impl Display) -> Self {
+        Self::Inject(e.to_string())
+    }
+
+    pub fn ipc(e: impl Display) -> Self {
+        Self::Ipc(e.to_string())
+    }
+
+    /// Turn a non-success HTTP response into an `Api` error, pulling the
+    /// message out of OpenAI-style `{"error": {"message": ...}}` bodies.
+    pub async fn from_response(service: &'static str, response: reqwest::Response) -> Self {
+        #[derive(Deserialize)]
+        struct Body {
+            error: Detail,
+        }
+        #[derive(Deserialize)]
+        struct Detail {
+            message: String,
+        }
+
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        let message = match serde_json::from_str::<Body>(&body) {
+            Ok(parsed) => parsed.error.message,
+            Err(_) => body.trim().chars().take(200).collect(),
+        };
+        Self::Api {
+            service,
+            status,
+            message,
+        }
+    }
+
+    /// Whether the provider couldn't be reached at all, as when the network
+    /// is down.
+    pub fn is_offline(&self) -> bool {
+        matches!(self, Self::Http(e) if e.is_connect() || e.is_timeout())
+    }
+
+    /// Process exit code for the CLI, one per kind of failure.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Config(_) => 3,
+            Self::Audio(_) => 4,
+            Self::Http(_) | Self::Api { .. } | Self::Provider(_) => 5,
+            Self::Inject(_) => 6,
+            Self::Ipc(_) => 7,
+        }
+    }
+}
+
+/// reqwest's `Display` is a chain of internals; say what went wrong instead.
+pub(crate) fn describe_http(e: &reqwest::Error) -> String {
+    let host = e
+        .url()
+        .and_then(|url| url.host_str())
+        .unwrap_or("the provider");
+    if e.is_timeout() {
+        format!("Request to {host} timed out")
+    } else if e.is_connect() {
+        format!("Could not connect to {host}")
+    } else if e.is_decode() {
+        format!("Unexpected response from {host}")
+    } else {
+        format!("Network error talking to {host}")
+    }
+}
@@ -0,0 +1,81 @@
+use serde_json::Value;
+use tokio::process::Command;
+use tracing::debug;
+
+#[derive(Debug, Default)]
+pub struct Window {
+    /// Wayland app id, or X11 class under XWayland.
+    pub app_id: Option<String>,
+    pub title: Option<String>,
+}
+
+/// The focused window, asked of whichever compositor we're running under.
+/// `None` if it can't be determined.
+pub async fn focused_window() -> Option<Window> {
+    let start = std::time::Instant::now();
+    let window = if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        let window = query_json("hyprctl", &["activewindow", "-j"]).await?;
+        Window {
+            app_id: string_field(&window, "class"),
+            title: string_field(&window, "title"),
+        }
+    } else if std::env::var_os("NIRI_SOCKET").is_some() {
+        let window = query_json("niri", &["msg", "--json", "focused-window"]).await?;
+        Window {
+            app_id: string_field(&window, "app_id"),
+            title: string_field(&window, "title"),
+        }
+    } else if std::env::var_os("SWAYSOCK").is_some() {
+        let tree = query_json("swaymsg", &["-t", "get_tree"]).await?;
+        let node = find_focused(&tree)?;
+        Window {
+            app_id: string_field(node, "app_id")
+                .or_else(|| string_field(&node["window_properties"], "class")),
+            title: string_field(node, "name"),
+        }
+    } else {
+        return None;
+    };
+    debug!("focused_window={window:?} ({:?})", start.elapsed());
+    Some(window)
+}
+
+/// App id (or X11 class) of the focused window.
+pub async fn focused_app() -> Option<String> {
+    focused_window().await?.app_id
+}
+
+async fn query_json(bin: &str, args: &[&str]) -> Option<Value> {
+    let output = Command::new(bin).args(args).output().await;
+    match output {
+        Ok(output) if output.status.success() => serde_json::from_slice(&output.stdout).ok(),
+        Ok(output) => {
+            debug!("{bin} exited with {}", output.status);
+            None
+        }
+        Err(e) => {
+            debug!("failed to run {bin}: {e}");
+            None
+        }
+    }
+}
+
+fn string_field(value: &Value, key: &str) -> Option<String> {
+    value
+        .get(key)?
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+}
+
+/// Depth-first search of sway's layout tree for the focused window.
+fn find_focused(node: &Value) -> Option<&Value> {
+    if node["focused"].as_bool() == Some(true) {
+        return Some(node);
+    }
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[key].as_array())
+        .flatten()
+        .find_map(find_focused)
+}
@@ -0,0 +1,158 @@
+use jiff::Timestamp;
+use jiff::tz::TimeZone;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tracing::error;
+
+/// One transcription, as appended to `history.jsonl`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: Timestamp,
+    pub provider: String,
+    /// Time spent transcribing; absent for live-streamed segments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// Language the provider detected, if it reports one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// The recording, when `[archive]` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio: Option<PathBuf>,
+    /// Text as returned by the provider.
+    pub raw: String,
+    /// Text after replacements, i.e. what was injected or printed.
+    pub text: String,
+}
+
+impl Entry {
+    pub fn new(
+        provider: &str,
+        latency: Option<Duration>,
+        language: Option<&str>,
+        raw: &str,
+        text: &str,
+    ) -> Self {
+        Self {
+            timestamp: Timestamp::now(),
+            provider: provider.to_string(),
+            latency_ms: latency.map(|d| d.as_millis() as u64),
+            language: language.map(str::to_string),
+            audio: None,
+            raw: raw.to_string(),
+            text: text.to_string(),
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    Some(dirs::state_dir()?.join("wayvoice").join("history.jsonl"))
+}
+
+/// Append `entry` to the history file. Failures are logged, never fatal.
+pub async fn record(entry: &Entry) {
+    let Some(path) = history_path() else {
+        return;
+    };
+    if let Err(e) = append(&path, entry).await {
+        error!("Failed to write history to {path:?}: {e}");
+    }
+}
+
+async fn append(path: &PathBuf, entry: &Entry) -> std::io::Result<()> {
+    // Dictated text is nobody else's business
+    if let Some(dir) = path.parent() {
+        tokio::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .await?;
+    }
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+        .await?;
+    file.write_all(&line).await
+}
+
+/// All recorded entries, oldest first. Lines that don't parse are skipped.
+pub fn load() -> Vec<Entry> {
+    let Some(content) = history_path().and_then(|path| std::fs::read_to_string(path).ok()) else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Print the `limit` most recent entries, newest first. Ids count back from
+/// the newest entry, which is 1.
+pub fn list(limit: usize) {
+    let entries = load();
+    for (id, entry) in entries.iter().rev().enumerate().take(limit) {
+        print_summary(id + 1, entry);
+    }
+}
+
+/// The entry with the given id (1 = most recent), or exit with an error.
+pub fn get(id: usize) -> Entry {
+    let entries = load();
+    let Some(entry) = id
+        .checked_sub(1)
+        .and_then(|i| entries.into_iter().rev().nth(i))
+    else {
+        eprintln!("No history entry {id}");
+        std::process::exit(1);
+    };
+    entry
+}
+
+pub fn show(id: usize) {
+    let entry = get(id);
+
+    println!("time:     {}", local_time(&entry.timestamp));
+    println!("provider: {}", entry.provider);
+    if let Some(ms) = entry.latency_ms {
+        println!("latency:  {ms}ms");
+    }
+    if let Some(language) = &entry.language {
+        println!("language: {language}");
+    }
+    if let Some(audio) = &entry.audio {
+        println!("audio:    {}", audio.display());
+    }
+    println!("raw:      {}", entry.raw);
+    println!("text:     {}", entry.text);
+}
+
+/// Case-insensitive substring search over raw and processed text.
+pub fn search(query: &str) {
+    let query = query.to_lowercase();
+    let entries = load();
+    for (id, entry) in entries.iter().rev().enumerate() {
+        if entry.text.to_lowercase().contains(&query) || entry.raw.to_lowercase().contains(&query) {
+            print_summary(id + 1, entry);
+        }
+    }
+}
+
+fn print_summary(id: usize, entry: &Entry) {
+    println!(
+        "{id:>4}  {}  {}",
+        local_time(&entry.timestamp),
+        entry.text.replace('\n', " ")
+    );
+}
+
+fn local_time(timestamp: &Timestamp) -> String {
+    timestamp
+        .to_zoned(TimeZone::system())
+        .strftime("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
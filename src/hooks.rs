@@ -0,0 +1,74 @@
+use crate::config::HooksConfig;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, error};
+
+/// Points in a dictation where a `[hooks]` command can run.
+#[derive(Debug, Clone, Copy)]
+pub enum Hook {
+    Start,
+    Stop,
+    /// After the text was injected; `input` is the text.
+    Text,
+    /// `input` is the error message.
+    Error,
+}
+
+impl Hook {
+    fn name(self) -> &'static str {
+        match self {
+            Hook::Start => "start",
+            Hook::Stop => "stop",
+            Hook::Text => "text",
+            Hook::Error => "error",
+        }
+    }
+}
+
+/// Run the command configured for `hook` in the background with `input` on
+/// stdin, and also in `$WAYVOICE_TEXT` or `$WAYVOICE_ERROR`.
+pub fn run(config: &HooksConfig, hook: Hook, input: &str) {
+    let command = match hook {
+        Hook::Start => &config.on_start,
+        Hook::Stop => &config.on_stop,
+        Hook::Text => &config.on_text,
+        Hook::Error => &config.on_error,
+    };
+    if command.is_empty() {
+        return;
+    }
+    debug!("on_{} hook: {command}", hook.name());
+
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", command])
+        .env("WAYVOICE_EVENT", hook.name())
+        .stdin(Stdio::piped());
+    match hook {
+        Hook::Text => cmd.env("WAYVOICE_TEXT", input),
+        Hook::Error => cmd.env("WAYVOICE_ERROR", input),
+        Hook::Start | Hook::Stop => &mut cmd,
+    };
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Failed to run on_{} hook: {e}", hook.name());
+            return;
+        }
+    };
+
+    let input = input.to_string();
+    tokio::spawn(async move {
+        if let Some(mut stdin) = child.stdin.take() {
+            // A hook that ignores stdin closes it early; that's fine
+            let _ = stdin.write_all(input.as_bytes()).await;
+        }
+        match child.wait().await {
+            Ok(status) if !status.success() => {
+                error!("on_{} hook failed ({status})", hook.name());
+            }
+            Ok(_) => {}
+            Err(e) => error!("on_{} hook failed: {e}", hook.name()),
+        }
+    });
+}
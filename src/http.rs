@@ -0,0 +1,87 @@
+use crate::config::Config;
+use crate::error::{WayvoiceError, describe_http};
+use reqwest::StatusCode;
+use std::time::Duration;
+use tracing::{debug, error, warn};
+
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The HTTP client shared by every request made with a config. Keeping one
+/// around lets reqwest reuse connections, so a dictation doesn't pay for a
+/// fresh TLS handshake.
+///
+/// Without `proxy` in the config, reqwest picks up `HTTPS_PROXY`,
+/// `ALL_PROXY` and `NO_PROXY` from the environment.
+pub fn build_client(config: &Config) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder().tcp_keepalive(Duration::from_secs(60));
+    if config.request_timeout_secs > 0 {
+        let timeout = Duration::from_secs(config.request_timeout_secs);
+        builder = builder
+            .timeout(timeout)
+            .connect_timeout(timeout.min(CONNECT_TIMEOUT));
+    }
+    if !config.proxy.is_empty() {
+        match reqwest::Proxy::all(&config.proxy) {
+            Ok(proxy) => builder = builder.proxy(proxy),
+            Err(e) => error!("Invalid proxy {:?}: {e}", config.proxy),
+        }
+    }
+    builder.build().unwrap_or_else(|e| {
+        error!("Failed to build HTTP client: {e}");
+        reqwest::Client::new()
+    })
+}
+
+/// Send the request from `build`, retrying rate limits, server errors,
+/// timeouts and failed connections up to `retries` times with exponential
+/// backoff. `build` is called again for each attempt because multipart
+/// bodies can't be cloned.
+pub async fn send_with_retry(
+    service: &str,
+    retries: u32,
+    mut build: impl FnMut() -> Result<reqwest::RequestBuilder, WayvoiceError>,
+) -> Result<reqwest::Response, WayvoiceError> {
+    let mut attempt = 0;
+    loop {
+        let result = build()?.send().await;
+        let (reason, delay) = match &result {
+            _ if attempt >= retries => return Ok(result?),
+            Ok(response) if is_transient(response.status()) => (
+                response.status().to_string(),
+                retry_after(response).unwrap_or_else(|| backoff(attempt)),
+            ),
+            Err(e) if e.is_timeout() || e.is_connect() => {
+                debug!("{service} request failed: {e:?}");
+                (describe_http(e), backoff(attempt))
+            }
+            _ => return Ok(result?),
+        };
+        attempt += 1;
+        warn!("{service}: {reason}, retrying in {delay:?} ({attempt}/{retries})");
+        tokio::time::sleep(delay).await;
+    }
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// 0.5s, 1s, 2s, ... capped at `MAX_BACKOFF`.
+fn backoff(attempt: u32) -> Duration {
+    (Duration::from_millis(500) * 2u32.saturating_pow(attempt)).min(MAX_BACKOFF)
+}
+
+/// A `Retry-After` header in seconds, as rate limiters send with 429s.
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let secs: u64 = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(secs).min(MAX_RETRY_AFTER))
+}
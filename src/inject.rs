@@ -1,15 +1,174 @@
-use log::debug;
+use crate::config::{InjectConfig, InjectMode};
+use crate::focus::focused_app;
+use crate::lock;
+use crate::notify::notify;
+use crate::{portal, virtual_keyboard};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
+use tracing::{debug, error};
 
-pub async fn inject_text(text: &str) {
-    let mode = injection_mode();
-    if mode == "clipboard" {
-        inject_via_clipboard(text).await;
+/// Ctrl+Shift+V works universally without conflicting with Ghostty's Ctrl+V
+/// image paste or requiring xremap translation.
+const DEFAULT_PASTE_KEY: &str = "ctrl+shift+v";
+
+/// Linux input keycode for BackSpace, for `ydotool key`.
+const KEY_BACKSPACE: u32 = 14;
+
+/// What the last `inject_text` typed, for `undo`.
+static LAST_INJECTION: Mutex<Option<Injection>> = Mutex::new(None);
+
+/// Text that arrived while the screen was locked, for `replay`.
+static HELD: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Text typed into the focused window: how many characters, and how.
+#[derive(Debug, Clone, Copy)]
+pub struct Injection {
+    mode: InjectMode,
+    chars: usize,
+}
+
+/// Forget and return the last injection, so it's only undone once.
+pub fn take_last_injection() -> Option<Injection> {
+    LAST_INJECTION.lock().unwrap().take()
+}
+
+/// Forget `draft` if it's the last text held back, so the final text
+/// replaces it instead of `replay` typing both. False if it wasn't held.
+pub fn unhold(draft: &str) -> bool {
+    let mut held = HELD.lock().unwrap();
+    if held.last().map(String::as_str) != Some(draft) {
+        return false;
+    }
+    held.pop();
+    true
+}
+
+/// Take the text held back while the screen was locked, joined up.
+pub fn take_held() -> Option<String> {
+    let held = std::mem::take(&mut *HELD.lock().unwrap());
+    (!held.is_empty()).then(|| {
+        held.iter()
+            .map(|text| text.trim())
+            .collect::<Vec<_>>()
+            .join(" ")
+    })
+}
+
+#[tracing::instrument(name = "inject", level = "debug", skip_all)]
+pub async fn inject_text(text: &str, config: &InjectConfig) {
+    // Only ask the compositor when there's a rule that could match
+    let app = if config.rules.is_empty() {
+        None
+    } else {
+        focused_app().await
+    };
+    let rule = app.as_deref().and_then(|app| config.rule_for(app));
+
+    let mode = rule.and_then(|r| r.mode).unwrap_or(config.mode);
+    let paste_key = rule
+        .and_then(|r| r.paste_key.as_deref())
+        .unwrap_or(DEFAULT_PASTE_KEY);
+    if rule.is_some() {
+        debug!("inject rule for {app:?}: mode={mode:?} paste_key={paste_key}");
+    }
+    // Typing would land in the password field
+    if config.hold_while_locked && lock::is_locked().await {
+        debug!("screen locked, holding {} chars", text.chars().count());
+        HELD.lock().unwrap().push(text.to_string());
+        notify("Screen locked, `wayvoice replay` types it after unlocking").await;
         return;
     }
 
-    let delay_ms = wtype_delay_ms(&mode);
-    let key_delay_ms = wtype_key_delay_ms();
+    let typed = match mode {
+        InjectMode::Clipboard => inject_via_clipboard(text, paste_key, config).await,
+        InjectMode::Wtype => inject_via_wtype(text, config).await,
+        InjectMode::Wayland => inject_via_virtual_keyboard(text, config).await,
+        InjectMode::Ydotool => inject_via_ydotool(text, config).await,
+        InjectMode::Portal => inject_via_portal(text, config).await,
+    };
+    // Undoing text that never arrived would erase the user's own
+    if !typed {
+        return;
+    }
+    *LAST_INJECTION.lock().unwrap() = Some(Injection {
+        mode,
+        chars: text.chars().count(),
+    });
+}
+
+/// Erase an injection with one backspace per character, through the same
+/// backend that typed it. A paste is erased the same way, via wtype.
+pub async fn erase(injection: Injection, config: &InjectConfig) {
+    let Injection { mode, chars } = injection;
+    debug!("undo: {chars} chars via {mode:?}");
+    let backspaces = "\u{8}".repeat(chars);
+    match mode {
+        InjectMode::Clipboard | InjectMode::Wtype => {
+            let mut cmd = Command::new("wtype");
+            if config.key_delay_ms > 0 {
+                cmd.args(["-d", &config.key_delay_ms.to_string()]);
+            }
+            for _ in 0..chars {
+                cmd.args(["-k", "BackSpace"]);
+            }
+            if let Err(e) = cmd.status().await {
+                error!("wtype failed: {e}");
+                notify("Undo failed").await;
+            }
+        }
+        InjectMode::Ydotool => {
+            let mut cmd = Command::new("ydotool");
+            cmd.args(["key", "--key-delay", &config.key_delay_ms.to_string()]);
+            for _ in 0..chars {
+                cmd.args([format!("{KEY_BACKSPACE}:1"), format!("{KEY_BACKSPACE}:0")]);
+            }
+            match cmd.status().await {
+                Ok(status) if status.success() => {}
+                Ok(status) => {
+                    error!("ydotool exited with {status} (is ydotoold running?)");
+                    notify("Undo failed").await;
+                }
+                Err(e) => {
+                    error!("ydotool failed: {e}");
+                    notify("Undo failed").await;
+                }
+            }
+        }
+        InjectMode::Wayland => {
+            inject_via_virtual_keyboard(&backspaces, config).await;
+        }
+        InjectMode::Portal => {
+            inject_via_portal(&backspaces, config).await;
+        }
+    }
+}
+
+/// Put `text` on the regular clipboard (not primary). Failures are
+/// reported; returns whether it worked.
+pub async fn copy_to_clipboard(text: &str) -> bool {
+    match Command::new("wl-copy").arg("--").arg(text).status().await {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            error!("wl-copy failed ({status})");
+            notify("Copying to the clipboard failed").await;
+            false
+        }
+        Err(e) => {
+            error!("wl-copy failed: {e}");
+            notify("Copying to the clipboard failed").await;
+            false
+        }
+    }
+}
+
+/// The `inject_via_*` backends report failures and return whether the text
+/// was typed.
+async fn inject_via_wtype(text: &str, config: &InjectConfig) -> bool {
+    let delay_ms = config.delay_ms(InjectMode::Wtype);
+    let key_delay_ms = config.key_delay_ms;
     debug!(
         "wtype delay_ms={delay_ms} key_delay_ms={key_delay_ms} text_len={}",
         text.len()
@@ -23,76 +182,251 @@ pub async fn inject_text(text: &str) {
         cmd.args(["-d", &key_delay_ms.to_string()]);
     }
     cmd.arg("--").arg(text);
-    let status = cmd.status().await;
-    if let Err(e) = status {
-        eprintln!("wtype failed: {e}");
-        notify("Injection failed").await;
+    wtype_succeeded(cmd.status().await).await
+}
+
+async fn wtype_succeeded(status: std::io::Result<std::process::ExitStatus>) -> bool {
+    match status {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            error!("wtype exited with {status}");
+            notify("Injection failed").await;
+            false
+        }
+        Err(e) => {
+            error!("wtype failed: {e}");
+            notify("Injection failed").await;
+            false
+        }
     }
 }
 
-async fn inject_via_clipboard(text: &str) {
-    let delay_ms = wtype_delay_ms("clipboard");
+async fn inject_via_clipboard(text: &str, paste_key: &str, config: &InjectConfig) -> bool {
+    let delay_ms = config.delay_ms(InjectMode::Clipboard);
     debug!(
         "injector=clipboard delay_ms={delay_ms} text_len={}",
         text.len()
     );
 
-    // Copy to regular clipboard (not primary) for universal compatibility
-    let mut copy = Command::new("wl-copy");
-    copy.arg("--").arg(text);
-    if let Err(e) = copy.status().await {
-        eprintln!("wl-copy failed: {e}");
-        notify("Injection failed").await;
-        return;
+    let restore_ms = config.clipboard_restore_ms;
+    let previous = if restore_ms > 0 {
+        snapshot_clipboard().await
+    } else {
+        None
+    };
+
+    // Regular clipboard (not primary) for universal compatibility
+    if !copy_to_clipboard(text).await {
+        return false;
     }
 
     if delay_ms > 0 {
-        tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
     }
 
-    // Use Ctrl+Shift+V to paste (works universally without conflicting with
-    // Ghostty's Ctrl+V image paste or requiring xremap translation)
     let status = Command::new("wtype")
-        .args([
-            "-M", "ctrl", "-M", "shift", "-k", "v", "-m", "shift", "-m", "ctrl",
-        ])
+        .args(paste_key_args(paste_key))
         .status()
         .await;
+    let pasted = wtype_succeeded(status).await;
 
-    if let Err(e) = status {
-        eprintln!("wtype failed: {e}");
-        notify("Injection failed").await;
+    if restore_ms > 0 {
+        // Give the focused app time to read the clipboard before swapping it back
+        tokio::time::sleep(Duration::from_millis(restore_ms)).await;
+        restore_clipboard(previous).await;
     }
+    pasted
 }
 
-pub async fn notify(message: &str) {
-    let _ = Command::new("notify-send")
-        .args([
-            "--app-name=wayvoice",
-            "--expire-time=2000",
-            "wayvoice",
-            message,
-        ])
+/// wtype arguments for a shortcut like "ctrl+shift+v": hold each modifier,
+/// tap the last key, then release the modifiers in reverse.
+fn paste_key_args(paste_key: &str) -> Vec<String> {
+    let mut parts: Vec<&str> = paste_key.split('+').map(str::trim).collect();
+    let key = parts.pop().unwrap_or("v");
+    let mut args = Vec::new();
+    for modifier in &parts {
+        args.extend(["-M".to_string(), modifier.to_lowercase()]);
+    }
+    args.extend(["-k".to_string(), key.to_string()]);
+    for modifier in parts.iter().rev() {
+        args.extend(["-m".to_string(), modifier.to_lowercase()]);
+    }
+    args
+}
+
+/// What was on the clipboard before we replaced it, as (mime type, bytes).
+type ClipboardSnapshot = Option<(String, Vec<u8>)>;
+
+async fn snapshot_clipboard() -> ClipboardSnapshot {
+    let types = Command::new("wl-paste").arg("--list-types").output().await;
+    let types = match types {
+        Ok(output) if output.status.success() => output.stdout,
+        // wl-paste fails when nothing is copied
+        _ => return None,
+    };
+    let types = String::from_utf8_lossy(&types);
+    let mime = types.lines().next()?.trim().to_string();
+
+    let output = Command::new("wl-paste")
+        .args(["--no-newline", "--type", &mime])
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    debug!(
+        "clipboard snapshot mime={mime} bytes={}",
+        output.stdout.len()
+    );
+    Some((mime, output.stdout))
+}
+
+/// Text on the clipboard or primary selection, or `None` when it's empty,
+/// not text, or marked as a secret by a password manager.
+pub(crate) async fn read_selection(primary: bool) -> Option<String> {
+    let wl_paste = |args: &[&str]| {
+        let mut cmd = Command::new("wl-paste");
+        if primary {
+            cmd.arg("--primary");
+        }
+        cmd.args(args);
+        cmd
+    };
+    let types = wl_paste(&["--list-types"]).output().await.ok()?;
+    let types = String::from_utf8_lossy(&types.stdout);
+    // KeePassXC and KDE Wallet tag copied passwords with this
+    if types.lines().any(|t| t == "x-kde-passwordManagerHint") {
+        debug!("selection is a password, skipping");
+        return None;
+    }
+    if !types.lines().any(|t| t.starts_with("text/plain")) {
+        return None;
+    }
+
+    let output = wl_paste(&["--no-newline", "--type", "text/plain"])
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+async fn restore_clipboard(previous: ClipboardSnapshot) {
+    let Some((mime, data)) = previous else {
+        let _ = Command::new("wl-copy").arg("--clear").status().await;
+        return;
+    };
+
+    let child = Command::new("wl-copy")
+        .args(["--type", &mime])
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            error!("wl-copy failed: {e}");
+            return;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take()
+        && let Err(e) = stdin.write_all(&data).await
+    {
+        error!("Failed to restore clipboard: {e}");
+    }
+    let _ = child.wait().await;
+}
+
+/// Type through ydotoold's uinput device, for compositors without the
+/// virtual-keyboard protocol (e.g. GNOME). ydotool finds the daemon's socket
+/// via `YDOTOOL_SOCKET`.
+async fn inject_via_ydotool(text: &str, config: &InjectConfig) -> bool {
+    let delay_ms = config.delay_ms(InjectMode::Ydotool);
+    let key_delay_ms = config.key_delay_ms;
+    debug!(
+        "injector=ydotool delay_ms={delay_ms} key_delay_ms={key_delay_ms} text_len={}",
+        text.len()
+    );
+
+    if delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    }
+
+    let status = Command::new("ydotool")
+        .args(["type", "--key-delay", &key_delay_ms.to_string()])
+        .arg("--")
+        .arg(text)
         .status()
         .await;
-}
 
-fn wtype_delay_ms(mode: &str) -> u64 {
-    std::env::var("VOICE_WTYPE_DELAY_MS")
-        .ok()
-        .and_then(|value| value.parse::<u64>().ok())
-        .unwrap_or_else(|| if mode == "clipboard" { 50 } else { 100 })
+    match status {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            error!("ydotool exited with {status} (is ydotoold running?)");
+            notify("Injection failed").await;
+            false
+        }
+        Err(e) => {
+            error!("ydotool failed: {e}");
+            notify("Injection failed").await;
+            false
+        }
+    }
 }
 
-fn wtype_key_delay_ms() -> u64 {
-    std::env::var("VOICE_WTYPE_KEY_DELAY_MS")
-        .ok()
-        .and_then(|value| value.parse::<u64>().ok())
-        .unwrap_or(5)
+async fn inject_via_virtual_keyboard(text: &str, config: &InjectConfig) -> bool {
+    let delay_ms = config.delay_ms(InjectMode::Wayland);
+    let key_delay_ms = config.key_delay_ms;
+    debug!(
+        "injector=wayland delay_ms={delay_ms} key_delay_ms={key_delay_ms} text_len={}",
+        text.len()
+    );
+
+    if delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    }
+
+    let text = text.to_string();
+    let result = tokio::task::spawn_blocking(move || {
+        virtual_keyboard::type_text(&text, Duration::from_millis(key_delay_ms))
+    })
+    .await;
+
+    match result {
+        Ok(Ok(())) => true,
+        Ok(Err(e)) => {
+            error!("virtual keyboard failed: {e}");
+            notify(&format!("Injection failed: {e}")).await;
+            false
+        }
+        Err(e) => {
+            error!("virtual keyboard task failed: {e}");
+            notify("Injection failed").await;
+            false
+        }
+    }
 }
 
-fn injection_mode() -> String {
-    std::env::var("VOICE_INJECT_MODE")
-        .ok()
-        .unwrap_or_else(|| "clipboard".to_string())
+/// Type through the RemoteDesktop portal, which GNOME and KDE implement
+/// without exposing wlroots protocols. Asks for permission once.
+async fn inject_via_portal(text: &str, config: &InjectConfig) -> bool {
+    let delay_ms = config.delay_ms(InjectMode::Portal);
+    let key_delay_ms = config.key_delay_ms;
+    debug!(
+        "injector=portal delay_ms={delay_ms} key_delay_ms={key_delay_ms} text_len={}",
+        text.len()
+    );
+
+    if delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    }
+
+    if let Err(e) = portal::type_text(text, Duration::from_millis(key_delay_ms)).await {
+        error!("portal injection failed: {e}");
+        notify(&format!("Injection failed: {e}")).await;
+        return false;
+    }
+    true
 }
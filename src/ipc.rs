@@ -1,26 +1,310 @@
-use crate::daemon::Daemon;
+use crate::config::{config_path, runtime_dir};
+use crate::daemon::{Daemon, Event, Refusal, Status};
+use crate::error::WayvoiceError;
+use crate::systemd;
+use serde::{Deserialize, Serialize};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
 use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{SignalKind, signal};
 use tokio::sync::Mutex;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
 
-fn socket_path() -> PathBuf {
-    std::env::var("XDG_RUNTIME_DIR")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| PathBuf::from("/tmp"))
-        .join("wayvoice.sock")
+/// Bumped on incompatible changes to `Request` or `Response`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// One newline-delimited JSON object per request.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+    pub command: String,
+    #[serde(default)]
+    pub args: Args,
 }
 
-pub async fn run_server(daemon: Arc<Mutex<Daemon>>) -> Result<(), Box<dyn std::error::Error>> {
-    let path = socket_path();
-    let _ = tokio::fs::remove_file(&path).await;
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Args {
+    /// Transcription language for the recording started by this request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Profile from `[profiles.<name>]` for the recording started by this request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// `false` keeps the text out of the focused window; it still goes to
+    /// history and `subscribe` clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inject: Option<bool>,
+    /// Format the text as a git commit message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<bool>,
+    /// Write the text to this file instead of the configured sinks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    /// For `stop` and `toggle`: answer once the recording is transcribed,
+    /// with its text, instead of right away.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wait: Option<bool>,
+}
+
+impl Args {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.language.is_none()
+            && self.profile.is_none()
+            && self.inject.is_none()
+            && self.commit.is_none()
+            && self.output.is_none()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub version: u32,
+    /// Short outcome of the command, e.g. the state after `toggle`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<Event>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorBody {
+    /// Stable machine-readable code: `bad_request`, `unsupported_version`,
+    /// `unknown_command`, `reload_failed`, `nothing_to_replay`,
+    /// `nothing_to_undo`, `nothing_to_retry`, `nothing_queued`, `busy`,
+    /// `not_recording`, `recording_failed`, `no_audio`,
+    /// `transcription_failed` or `over_budget`.
+    pub code: String,
+    pub message: String,
+}
+
+impl ErrorBody {
+    /// Exit code for the CLI, matching `WayvoiceError::exit_code` where the
+    /// kinds overlap.
+    pub fn exit_code(&self) -> i32 {
+        match self.code.as_str() {
+            "reload_failed" => 3,
+            "recording_failed" => 4,
+            "transcription_failed" => 5,
+            "busy" => 8,
+            "not_recording" => 9,
+            "no_audio" => 10,
+            "over_budget" => 11,
+            _ => 1,
+        }
+    }
+}
+
+impl Response {
+    fn empty() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            result: None,
+            status: None,
+            event: None,
+            error: None,
+        }
+    }
+
+    fn result(result: &str) -> Self {
+        Self {
+            result: Some(result.to_string()),
+            ..Self::empty()
+        }
+    }
+
+    fn status(status: Status) -> Self {
+        Self {
+            status: Some(status),
+            ..Self::empty()
+        }
+    }
+
+    fn event(event: Event) -> Self {
+        Self {
+            event: Some(event),
+            ..Self::empty()
+        }
+    }
+
+    fn refused(refusal: Refusal) -> Self {
+        Self::error(refusal.code(), refusal.to_string())
+    }
+
+    fn error(code: &str, message: String) -> Self {
+        Self {
+            error: Some(ErrorBody {
+                code: code.to_string(),
+                message,
+            }),
+            ..Self::empty()
+        }
+    }
+}
+
+static SOCKET_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// `wayvoice.lock` next to the socket, held for the daemon's lifetime.
+static DAEMON_LOCK: OnceLock<std::fs::File> = OnceLock::new();
+
+/// Whether the listener came from systemd rather than our own bind.
+static SOCKET_ACTIVATED: AtomicBool = AtomicBool::new(false);
+
+/// How long `serve --replace` waits for the old daemon to exit.
+const QUIT_POLLS: u32 = 50;
+const QUIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long `spawn_daemon` waits for the new daemon to answer.
+const SPAWN_WAIT: Duration = Duration::from_secs(5);
+
+/// Use `path` instead of the default socket (the `--socket` flag).
+pub fn set_socket_path(path: PathBuf) {
+    let _ = SOCKET_PATH.set(path);
+}
+
+fn socket_path() -> Result<PathBuf, WayvoiceError> {
+    if let Some(path) = SOCKET_PATH.get() {
+        return Ok(path.clone());
+    }
+    if let Ok(path) = std::env::var("WAYVOICE_SOCKET")
+        && !path.is_empty()
+    {
+        return Ok(PathBuf::from(path));
+    }
+    Ok(runtime_dir()?.join("wayvoice.sock"))
+}
+
+/// Bind the daemon socket. A daemon already answering on it is an error,
+/// unless `replace` is set, in which case it is asked to quit first.
+pub async fn bind(replace: bool) -> Result<UnixListener, WayvoiceError> {
+    if let Some(listener) = systemd::activated_listener().map_err(WayvoiceError::ipc)? {
+        SOCKET_ACTIVATED.store(true, Ordering::Relaxed);
+        println!("Listening on the socket from systemd");
+        return UnixListener::from_std(listener).map_err(WayvoiceError::ipc);
+    }
+
+    let path = socket_path()?;
+    // Whoever holds the lock owns the socket, so two daemons started
+    // together can't delete each other's
+    let lock_path = path.with_extension("lock");
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .mode(0o600)
+        .open(&lock_path)
+        .map_err(|e| WayvoiceError::Ipc(format!("Failed to open {lock_path:?}: {e}")))?;
+    if !try_lock(&lock)? {
+        if !replace {
+            return Err(WayvoiceError::Ipc(format!(
+                "A daemon is already running on {path:?}; use `serve --replace` to take over"
+            )));
+        }
+        send_command("quit", Args::default()).await?;
+        let mut quit = false;
+        for _ in 0..QUIT_POLLS {
+            tokio::time::sleep(QUIT_POLL_INTERVAL).await;
+            if try_lock(&lock)? {
+                quit = true;
+                break;
+            }
+        }
+        if !quit {
+            return Err(WayvoiceError::Ipc(format!(
+                "The daemon on {path:?} did not quit"
+            )));
+        }
+    }
+    // Held until the process exits
+    let _ = DAEMON_LOCK.set(lock);
 
-    let listener = UnixListener::bind(&path)?;
+    // A stale socket from a daemon that didn't exit cleanly
+    let _ = tokio::fs::remove_file(&path).await;
+    // Created 0600 rather than restricted after the fact
+    // SAFETY: umask has no preconditions; nothing else creates files while
+    // the daemon is binding
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(&path);
+    // SAFETY: as above
+    unsafe { libc::umask(umask) };
+    let listener =
+        listener.map_err(|e| WayvoiceError::Ipc(format!("Failed to bind {path:?}: {e}")))?;
     println!("Listening on {path:?}");
+    Ok(listener)
+}
+
+/// Take the daemon lock without waiting; false if another daemon has it.
+fn try_lock(lock: &std::fs::File) -> Result<bool, WayvoiceError> {
+    // SAFETY: flock on an fd we own
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let e = std::io::Error::last_os_error();
+    if e.kind() == std::io::ErrorKind::WouldBlock {
+        return Ok(false);
+    }
+    Err(WayvoiceError::Ipc(format!("Failed to lock: {e}")))
+}
 
+/// Serve until SIGINT or SIGTERM, then shut down cleanly.
+pub async fn run_server_until_signal(
+    listener: UnixListener,
+    daemon: Arc<Mutex<Daemon>>,
+) -> Result<(), WayvoiceError> {
+    let mut terminate = signal(SignalKind::terminate()).map_err(WayvoiceError::ipc)?;
+    systemd::notify("READY=1");
+    tokio::select! {
+        result = run_server(listener, daemon.clone()) => result,
+        _ = tokio::signal::ctrl_c() => shut_down(&daemon).await,
+        _ = terminate.recv() => shut_down(&daemon).await,
+    }
+}
+
+/// Stop recording, drop pending work and the socket, and exit.
+async fn shut_down(daemon: &Mutex<Daemon>) -> ! {
+    systemd::notify("STOPPING=1");
+    daemon.lock().await.shutdown().await;
+    // systemd keeps listening on an activated socket to start us again
+    if !SOCKET_ACTIVATED.load(Ordering::Relaxed)
+        && let Ok(path) = socket_path()
+    {
+        let _ = std::fs::remove_file(path);
+    }
+    std::process::exit(0);
+}
+
+pub async fn run_server(
+    listener: UnixListener,
+    daemon: Arc<Mutex<Daemon>>,
+) -> Result<(), WayvoiceError> {
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, _) = listener.accept().await.map_err(WayvoiceError::ipc)?;
+        // Only the user running the daemon may control the microphone
+        // SAFETY: getuid has no preconditions and cannot fail
+        let uid = unsafe { libc::getuid() };
+        match stream.peer_cred() {
+            Ok(cred) if cred.uid() == uid => {}
+            Ok(cred) => {
+                warn!("Rejected connection from uid {}", cred.uid());
+                continue;
+            }
+            Err(e) => {
+                warn!("Rejected connection without credentials: {e}");
+                continue;
+            }
+        }
         let daemon = daemon.clone();
         tokio::spawn(handle_client(stream, daemon));
     }
@@ -31,38 +315,237 @@ async fn handle_client(stream: UnixStream, daemon: Arc<Mutex<Daemon>>) {
     let mut reader = BufReader::new(reader);
     let mut line = String::new();
 
-    if reader.read_line(&mut line).await.is_ok() {
-        let response = match line.trim() {
-            "toggle" => {
-                let mut d = daemon.lock().await;
-                d.toggle().await.to_string()
-            }
-            "cancel" => {
-                let mut d = daemon.lock().await;
-                d.cancel().await.to_string()
-            }
-            "status" => {
-                let d = daemon.lock().await;
-                d.status().to_string()
+    if reader.read_line(&mut line).await.is_err() {
+        return;
+    }
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) if request.version == PROTOCOL_VERSION && request.command == "subscribe" => {
+            stream_events(&mut writer, &daemon).await;
+            return;
+        }
+        Ok(request) if request.version == PROTOCOL_VERSION && request.command == "quit" => {
+            let _ = write_response(&mut writer, &Response::result("quitting")).await;
+            shut_down(&daemon).await;
+        }
+        Ok(request) => handle_request(request, &daemon).await,
+        Err(e) => Response::error("bad_request", format!("Invalid request: {e}")),
+    };
+    let _ = write_response(&mut writer, &response).await;
+}
+
+async fn write_response(writer: &mut OwnedWriteHalf, response: &Response) -> std::io::Result<()> {
+    let mut payload = serde_json::to_vec(response)?;
+    payload.push(b'\n');
+    writer.write_all(&payload).await
+}
+
+/// Keep the connection open and send one response per event, starting with
+/// the current state, until the client goes away.
+async fn stream_events(writer: &mut OwnedWriteHalf, daemon: &Mutex<Daemon>) {
+    let (current, mut events) = daemon.lock().await.subscribe();
+    if write_response(writer, &Response::event(current))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                debug!("subscriber lagged, missed {missed} events");
+                continue;
             }
-            _ => "unknown".to_string(),
+            Err(RecvError::Closed) => return,
         };
+        if write_response(writer, &Response::event(event))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
 
-        let _ = writer.write_all(response.as_bytes()).await;
-        let _ = writer.write_all(b"\n").await;
+async fn handle_request(request: Request, daemon: &Mutex<Daemon>) -> Response {
+    if request.version != PROTOCOL_VERSION {
+        return Response::error(
+            "unsupported_version",
+            format!(
+                "Protocol version {} is not supported (daemon speaks {PROTOCOL_VERSION})",
+                request.version
+            ),
+        );
+    }
+
+    let mut args = request.args;
+    let wait = args.wait.take().unwrap_or(false);
+    match request.command.as_str() {
+        command @ ("toggle" | "start" | "stop") => {
+            let mut daemon = daemon.lock().await;
+            let result = match command {
+                "toggle" => daemon.toggle(args).await,
+                "start" => daemon.start(args).await,
+                _ => daemon.stop().await,
+            };
+            match result {
+                // Only a recording that was just stopped leaves it transcribing
+                Ok("transcribing") if wait => {
+                    let transcribed = daemon.transcribed();
+                    drop(daemon);
+                    match transcribed.await {
+                        Ok(text) => Response::result(&text),
+                        Err(refusal) => Response::refused(refusal),
+                    }
+                }
+                Ok(result) => Response::result(result),
+                Err(refusal) => Response::refused(refusal),
+            }
+        }
+        "cancel" => match daemon.lock().await.cancel().await {
+            Ok(result) => Response::result(result),
+            Err(refusal) => Response::refused(refusal),
+        },
+        "lang" => Response::result(&daemon.lock().await.set_language(args.language)),
+        "status" => Response::status(daemon.lock().await.status()),
+        "metrics" => Response::result(daemon.lock().await.metrics().trim_end()),
+        "reload" => match daemon.lock().await.reload() {
+            Ok(result) => Response::result(result),
+            Err(e) => Response::error("reload_failed", e.to_string()),
+        },
+        "replay" => match daemon.lock().await.replay() {
+            Ok(result) => Response::result(result),
+            Err(e) => Response::error("nothing_to_replay", e),
+        },
+        "undo" => match daemon.lock().await.undo() {
+            Ok(result) => Response::result(result),
+            Err(e) => Response::error("nothing_to_undo", e),
+        },
+        "retry" => match daemon.lock().await.retry().await {
+            Ok(result) => Response::result(result),
+            Err(e) => Response::error("nothing_to_retry", e),
+        },
+        "flush" => match daemon.lock().await.flush() {
+            Ok(result) => Response::result(result),
+            Err(e) => Response::error("nothing_queued", e),
+        },
+        other => Response::error("unknown_command", format!("Unknown command: {other}")),
     }
 }
 
-pub async fn send_command(cmd: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let path = socket_path();
-    let mut stream = UnixStream::connect(&path).await?;
+/// Whether a daemon answers on the socket.
+pub async fn daemon_running() -> bool {
+    let Ok(path) = socket_path() else {
+        return false;
+    };
+    UnixStream::connect(path).await.is_ok()
+}
 
-    stream.write_all(cmd.as_bytes()).await?;
-    stream.write_all(b"\n").await?;
+/// Start `wayvoice serve` in the background, for `autostart_daemon`, and
+/// wait until it answers on the socket. Its stderr goes to
+/// `daemon.log` in the state directory, so a daemon that exits right away
+/// can say why.
+pub async fn spawn_daemon() -> Result<(), WayvoiceError> {
+    let exe = std::env::current_exe().map_err(WayvoiceError::ipc)?;
+    let path = socket_path()?;
+    let log = daemon_log();
+    let stderr = log
+        .as_ref()
+        .and_then(|log| {
+            std::fs::create_dir_all(log.parent()?).ok()?;
+            std::fs::File::create(log).ok()
+        })
+        .map_or_else(Stdio::null, Stdio::from);
+    let mut child = std::process::Command::new(exe)
+        .arg("--config")
+        .arg(config_path())
+        .arg("--socket")
+        .arg(&path)
+        .arg("serve")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(stderr)
+        // Not killed along with the terminal or keybind that started it
+        .process_group(0)
+        .spawn()
+        .map_err(|e| WayvoiceError::Ipc(format!("Failed to start the daemon: {e}")))?;
 
+    let deadline = Instant::now() + SPAWN_WAIT;
+    while UnixStream::connect(&path).await.is_err() {
+        if let Ok(Some(status)) = child.try_wait() {
+            let reason = log
+                .and_then(|log| std::fs::read_to_string(log).ok())
+                .map(|output| output.trim().to_string())
+                .filter(|output| !output.is_empty())
+                .unwrap_or_else(|| status.to_string());
+            return Err(WayvoiceError::Ipc(format!(
+                "The daemon exited at startup: {reason}"
+            )));
+        }
+        if Instant::now() >= deadline {
+            let mut message = "The daemon didn't start in time".to_string();
+            if let Some(log) = log {
+                message.push_str(&format!("; see {}", log.display()));
+            }
+            return Err(WayvoiceError::Ipc(message));
+        }
+        tokio::time::sleep(QUIT_POLL_INTERVAL).await;
+    }
+    Ok(())
+}
+
+/// Where an autostarted daemon's stderr goes.
+fn daemon_log() -> Option<PathBuf> {
+    Some(dirs::state_dir()?.join("wayvoice").join("daemon.log"))
+}
+
+/// Connect to the daemon and send a single request.
+async fn send_request(command: &str, args: Args) -> Result<UnixStream, WayvoiceError> {
+    let path = socket_path()?;
+    let mut stream = UnixStream::connect(&path)
+        .await
+        .map_err(|e| WayvoiceError::Ipc(format!("{e} (is daemon running?)")))?;
+
+    let request = Request {
+        version: PROTOCOL_VERSION,
+        command: command.to_string(),
+        args,
+    };
+    let mut payload = serde_json::to_vec(&request).map_err(WayvoiceError::ipc)?;
+    payload.push(b'\n');
+    stream
+        .write_all(&payload)
+        .await
+        .map_err(WayvoiceError::ipc)?;
+    Ok(stream)
+}
+
+pub async fn send_command(command: &str, args: Args) -> Result<Response, WayvoiceError> {
+    let stream = send_request(command, args).await?;
     let mut reader = BufReader::new(stream);
     let mut response = String::new();
-    reader.read_line(&mut response).await?;
+    reader
+        .read_line(&mut response)
+        .await
+        .map_err(WayvoiceError::ipc)?;
 
-    Ok(response.trim().to_string())
+    serde_json::from_str(&response).map_err(WayvoiceError::ipc)
+}
+
+/// Subscribe to daemon events, calling `on_event` for each until the daemon
+/// closes the connection.
+pub async fn subscribe(mut on_event: impl FnMut(Event)) -> Result<(), WayvoiceError> {
+    let stream = send_request("subscribe", Args::default()).await?;
+    let mut lines = BufReader::new(stream).lines();
+    while let Some(line) = lines.next_line().await.map_err(WayvoiceError::ipc)? {
+        let response: Response = serde_json::from_str(&line).map_err(WayvoiceError::ipc)?;
+        if let Some(error) = response.error {
+            return Err(WayvoiceError::Ipc(error.message));
+        }
+        if let Some(event) = response.event {
+            on_event(event);
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,52 @@
+//! The wayvoice pipeline as a library: record audio, transcribe it, clean up
+//! the text and type it into the focused window.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), wayvoice::error::WayvoiceError> {
+//! let config = wayvoice::config::load_config();
+//! let mut recorder = wayvoice::recorder::Recorder::new(&config);
+//! recorder.start().await?;
+//! // ...
+//! let audio = recorder.stop().await;
+//! let transcript = wayvoice::transcription::transcribe_audio(audio, &config).await?;
+//! let rules = config.replacements_for(transcript.language.as_deref());
+//! let text = wayvoice::text::apply_replacements(&transcript.text, rules);
+//! wayvoice::inject::inject_text(&text, &config.inject).await;
+//! # Ok(())
+//! # }
+//! ```
+
+mod archive;
+pub mod audio;
+pub mod commands;
+pub mod config;
+mod confirm;
+pub mod daemon;
+pub mod error;
+mod focus;
+pub mod history;
+mod hooks;
+mod http;
+pub mod inject;
+pub mod ipc;
+mod lock;
+pub mod logging;
+mod media;
+pub mod meeting;
+pub mod metrics;
+pub mod notify;
+pub mod offline;
+pub mod oneshot;
+pub mod osd;
+mod output;
+mod portal;
+pub mod postprocess;
+pub mod recorder;
+mod sound;
+pub mod stats;
+mod streaming;
+pub mod systemd;
+pub mod text;
+pub mod transcription;
+mod virtual_keyboard;
+pub mod waybar;
@@ -0,0 +1,65 @@
+use std::os::unix::fs::MetadataExt;
+use std::time::Duration;
+use tracing::debug;
+use zbus::zvariant::OwnedValue;
+
+/// Screen lockers that don't set logind's `LockedHint`, found by process
+/// name while they run.
+const LOCKERS: &[&str] = &["swaylock", "hyprlock", "gtklock", "waylock"];
+
+/// logind shouldn't hold up typing for long.
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+static CONNECTION: tokio::sync::OnceCell<zbus::Connection> = tokio::sync::OnceCell::const_new();
+
+/// Whether the session is locked, going by logind's `LockedHint` or a
+/// running screen locker. Unknown counts as unlocked.
+pub(crate) async fn is_locked() -> bool {
+    if locker_running() {
+        return true;
+    }
+    match tokio::time::timeout(TIMEOUT, locked_hint()).await {
+        Ok(Ok(locked)) => locked,
+        Ok(Err(e)) => {
+            debug!("LockedHint: {e}");
+            false
+        }
+        Err(_) => {
+            debug!("LockedHint: timed out");
+            false
+        }
+    }
+}
+
+/// `session/auto` is the caller's session, or for a systemd user service
+/// outside any session, the user's graphical one.
+async fn locked_hint() -> zbus::Result<bool> {
+    let connection = CONNECTION.get_or_try_init(zbus::Connection::system).await?;
+    let reply = connection
+        .call_method(
+            Some("org.freedesktop.login1"),
+            "/org/freedesktop/login1/session/auto",
+            Some("org.freedesktop.DBus.Properties"),
+            "Get",
+            &("org.freedesktop.login1.Session", "LockedHint"),
+        )
+        .await?;
+    let value: OwnedValue = reply.body().deserialize()?;
+    Ok(bool::try_from(value).unwrap_or(false))
+}
+
+/// One of `LOCKERS` running as us; another user's locker doesn't lock our
+/// session.
+fn locker_running() -> bool {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return false;
+    };
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    entries.filter_map(Result::ok).any(|entry| {
+        let path = entry.path();
+        std::fs::metadata(&path).is_ok_and(|m| m.uid() == uid)
+            && std::fs::read_to_string(path.join("comm"))
+                .is_ok_and(|comm| LOCKERS.contains(&comm.trim_end()))
+    })
+}
@@ -0,0 +1,114 @@
+use crate::config::{Config, resolve_path};
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::writer::MakeWriter;
+use tracing_subscriber::prelude::*;
+
+/// Where log lines are copied to once a daemon config sets `log_file`.
+static LOG_FILE: Mutex<Option<RotatingFile>> = Mutex::new(None);
+
+/// Log to stderr, filtered by `RUST_LOG` (default `warn`). Timing spans are
+/// reported when they close, so `RUST_LOG=wayvoice=debug` shows how long
+/// recording, uploading, transcribing and injecting took.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"));
+    let file = tracing_subscriber::fmt::layer()
+        .with_writer(LogFile)
+        .with_ansi(false)
+        .with_span_events(FmtSpan::CLOSE);
+    let stderr = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_span_events(FmtSpan::CLOSE);
+    // Span fields are formatted once and shared, so the file layer goes
+    // first to keep color codes out of the file
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(file)
+        .with(stderr)
+        .init();
+}
+
+/// Also write to `config.log_file`, or stop when it's empty.
+pub fn configure(config: &Config) {
+    let file = if config.log_file.is_empty() {
+        None
+    } else {
+        let path = resolve_path(&config.log_file);
+        let max_bytes = config.log_max_mb * 1024 * 1024;
+        match RotatingFile::open(path.clone(), max_bytes) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                tracing::error!("Failed to open log file {path:?}: {e}");
+                None
+            }
+        }
+    };
+    *LOG_FILE.lock().unwrap() = file;
+}
+
+/// A log file that is moved to `<path>.1` once it reaches `max_bytes`.
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    file: File,
+    len: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            file,
+            len,
+        })
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        std::fs::rename(&self.path, rotated)?;
+        *self = Self::open(self.path.clone(), self.max_bytes)?;
+        Ok(())
+    }
+}
+
+/// `MakeWriter` for the file layer; writes nothing while no file is set.
+struct LogFile;
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut guard = LOG_FILE.lock().unwrap();
+        let Some(file) = guard.as_mut() else {
+            return Ok(buf.len());
+        };
+        if file.max_bytes > 0 && file.len > 0 && file.len + buf.len() as u64 > file.max_bytes {
+            file.rotate()?;
+        }
+        file.file.write_all(buf)?;
+        file.len += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = LogFile;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogFile
+    }
+}
@@ -1,21 +1,28 @@
-mod config;
-mod daemon;
-mod inject;
-mod ipc;
-mod oneshot;
-mod text;
-mod transcription;
-
 use clap::{Parser, Subcommand};
-use daemon::Daemon;
-use ipc::{run_server, send_command};
-use oneshot::run_once;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{Mutex, mpsc};
+use wayvoice::daemon::{Daemon, Status};
+use wayvoice::error::WayvoiceError;
+use wayvoice::ipc::{self, Args, run_server_until_signal, send_command, subscribe};
+use wayvoice::metrics::serve_http;
+use wayvoice::oneshot::{
+    StopAt, bench, list_models, record, retranscribe, run_once, transcribe_file,
+};
+use wayvoice::osd::run_osd;
+use wayvoice::waybar::run_waybar;
+use wayvoice::{config, history, logging, meeting, offline, stats, systemd};
 
 #[derive(Parser)]
 #[command(name = "wayvoice", about = "Voice-to-text for Wayland")]
 struct Cli {
+    /// Config file to use instead of ~/.config/wayvoice.toml [env: WAYVOICE_CONFIG]
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Daemon socket to serve or connect to [env: WAYVOICE_SOCKET]
+    #[arg(long, global = true, value_name = "PATH")]
+    socket: Option<PathBuf>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -23,62 +30,559 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Run the daemon
-    Serve,
+    Serve {
+        /// Ask an already running daemon to quit and take over its socket
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Stop the running daemon
+    Quit,
+    /// Install and enable a systemd user service for the daemon
+    InstallService {
+        /// Start the daemon at login instead of on the first command
+        #[arg(long)]
+        no_socket: bool,
+    },
+    /// Disable and remove the systemd user service
+    UninstallService,
     /// Toggle recording on/off
-    Toggle,
+    Toggle {
+        #[command(flatten)]
+        overrides: Overrides,
+        /// When stopping, wait for the transcription and print its text
+        #[arg(long)]
+        wait: bool,
+    },
+    /// Start recording (e.g. on key press for push-to-talk)
+    Start {
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+    /// Stop recording and transcribe (e.g. on key release)
+    Stop {
+        /// Wait for the transcription and print its text
+        #[arg(long)]
+        wait: bool,
+    },
     /// Cancel current operation
     Cancel,
     /// Get current status
-    Status,
+    Status {
+        /// Print state, provider, language, profile, last error and last transcription time as JSON
+        #[arg(long)]
+        json: bool,
+        /// Keep running and print the status again on every change
+        #[arg(long)]
+        follow: bool,
+    },
+    /// Switch the running daemon's transcription language, or print it
+    Lang {
+        /// Language code, or "auto" to detect it
+        code: Option<String>,
+        /// Go back to the configured `language`
+        #[arg(long, conflicts_with = "code")]
+        reset: bool,
+    },
+    /// Print daemon metrics in Prometheus text format
+    Metrics,
+    /// Re-read wayvoice.toml in the running daemon
+    Reload,
+    /// Inject the last transcription again
+    Replay,
+    /// Erase the last injected text with backspaces
+    Undo,
+    /// Transcribe the last recording again (e.g. after a provider error)
+    Retry,
+    /// Recordings kept while the provider couldn't be reached
+    Queue {
+        #[command(subcommand)]
+        command: QueueCommand,
+    },
+    /// Print state changes and transcribed text as JSON lines
+    Subscribe,
+    /// Print Waybar custom-module JSON on every state change
+    Waybar,
+    /// Show an on-screen indicator while recording (wlr-layer-shell)
+    Osd,
+    /// Create or check wayvoice.toml
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Show dictation counts, words, audio time and latency per provider
+    Stats,
+    /// Browse past transcriptions
+    History {
+        #[command(subcommand)]
+        command: HistoryCommand,
+    },
     /// One-shot: record until Enter, transcribe, print to stdout
-    Once,
+    Once {
+        /// Provider to use instead of the configured one (groq, openai, deepgram, whisper-cpp, vosk)
+        #[arg(long)]
+        provider: Option<String>,
+        /// Profile from [profiles.<name>] to use
+        #[arg(long)]
+        profile: Option<String>,
+        /// Turn the dictation into a conventional commit message
+        #[arg(long)]
+        commit: bool,
+        /// Write the text to this file instead of stdout
+        #[arg(long, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// Copy the text to the clipboard instead of printing it
+        #[arg(long)]
+        copy: bool,
+        /// Type the text into the focused window instead of printing it
+        #[arg(long)]
+        inject: bool,
+        /// Stop after this many seconds (default: max_recording_secs)
+        #[arg(long, value_name = "SECS")]
+        max_seconds: Option<u64>,
+        /// Stop after this many seconds of silence once you've spoken
+        #[arg(long, value_name = "SECS", num_args = 0..=1, default_missing_value = "2")]
+        stop_on_silence: Option<f64>,
+    },
+    /// Record until Enter and save the audio without transcribing it
+    Record {
+        /// Where to save it (.wav, or .ogg/.opus for Opus; - for stdout);
+        /// default: a new file under ~/.local/share/wayvoice/recordings
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// Stop after this many seconds
+        #[arg(long, value_name = "SECS")]
+        duration: Option<u64>,
+    },
+    /// Record a call until Enter, transcribing it in chunks to a file
+    Meeting {
+        /// What to record: mic, system (what's playing) or both
+        #[arg(long, value_parser = ["mic", "system", "both"])]
+        source: Option<String>,
+        /// Write the transcript here instead of a new file under [meeting] dir
+        #[arg(long, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// Provider to use instead of the configured one (groq, openai, deepgram, whisper-cpp, vosk)
+        #[arg(long)]
+        provider: Option<String>,
+        /// Profile from [profiles.<name>] to use
+        #[arg(long)]
+        profile: Option<String>,
+    },
+    /// Send an audio file to each set-up provider and compare latency and text
+    Bench {
+        file: PathBuf,
+        /// Provider to include (repeatable); default: every one with a key or model
+        #[arg(long)]
+        provider: Vec<String>,
+        /// Extra model to try on the configured provider (repeatable)
+        #[arg(long)]
+        model: Vec<String>,
+    },
+    /// List the transcription models the provider offers, for `model`
+    Models {
+        /// Provider to ask instead of the configured one (groq, openai, deepgram)
+        #[arg(long)]
+        provider: Option<String>,
+    },
+    /// Transcribe an audio file (wav, mp3, ogg, ...; - for stdin) and print the text
+    Transcribe {
+        file: PathBuf,
+        /// Type the text into the focused window instead of printing it
+        #[arg(long)]
+        inject: bool,
+        /// Print subtitles or JSON with segment timestamps instead of text
+        #[arg(long, value_parser = ["srt", "vtt", "json"], conflicts_with = "inject")]
+        format: Option<String>,
+        /// Label who said what (needs provider deepgram)
+        #[arg(long)]
+        diarize: bool,
+        /// Provider to use instead of the configured one (groq, openai, deepgram, whisper-cpp, vosk)
+        #[arg(long)]
+        provider: Option<String>,
+        /// Profile from [profiles.<name>] to use
+        #[arg(long)]
+        profile: Option<String>,
+    },
+}
+
+/// Options for the recording started by `toggle` / `start`.
+#[derive(clap::Args)]
+struct Overrides {
+    /// Transcription language for this recording (e.g. "de")
+    #[arg(long)]
+    language: Option<String>,
+    /// Profile from [profiles.<name>] to use for this recording
+    #[arg(long)]
+    profile: Option<String>,
+    /// Don't type the text; it still goes to history and subscribers
+    #[arg(long)]
+    no_inject: bool,
+    /// Turn the dictation into a conventional commit message
+    #[arg(long)]
+    commit: bool,
+    /// Write the text to this file instead of typing it
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+impl From<Overrides> for Args {
+    fn from(overrides: Overrides) -> Self {
+        Args {
+            language: overrides.language,
+            profile: overrides.profile,
+            inject: overrides.no_inject.then_some(false),
+            commit: overrides.commit.then_some(true),
+            // The daemon runs elsewhere, so relative paths are resolved here
+            output: overrides
+                .output
+                .map(|path| std::path::absolute(&path).unwrap_or(path)),
+            wait: None,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Write a commented default config file
+    Init {
+        /// Overwrite an existing config
+        #[arg(long)]
+        force: bool,
+    },
+    /// Parse the config file and report unknown keys and invalid values
+    Validate,
+}
+
+#[derive(Subcommand)]
+enum QueueCommand {
+    /// Send queued recordings now instead of waiting for the next retry
+    Flush,
+    /// List queued recordings
+    List,
+}
+
+#[derive(Subcommand)]
+enum HistoryCommand {
+    /// List recent transcriptions, newest first
+    List {
+        /// How many entries to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Show one entry in full (1 = most recent)
+    Show { id: usize },
+    /// Find transcriptions containing a phrase
+    Search { query: String },
+    /// Transcribe an entry's archived audio again and diff the result
+    Retranscribe {
+        id: usize,
+        /// Provider to use instead of the configured one (groq, openai, deepgram, whisper-cpp, vosk)
+        #[arg(long)]
+        provider: Option<String>,
+        /// Model to use instead of the configured one
+        #[arg(long)]
+        model: Option<String>,
+    },
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    logging::init();
     let cli = Cli::parse();
+    if let Some(path) = cli.config.clone() {
+        config::set_config_path(path);
+    }
+    if let Some(path) = cli.socket {
+        ipc::set_socket_path(path);
+    }
 
     match cli.command {
-        Commands::Serve => {
+        Commands::Serve { replace } => {
+            let listener = match ipc::bind(replace).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(e.exit_code());
+                }
+            };
             let daemon = Arc::new(Mutex::new(Daemon::new()));
 
-            let daemon_for_signal = daemon.clone();
+            let port = daemon.lock().await.metrics_port();
+            if port != 0 {
+                tokio::spawn(serve_http(port, daemon.clone()));
+            }
+
+            let daemon_for_tick = daemon.clone();
             tokio::spawn(async move {
-                let _ = tokio::signal::ctrl_c().await;
-                let mut d = daemon_for_signal.lock().await;
-                let _ = d.cancel().await;
-                std::process::exit(0);
+                let mut interval = tokio::time::interval(Duration::from_secs(1));
+                loop {
+                    interval.tick().await;
+                    daemon_for_tick.lock().await.tick().await;
+                }
             });
 
-            if let Err(e) = run_server(daemon).await {
+            if let Err(e) = run_server_until_signal(listener, daemon).await {
                 eprintln!("Server error: {e}");
+                std::process::exit(e.exit_code());
+            }
+        }
+        Commands::Toggle { overrides, wait } => {
+            let args = Args {
+                wait: wait.then_some(true),
+                ..overrides.into()
+            };
+            client_command("toggle", args).await
+        }
+        Commands::Start { overrides } => client_command("start", overrides.into()).await,
+        Commands::Stop { wait } => {
+            let args = Args {
+                wait: wait.then_some(true),
+                ..Args::default()
+            };
+            client_command("stop", args).await
+        }
+        Commands::Cancel => client_command("cancel", Args::default()).await,
+        Commands::Quit => client_command("quit", Args::default()).await,
+        Commands::InstallService { no_socket } => {
+            if let Err(e) = systemd::install(cli.config.as_deref(), no_socket).await {
+                eprintln!("{e}");
                 std::process::exit(1);
             }
         }
-        Commands::Toggle => match send_command("toggle").await {
-            Ok(response) => println!("{response}"),
-            Err(e) => {
-                eprintln!("Failed to connect: {e} (is daemon running?)");
+        Commands::UninstallService => {
+            if let Err(e) = systemd::uninstall().await {
+                eprintln!("{e}");
                 std::process::exit(1);
             }
-        },
-        Commands::Cancel => match send_command("cancel").await {
-            Ok(response) => println!("{response}"),
+        }
+        Commands::Status { json, follow } => print_status(json, follow).await,
+        Commands::Lang {
+            code: None,
+            reset: false,
+        } => match fetch_status().await {
+            Ok(status) => println!("{}", status.language.as_deref().unwrap_or("auto")),
             Err(e) => {
                 eprintln!("Failed to connect: {e}");
-                std::process::exit(1);
+                std::process::exit(e.exit_code());
             }
         },
-        Commands::Status => match send_command("status").await {
-            Ok(response) => println!("{response}"),
-            Err(e) => {
-                eprintln!("Failed to connect: {e}");
+        Commands::Lang { code, .. } => {
+            let args = Args {
+                language: code,
+                ..Args::default()
+            };
+            client_command("lang", args).await
+        }
+        Commands::Metrics => client_command("metrics", Args::default()).await,
+        Commands::Reload => client_command("reload", Args::default()).await,
+        Commands::Replay => client_command("replay", Args::default()).await,
+        Commands::Undo => client_command("undo", Args::default()).await,
+        Commands::Retry => client_command("retry", Args::default()).await,
+        Commands::Queue { command } => match command {
+            QueueCommand::Flush => client_command("flush", Args::default()).await,
+            QueueCommand::List => offline::list(),
+        },
+        Commands::Subscribe => {
+            let printed = subscribe(|event| {
+                if let Ok(line) = serde_json::to_string(&event) {
+                    println!("{line}");
+                }
+            })
+            .await;
+            if let Err(e) = printed {
+                eprintln!("Failed to subscribe: {e}");
+                std::process::exit(e.exit_code());
+            }
+        }
+        Commands::Waybar => run_waybar().await,
+        Commands::Osd => {
+            if let Err(e) = run_osd().await {
+                eprintln!("OSD failed: {e}");
                 std::process::exit(1);
             }
+        }
+        Commands::Config { command } => match command {
+            ConfigCommand::Init { force } => match config::init_config(force) {
+                Ok(path) => println!("Wrote {}", path.display()),
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(e.exit_code());
+                }
+            },
+            ConfigCommand::Validate => match config::validate_config() {
+                Ok(path) => println!("{} is valid", path.display()),
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(e.exit_code());
+                }
+            },
+        },
+        Commands::Stats => stats::show(&config::load_config().cost),
+        Commands::History { command } => match command {
+            HistoryCommand::List { limit } => history::list(limit),
+            HistoryCommand::Show { id } => history::show(id),
+            HistoryCommand::Search { query } => history::search(&query),
+            HistoryCommand::Retranscribe {
+                id,
+                provider,
+                model,
+            } => retranscribe(id, provider.as_deref(), model.as_deref()).await,
         },
-        Commands::Once => {
-            run_once().await;
+        Commands::Once {
+            provider,
+            profile,
+            commit,
+            output,
+            copy,
+            inject,
+            max_seconds,
+            stop_on_silence,
+        } => {
+            let stop = StopAt {
+                max: max_seconds.map(Duration::from_secs),
+                silence: stop_on_silence.and_then(|secs| Duration::try_from_secs_f64(secs).ok()),
+            };
+            run_once(
+                provider.as_deref(),
+                profile.as_deref(),
+                commit,
+                output.as_deref(),
+                copy,
+                inject,
+                stop,
+            )
+            .await;
+        }
+        Commands::Record { output, duration } => record(output.as_deref(), duration).await,
+        Commands::Meeting {
+            source,
+            output,
+            provider,
+            profile,
+        } => {
+            meeting::run(
+                source.as_deref(),
+                output.as_deref(),
+                provider.as_deref(),
+                profile.as_deref(),
+            )
+            .await;
+        }
+        Commands::Bench {
+            file,
+            provider,
+            model,
+        } => bench(&file, &provider, &model).await,
+        Commands::Models { provider } => list_models(provider.as_deref()).await,
+        Commands::Transcribe {
+            file,
+            inject,
+            format,
+            diarize,
+            provider,
+            profile,
+        } => {
+            transcribe_file(
+                &file,
+                inject,
+                format.as_deref(),
+                diarize,
+                provider.as_deref(),
+                profile.as_deref(),
+            )
+            .await;
+        }
+    }
+}
+
+/// Start a daemon if none is running and `autostart_daemon` is set.
+/// Returns whether it started one that answers, so the command is worth
+/// sending again.
+async fn autostart_daemon() -> bool {
+    if ipc::daemon_running().await || !config::load_config().autostart_daemon {
+        return false;
+    }
+    eprintln!("Starting the daemon");
+    match ipc::spawn_daemon().await {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{e}");
+            false
+        }
+    }
+}
+
+/// The daemon's current status.
+async fn fetch_status() -> Result<Status, WayvoiceError> {
+    let response = send_command("status", Args::default()).await?;
+    if let Some(error) = response.error {
+        return Err(WayvoiceError::Ipc(error.message));
+    }
+    response
+        .status
+        .ok_or_else(|| WayvoiceError::Ipc("No status in the response".to_string()))
+}
+
+fn status_line(status: &Status, json: bool) -> String {
+    if json {
+        serde_json::to_string(status).unwrap_or_default()
+    } else {
+        status.to_string()
+    }
+}
+
+/// Print the status once, or with `follow` again after every event from
+/// the daemon (skipping repeats) until it exits.
+async fn print_status(json: bool, follow: bool) {
+    let (events, mut changed) = mpsc::unbounded_channel();
+    let _ = events.send(());
+    let subscribed = async {
+        if follow {
+            subscribe(move |_| {
+                let _ = events.send(());
+            })
+            .await
+        } else {
+            Ok(())
+        }
+    };
+    let printed = async {
+        let mut last = String::new();
+        while changed.recv().await.is_some() {
+            let line = status_line(&fetch_status().await?, json);
+            if line != last {
+                println!("{line}");
+                last = line;
+            }
+        }
+        Ok(())
+    };
+    if let Err(e) = tokio::try_join!(subscribed, printed) {
+        eprintln!("Failed to connect: {e}");
+        std::process::exit(e.exit_code());
+    }
+}
+
+async fn client_command(cmd: &str, args: Args) {
+    let mut sent = send_command(cmd, args.clone()).await;
+    if sent.is_err() && matches!(cmd, "toggle" | "start") && autostart_daemon().await {
+        sent = send_command(cmd, args).await;
+    }
+    match sent {
+        Ok(response) => {
+            if let Some(error) = response.error {
+                eprintln!("{}", error.message);
+                std::process::exit(error.exit_code());
+            }
+            if let Some(status) = response.status {
+                println!("{status}");
+            } else if let Some(result) = response.result {
+                println!("{result}");
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to connect: {e}");
+            std::process::exit(e.exit_code());
         }
     }
 }
@@ -0,0 +1,99 @@
+use std::time::Duration;
+use tracing::{debug, error};
+use zbus::zvariant::OwnedValue;
+
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+
+/// A player that doesn't answer shouldn't hold up the recording.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+static CONNECTION: tokio::sync::OnceCell<zbus::Connection> = tokio::sync::OnceCell::const_new();
+
+async fn connection() -> zbus::Result<&'static zbus::Connection> {
+    CONNECTION.get_or_try_init(zbus::Connection::session).await
+}
+
+/// Pause every MPRIS player that is playing, returning their bus names so
+/// `resume` starts only those again.
+pub(crate) async fn pause() -> Vec<String> {
+    match tokio::time::timeout(TIMEOUT, pause_playing()).await {
+        Ok(Ok(paused)) => paused,
+        Ok(Err(e)) => {
+            error!("Failed to pause media players: {e}");
+            Vec::new()
+        }
+        Err(_) => {
+            error!("Media players took too long to pause");
+            Vec::new()
+        }
+    }
+}
+
+async fn pause_playing() -> zbus::Result<Vec<String>> {
+    let connection = connection().await?;
+    let reply = connection
+        .call_method(
+            Some("org.freedesktop.DBus"),
+            "/org/freedesktop/DBus",
+            Some("org.freedesktop.DBus"),
+            "ListNames",
+            &(),
+        )
+        .await?;
+    let names: Vec<String> = reply.body().deserialize()?;
+
+    let mut paused = Vec::new();
+    for name in names.into_iter().filter(|n| n.starts_with(MPRIS_PREFIX)) {
+        match pause_if_playing(connection, &name).await {
+            Ok(true) => paused.push(name),
+            Ok(false) => {}
+            // One broken player shouldn't keep the others playing
+            Err(e) => debug!("{name}: {e}"),
+        }
+    }
+    Ok(paused)
+}
+
+async fn pause_if_playing(connection: &zbus::Connection, name: &str) -> zbus::Result<bool> {
+    let reply = connection
+        .call_method(
+            Some(name),
+            MPRIS_PATH,
+            Some("org.freedesktop.DBus.Properties"),
+            "Get",
+            &(PLAYER, "PlaybackStatus"),
+        )
+        .await?;
+    let status: OwnedValue = reply.body().deserialize()?;
+    if String::try_from(status).ok().as_deref() != Some("Playing") {
+        return Ok(false);
+    }
+    debug!("pausing {name}");
+    connection
+        .call_method(Some(name), MPRIS_PATH, Some(PLAYER), "Pause", &())
+        .await?;
+    Ok(true)
+}
+
+/// Start the players `pause` stopped again, in the background.
+pub(crate) fn resume(players: Vec<String>) {
+    if players.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        let Ok(connection) = connection().await else {
+            return;
+        };
+        for name in players {
+            debug!("resuming {name}");
+            let played = connection
+                .call_method(Some(name.as_str()), MPRIS_PATH, Some(PLAYER), "Play", &())
+                .await;
+            if let Err(e) = played {
+                error!("Failed to resume {name}: {e}");
+            }
+        }
+    });
+}
@@ -0,0 +1,206 @@
+use crate::config::{Config, MeetingSource, Provider, resolve_path};
+use crate::error::WayvoiceError;
+use crate::oneshot::cli_config;
+use crate::recorder::{Recorder, is_muted, level_db, pick_device, wav_bytes};
+use crate::text::apply_replacements;
+use crate::transcription::transcribe_audio;
+use jiff::Zoned;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// Chunks quieter than this are skipped; Whisper invents text for silence.
+const QUIET_DB: f32 = -55.0;
+
+/// A stretch of the meeting, `offset` seconds in.
+struct Chunk {
+    offset: u64,
+    samples: Vec<i16>,
+}
+
+/// Record a call until Enter or Ctrl+C, transcribing it every
+/// `meeting.chunk_secs` and appending the text to `output` (a new file
+/// under the meetings directory by default).
+pub async fn run(
+    source: Option<&str>,
+    output: Option<&Path>,
+    provider: Option<&str>,
+    profile: Option<&str>,
+) {
+    let mut config = cli_config(provider, profile);
+    config.diarize = config.meeting.diarize;
+    if config.diarize && config.provider != Provider::Deepgram {
+        eprintln!("Speakers are only labelled with provider deepgram");
+    }
+    let source = match source {
+        Some(name) => MeetingSource::from_name(name).unwrap_or_else(|| {
+            let e = WayvoiceError::Config(format!("Unknown meeting source: {name}"));
+            eprintln!("{e}");
+            std::process::exit(e.exit_code());
+        }),
+        None => config.meeting.source,
+    };
+    let path = match output {
+        Some(path) => path.to_path_buf(),
+        None => default_path(&config.meeting.dir).unwrap_or_else(|| {
+            eprintln!("Could not find the data directory; pass --output");
+            std::process::exit(1);
+        }),
+    };
+    if let Some(dir) = path.parent()
+        && let Err(e) = tokio::fs::create_dir_all(dir).await
+    {
+        eprintln!("Failed to create {dir:?}: {e}");
+        std::process::exit(1);
+    }
+
+    let mut recorders = Vec::new();
+    if source != MeetingSource::System {
+        recorders.push(Recorder::for_device(&config, &pick_device(&config).await));
+    }
+    if source != MeetingSource::Mic {
+        recorders.push(Recorder::monitor(&config, &config.meeting.monitor_device));
+    }
+    for recorder in &mut recorders {
+        if let Err(e) = recorder.start().await {
+            eprintln!("{e}");
+            std::process::exit(e.exit_code());
+        }
+    }
+    eprintln!(
+        "Recording meeting to {} (press Enter to stop)",
+        path.display()
+    );
+    if source != MeetingSource::System && is_muted(&config.audio_device).await {
+        eprintln!("Mic is muted");
+    }
+
+    let (chunks, rx) = mpsc::unbounded_channel();
+    let writer = tokio::spawn(transcribe_chunks(rx, path.clone(), config.clone()));
+
+    let stop = async {
+        let mut line = String::new();
+        let mut stdin = BufReader::new(tokio::io::stdin());
+        tokio::select! {
+            _ = stdin.read_line(&mut line) => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    };
+    tokio::pin!(stop);
+    let chunk_len = Duration::from_secs(config.meeting.chunk_secs.max(1));
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + chunk_len, chunk_len);
+    let start = std::time::Instant::now();
+    let mut offset = 0;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let samples = mix(recorders.iter().map(Recorder::take_samples).collect());
+                let _ = chunks.send(Chunk { offset, samples });
+                offset = start.elapsed().as_secs();
+            }
+            _ = &mut stop => break,
+        }
+    }
+
+    let mut last = Vec::new();
+    for recorder in &mut recorders {
+        last.push(recorder.stop_samples().await);
+    }
+    let _ = chunks.send(Chunk {
+        offset,
+        samples: mix(last),
+    });
+    drop(chunks);
+    eprintln!("Transcribing the rest...");
+    let _ = writer.await;
+    eprintln!("Saved {}", path.display());
+}
+
+/// `<dir>/<timestamp>.txt`, with `dir` defaulting to the data directory.
+fn default_path(dir: &str) -> Option<PathBuf> {
+    let dir = if dir.is_empty() {
+        dirs::data_dir()?.join("wayvoice").join("meetings")
+    } else {
+        resolve_path(dir)
+    };
+    let name = Zoned::now().strftime("%Y-%m-%d_%H-%M-%S").to_string();
+    Some(dir.join(format!("{name}.txt")))
+}
+
+/// Sum the mic and system audio into one track.
+fn mix(tracks: Vec<Vec<i16>>) -> Vec<i16> {
+    let len = tracks.iter().map(Vec::len).max().unwrap_or(0);
+    let mut mixed = vec![0i16; len];
+    for track in tracks {
+        for (out, sample) in mixed.iter_mut().zip(track) {
+            *out = out.saturating_add(sample);
+        }
+    }
+    mixed
+}
+
+/// Transcribe chunks in order, appending `[HH:MM:SS] text` lines to `path`.
+/// A chunk that fails is saved next to it as WAV so it can be retried with
+/// `wayvoice transcribe`.
+async fn transcribe_chunks(
+    mut chunks: mpsc::UnboundedReceiver<Chunk>,
+    path: PathBuf,
+    config: Config,
+) {
+    while let Some(Chunk { offset, samples }) = chunks.recv().await {
+        let stamp = format!(
+            "{:02}:{:02}:{:02}",
+            offset / 3600,
+            offset / 60 % 60,
+            offset % 60
+        );
+        if level_db(&samples) < QUIET_DB {
+            debug!("meeting: {stamp} is silent, skipping");
+            continue;
+        }
+        let wav = wav_bytes(&samples);
+        let line = match transcribe_audio(wav.clone(), &config).await {
+            Ok(transcript) => {
+                let text = apply_replacements(
+                    &transcript.text,
+                    config.replacements_for(transcript.language.as_deref()),
+                );
+                if text.is_empty() {
+                    continue;
+                }
+                // One line per speaker turn when diarizing
+                let lines: String = text
+                    .lines()
+                    .map(|line| format!("[{stamp}] {line}\n"))
+                    .collect();
+                print!("{lines}");
+                lines
+            }
+            Err(e) => {
+                let audio = path.with_extension(format!("{}.wav", stamp.replace(':', "-")));
+                eprintln!("[{stamp}] Transcription failed: {e}");
+                if let Err(e) = tokio::fs::write(&audio, &wav).await {
+                    eprintln!("Failed to write {audio:?}: {e}");
+                }
+                format!(
+                    "[{stamp}] (transcription failed: {e}; audio in {})\n",
+                    audio.display()
+                )
+            }
+        };
+        if let Err(e) = append(&path, &line).await {
+            eprintln!("Failed to write {path:?}: {e}");
+        }
+    }
+}
+
+async fn append(path: &Path, line: &str) -> std::io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(line.as_bytes()).await
+}
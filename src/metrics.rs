@@ -0,0 +1,142 @@
+use crate::daemon::Daemon;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tracing::{debug, error};
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0];
+
+#[derive(Default)]
+struct ProviderMetrics {
+    dictations: u64,
+    errors: u64,
+    /// Count per bucket in `LATENCY_BUCKETS`, not cumulative.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_count: u64,
+    latency_sum: f64,
+}
+
+/// In-memory counters since the daemon started, by provider.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    providers: std::sync::Mutex<BTreeMap<String, ProviderMetrics>>,
+}
+
+impl Metrics {
+    /// Count a transcription that produced text; `latency` is absent for live
+    /// streaming.
+    pub(crate) fn record_dictation(&self, provider: &str, latency: Option<Duration>) {
+        let mut providers = self.providers.lock().unwrap();
+        let metrics = providers.entry(provider.to_string()).or_default();
+        metrics.dictations += 1;
+        if let Some(latency) = latency {
+            let secs = latency.as_secs_f64();
+            if let Some(i) = LATENCY_BUCKETS.iter().position(|le| secs <= *le) {
+                metrics.buckets[i] += 1;
+            }
+            metrics.latency_count += 1;
+            metrics.latency_sum += secs;
+        }
+    }
+
+    pub(crate) fn record_error(&self, provider: &str) {
+        let mut providers = self.providers.lock().unwrap();
+        providers.entry(provider.to_string()).or_default().errors += 1;
+    }
+
+    /// Prometheus text exposition format.
+    pub(crate) fn render(&self) -> String {
+        let providers = self.providers.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP wayvoice_dictations_total Transcriptions that produced text.\n");
+        out.push_str("# TYPE wayvoice_dictations_total counter\n");
+        for (provider, metrics) in providers.iter() {
+            let _ = writeln!(
+                out,
+                "wayvoice_dictations_total{{provider=\"{provider}\"}} {}",
+                metrics.dictations
+            );
+        }
+
+        out.push_str("# HELP wayvoice_errors_total Failed transcriptions.\n");
+        out.push_str("# TYPE wayvoice_errors_total counter\n");
+        for (provider, metrics) in providers.iter() {
+            let _ = writeln!(
+                out,
+                "wayvoice_errors_total{{provider=\"{provider}\"}} {}",
+                metrics.errors
+            );
+        }
+
+        out.push_str(
+            "# HELP wayvoice_latency_seconds From stopping the recording to the text being delivered.\n",
+        );
+        out.push_str("# TYPE wayvoice_latency_seconds histogram\n");
+        for (provider, metrics) in providers.iter() {
+            let mut cumulative = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(metrics.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "wayvoice_latency_seconds_bucket{{provider=\"{provider}\",le=\"{le}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "wayvoice_latency_seconds_bucket{{provider=\"{provider}\",le=\"+Inf\"}} {}",
+                metrics.latency_count
+            );
+            let _ = writeln!(
+                out,
+                "wayvoice_latency_seconds_sum{{provider=\"{provider}\"}} {}",
+                metrics.latency_sum
+            );
+            let _ = writeln!(
+                out,
+                "wayvoice_latency_seconds_count{{provider=\"{provider}\"}} {}",
+                metrics.latency_count
+            );
+        }
+        out
+    }
+}
+
+/// Answer every HTTP request on `127.0.0.1:port` with the daemon's metrics.
+pub async fn serve_http(port: u16, daemon: Arc<Mutex<Daemon>>) {
+    let listener = match TcpListener::bind(("127.0.0.1", port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind metrics port {port}: {e}");
+            return;
+        }
+    };
+    println!("Serving metrics on http://127.0.0.1:{port}/metrics");
+
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let body = daemon.lock().await.metrics();
+        tokio::spawn(async move {
+            // Any path gets the metrics, so the request itself is ignored
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\n\
+                 Connection: close\r\n\r\n{body}",
+                body.len()
+            );
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                debug!("metrics: {e}");
+            }
+        });
+    }
+}
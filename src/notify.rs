@@ -0,0 +1,150 @@
+use crate::config::{Config, NotifierKind};
+use crate::transcription::BoxFuture;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::process::Command;
+use tracing::error;
+
+const EXPIRE_MS: i32 = 2000;
+
+/// A place to show short status messages ("Recording...", errors).
+pub trait Notifier: Send + Sync {
+    fn notify<'a>(&'a self, message: &'a str) -> BoxFuture<'a, ()>;
+}
+
+/// Chosen by `configure`; notify-send until then.
+static NOTIFIER: RwLock<Option<Arc<dyn Notifier>>> = RwLock::new(None);
+
+/// Id of the last notification, so the next one replaces it instead of
+/// stacking up ("Recording...", "Transcribing...", ...).
+static NOTIFICATION_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Pick the backend from `notifications` / `notifier`.
+pub fn configure(config: &Config) {
+    let notifier: Arc<dyn Notifier> = if !config.notifications {
+        Arc::new(Silent)
+    } else {
+        match config.notifier {
+            NotifierKind::NotifySend => Arc::new(NotifySend),
+            NotifierKind::Dbus => Arc::new(DBus::default()),
+            NotifierKind::Stdout => Arc::new(Stdout),
+        }
+    };
+    *NOTIFIER.write().unwrap() = Some(notifier);
+}
+
+pub async fn notify(message: &str) {
+    let notifier = NOTIFIER.read().unwrap().clone();
+    match notifier {
+        Some(notifier) => notifier.notify(message).await,
+        None => NotifySend.notify(message).await,
+    }
+}
+
+/// Spawns `notify-send` for every message.
+struct NotifySend;
+
+impl NotifySend {
+    async fn send(message: &str) {
+        let mut cmd = Command::new("notify-send");
+        cmd.args(["--app-name=wayvoice", "--print-id"])
+            .arg(format!("--expire-time={EXPIRE_MS}"));
+        let id = NOTIFICATION_ID.load(Ordering::Relaxed);
+        if id != 0 {
+            cmd.arg(format!("--replace-id={id}"));
+        }
+        cmd.args(["wayvoice", message]);
+
+        match cmd.output().await {
+            Ok(output) if output.status.success() => {
+                let id = String::from_utf8_lossy(&output.stdout).trim().parse();
+                NOTIFICATION_ID.store(id.unwrap_or(0), Ordering::Relaxed);
+            }
+            // notify-send before libnotify 0.7.9 has neither flag
+            Ok(_) => {
+                let _ = Command::new("notify-send")
+                    .arg("--app-name=wayvoice")
+                    .arg(format!("--expire-time={EXPIRE_MS}"))
+                    .args(["wayvoice", message])
+                    .status()
+                    .await;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+impl Notifier for NotifySend {
+    fn notify<'a>(&'a self, message: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(Self::send(message))
+    }
+}
+
+/// Talks to `org.freedesktop.Notifications` on the session bus directly,
+/// saving a process spawn per message.
+#[derive(Default)]
+struct DBus {
+    connection: tokio::sync::OnceCell<zbus::Connection>,
+}
+
+impl DBus {
+    async fn send(&self, message: &str) -> zbus::Result<()> {
+        let connection = self
+            .connection
+            .get_or_try_init(zbus::Connection::session)
+            .await?;
+        let hints: HashMap<&str, zbus::zvariant::Value> = HashMap::new();
+        let reply = connection
+            .call_method(
+                Some("org.freedesktop.Notifications"),
+                "/org/freedesktop/Notifications",
+                Some("org.freedesktop.Notifications"),
+                "Notify",
+                &(
+                    "wayvoice",
+                    NOTIFICATION_ID.load(Ordering::Relaxed),
+                    "",
+                    "wayvoice",
+                    message,
+                    Vec::<&str>::new(),
+                    hints,
+                    EXPIRE_MS,
+                ),
+            )
+            .await?;
+        let id: u32 = reply.body().deserialize()?;
+        NOTIFICATION_ID.store(id, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Notifier for DBus {
+    fn notify<'a>(&'a self, message: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            if let Err(e) = self.send(message).await {
+                error!("Notification failed: {e}");
+            }
+        })
+    }
+}
+
+/// For running without a notification daemon, e.g. under a terminal.
+/// Writes to stderr, so it stays out of text `once` and `transcribe` print.
+struct Stdout;
+
+impl Notifier for Stdout {
+    fn notify<'a>(&'a self, message: &'a str) -> BoxFuture<'a, ()> {
+        eprintln!("wayvoice: {message}");
+        Box::pin(std::future::ready(()))
+    }
+}
+
+/// `notifications = false`.
+struct Silent;
+
+impl Notifier for Silent {
+    fn notify<'a>(&'a self, _: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(std::future::ready(()))
+    }
+}
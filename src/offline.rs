@@ -0,0 +1,97 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tracing::error;
+
+/// Set while queued recordings are being sent, so flushes don't overlap.
+static FLUSHING: AtomicBool = AtomicBool::new(false);
+
+fn queue_dir() -> Option<PathBuf> {
+    Some(dirs::state_dir()?.join("wayvoice").join("queue"))
+}
+
+/// Keep a recording that couldn't be sent. Returns how many are waiting,
+/// or `None` if it couldn't be saved.
+pub(crate) async fn save(audio_data: &[u8]) -> Option<usize> {
+    let dir = queue_dir()?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = dir.join(format!("{millis}.wav"));
+    // Voice recordings are nobody else's business
+    let written = async {
+        tokio::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)
+            .await?;
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .await?;
+        file.write_all(audio_data).await
+    };
+    if let Err(e) = written.await {
+        error!("Failed to queue recording at {path:?}: {e}");
+        return None;
+    }
+    Some(queued().len())
+}
+
+/// Queued recordings, oldest first.
+pub fn queued() -> Vec<PathBuf> {
+    let Some(entries) = queue_dir().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "wav"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Print the queued recordings with their length.
+pub fn list() {
+    let paths = queued();
+    if paths.is_empty() {
+        println!("No queued recordings");
+        return;
+    }
+    for path in paths {
+        let secs = std::fs::read(&path)
+            .map(|wav| crate::stats::wav_secs(&wav))
+            .unwrap_or_default();
+        println!("{}  {secs:.1}s", path.display());
+    }
+}
+
+/// Done with a recording: its text made it to history.
+pub(crate) async fn remove(path: &Path) {
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        error!("Failed to remove {path:?}: {e}");
+    }
+}
+
+/// Set a recording the provider rejected aside as `.wav.failed`, so it
+/// isn't sent again on every retry but can still be transcribed by hand.
+pub(crate) async fn set_aside(path: &Path) {
+    let mut failed = path.as_os_str().to_owned();
+    failed.push(".failed");
+    if let Err(e) = tokio::fs::rename(path, &failed).await {
+        error!("Failed to move {path:?} aside: {e}");
+    }
+}
+
+/// Claim the queue for a flush; false if one is already running.
+pub(crate) fn start_flush() -> bool {
+    !FLUSHING.swap(true, Ordering::AcqRel)
+}
+
+pub(crate) fn end_flush() {
+    FLUSHING.store(false, Ordering::Release);
+}
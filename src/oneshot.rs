@@ -1,84 +1,549 @@
-use crate::config::load_config;
-use crate::text::apply_replacements;
-use crate::transcription::transcribe_audio;
-use log::debug;
-use std::process::Stdio;
-use tokio::process::Command;
-
-pub async fn run_once() {
-    let config = load_config();
-    let audio_file = std::env::temp_dir().join("voice-recording.wav");
-    let _ = tokio::fs::remove_file(&audio_file).await;
-
-    // Start recording
-    let mut child = match Command::new("pw-record")
-        .args([
-            "--format",
-            "s16",
-            "--rate",
-            "16000",
-            "--channels",
-            "1",
-            audio_file.to_str().unwrap(),
-        ])
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()
-    {
-        Ok(child) => child,
-        Err(e) => {
-            eprintln!("Failed to start pw-record: {e}");
-            std::process::exit(1);
+use crate::audio::{decode_to_wav, encode_opus, fix_wav_sizes, is_wav, upload_format};
+use crate::config::{Config, Provider, load_config, loggable};
+use crate::error::WayvoiceError;
+use crate::history::{self, Entry};
+use crate::inject::{copy_to_clipboard, inject_text};
+use crate::notify;
+use crate::postprocess::apply_postprocess;
+use crate::recorder::{Recorder, is_muted, pick_device};
+use crate::text::{apply_replacements, format_subtitles, word_diff};
+use crate::transcription::{Transcript, provider_for, transcribe_audio};
+use jiff::Zoned;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tracing::debug;
+
+/// Input louder than this counts as speech for `StopAt::silence`.
+const SPEECH_DB: f32 = -45.0;
+
+/// When a recording from the terminal ends, besides Enter or Ctrl+C.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StopAt {
+    /// Stop after this long.
+    pub max: Option<Duration>,
+    /// Stop once it's been this quiet for this long after speech.
+    pub silence: Option<Duration>,
+}
+
+impl StopAt {
+    /// Wait for Enter, Ctrl+C or one of the limits. Without a terminal
+    /// (stdin at EOF), only the last two stop it.
+    async fn wait(&self, recorder: &Recorder) {
+        let enter = async {
+            let mut line = String::new();
+            match BufReader::new(tokio::io::stdin())
+                .read_line(&mut line)
+                .await
+            {
+                Ok(0) | Err(_) => std::future::pending().await,
+                Ok(_) => {}
+            }
+        };
+        let limit = async {
+            match self.max {
+                Some(max) => tokio::time::sleep(max).await,
+                None => std::future::pending().await,
+            }
+        };
+        let silence = async {
+            let Some(silence) = self.silence else {
+                return std::future::pending().await;
+            };
+            let mut ticker = tokio::time::interval(Duration::from_millis(100));
+            let mut last_speech = None;
+            loop {
+                ticker.tick().await;
+                if recorder.level_db() > SPEECH_DB {
+                    last_speech = Some(std::time::Instant::now());
+                } else if last_speech.is_some_and(|at| at.elapsed() >= silence) {
+                    debug!("stopping after {silence:?} of silence");
+                    return;
+                }
+            }
+        };
+        tokio::select! {
+            _ = enter => {}
+            _ = limit => {}
+            _ = silence => {}
+            _ = tokio::signal::ctrl_c() => {}
         }
-    };
+    }
+}
+
+/// One dictation from the terminal. `commit` formats it as a commit
+/// message. The text goes to `output`, the clipboard (`copy`) and/or the
+/// focused window (`inject`), or else stdout.
+pub async fn run_once(
+    provider: Option<&str>,
+    profile: Option<&str>,
+    commit: bool,
+    output: Option<&Path>,
+    copy: bool,
+    inject: bool,
+    stop: StopAt,
+) {
+    let mut config = cli_config(provider, profile);
+    config.commit_message |= commit;
+    let mut recorder = Recorder::for_device(&config, &pick_device(&config).await);
+
+    if let Err(e) = recorder.start().await {
+        eprintln!("{e}");
+        std::process::exit(e.exit_code());
+    }
 
     eprintln!("Recording... (press Enter to stop)");
+    if is_muted(&config.audio_device).await {
+        eprintln!("Mic is muted");
+    }
 
-    // Wait for Enter or Ctrl+C
-    let mut line = String::new();
-    let _ = std::io::stdin().read_line(&mut line);
+    // Capture keeps running while this waits
+    let stop = StopAt {
+        max: stop.max.or_else(|| {
+            (config.max_recording_secs > 0).then(|| Duration::from_secs(config.max_recording_secs))
+        }),
+        ..stop
+    };
+    stop.wait(&recorder).await;
 
-    // Stop recording
-    let _ = child.kill().await;
-    let _ = child.wait().await;
+    let audio_data = recorder.stop().await;
 
     // Check if we got any audio
-    let audio_data = match tokio::fs::metadata(&audio_file).await {
-        Ok(meta) if meta.len() < 1000 => {
-            eprintln!("No audio recorded (file too small)");
+    if audio_data.len() < 1000 {
+        let e = WayvoiceError::audio("No audio recorded");
+        eprintln!("{e}");
+        std::process::exit(e.exit_code());
+    }
+    debug!("audio bytes: {}", audio_data.len());
+
+    eprintln!("Transcribing...");
+
+    // Transcribe
+    let start = std::time::Instant::now();
+    let transcript = match transcribe_audio(audio_data, &config).await {
+        Ok(transcript) => transcript,
+        Err(e) => {
+            eprintln!("Transcription failed: {e}");
+            std::process::exit(e.exit_code());
+        }
+    };
+
+    let text = finish_text(transcript, start.elapsed(), &config).await;
+    if let Some(path) = output
+        && let Err(e) = tokio::fs::write(path, format!("{text}\n")).await
+    {
+        eprintln!("Failed to write {path:?}: {e}");
+        std::process::exit(1);
+    }
+    if copy && !text.is_empty() && !copy_to_clipboard(&text).await {
+        std::process::exit(WayvoiceError::inject("Failed to copy to the clipboard").exit_code());
+    }
+    if inject && !text.is_empty() {
+        inject_text(&text, &config.inject).await;
+    }
+    if output.is_none() && !copy && !inject {
+        println!("{text}");
+    }
+}
+
+/// Record from the microphone until Enter, Ctrl+C or `duration` and save
+/// it without transcribing: to `output` (Ogg/Opus for `.ogg`/`.opus`,
+/// stdout for `-`) or a new WAV under the data directory.
+pub async fn record(output: Option<&Path>, duration: Option<u64>) {
+    let config = cli_config(None, None);
+    let path = match output {
+        Some(path) => path.to_path_buf(),
+        None => default_recording_path().unwrap_or_else(|| {
+            eprintln!("Could not find the data directory; pass --output");
             std::process::exit(1);
+        }),
+    };
+    let to_stdout = path == Path::new("-");
+    if !to_stdout
+        && let Some(dir) = path.parent()
+        && let Err(e) = tokio::fs::create_dir_all(dir).await
+    {
+        eprintln!("Failed to create {dir:?}: {e}");
+        std::process::exit(1);
+    }
+
+    let mut recorder = Recorder::for_device(&config, &pick_device(&config).await);
+    if let Err(e) = recorder.start().await {
+        eprintln!("{e}");
+        std::process::exit(e.exit_code());
+    }
+    match duration {
+        Some(secs) => eprintln!("Recording for {secs}s... (press Enter to stop early)"),
+        None => eprintln!("Recording... (press Enter to stop)"),
+    }
+    if is_muted(&config.audio_device).await {
+        eprintln!("Mic is muted");
+    }
+
+    let stop = StopAt {
+        max: duration.map(Duration::from_secs),
+        silence: None,
+    };
+    stop.wait(&recorder).await;
+
+    let wav = recorder.stop().await;
+    if wav.len() < 1000 {
+        let e = WayvoiceError::audio("No audio recorded");
+        eprintln!("{e}");
+        std::process::exit(e.exit_code());
+    }
+    let opus = path
+        .extension()
+        .is_some_and(|ext| ext == "ogg" || ext == "opus");
+    let audio_data = if opus {
+        encode_opus(&wav, config.opus_bitrate)
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(e.exit_code());
+            })
+    } else {
+        wav
+    };
+    let written = if to_stdout {
+        tokio::io::stdout().write_all(&audio_data).await
+    } else {
+        tokio::fs::write(&path, &audio_data).await
+    };
+    if let Err(e) = written {
+        eprintln!("Failed to write {path:?}: {e}");
+        std::process::exit(1);
+    }
+    if !to_stdout {
+        eprintln!("Saved {}", path.display());
+    }
+}
+
+/// `recordings/<timestamp>.wav` under the data directory.
+fn default_recording_path() -> Option<PathBuf> {
+    let name = Zoned::now().strftime("%Y-%m-%d_%H-%M-%S").to_string();
+    Some(
+        dirs::data_dir()?
+            .join("wayvoice")
+            .join("recordings")
+            .join(format!("{name}.wav")),
+    )
+}
+
+/// Transcribe an existing audio file, then print or inject the result.
+/// `format` prints `srt` or `vtt` subtitles or `json` with the segments
+/// instead of plain text; `diarize` labels speakers.
+pub async fn transcribe_file(
+    path: &Path,
+    inject: bool,
+    format: Option<&str>,
+    diarize: bool,
+    provider: Option<&str>,
+    profile: Option<&str>,
+) {
+    let mut config = cli_config(provider, profile);
+    config.diarize = diarize;
+    if diarize && config.provider != Provider::Deepgram {
+        eprintln!("Speakers are only labelled with provider deepgram");
+    }
+    let audio_data = read_audio(path, &config).await;
+
+    let start = std::time::Instant::now();
+    let transcript = match transcribe_audio(audio_data, &config).await {
+        Ok(transcript) => transcript,
+        Err(e) => {
+            eprintln!("Transcription failed: {e}");
+            std::process::exit(e.exit_code());
+        }
+    };
+
+    let mut segments = transcript.segments.clone();
+    if matches!(format, Some("srt" | "vtt")) && segments.is_empty() {
+        eprintln!(
+            "{} returned no timestamps; subtitles need a Whisper model with verbose_json",
+            config.provider.as_str()
+        );
+        std::process::exit(1);
+    }
+    for segment in &mut segments {
+        segment.text = apply_replacements(
+            &segment.text,
+            config.replacements_for(transcript.language.as_deref()),
+        );
+    }
+    let language = transcript.language.clone();
+    let text = finish_text(transcript, start.elapsed(), &config).await;
+    match format {
+        Some("srt") => print!("{}", format_subtitles(&segments, false)),
+        Some("vtt") => print!("{}", format_subtitles(&segments, true)),
+        Some(_) => {
+            let json = serde_json::json!({
+                "text": text,
+                "language": language,
+                "segments": segments,
+            });
+            println!("{json:#}");
         }
+        None if inject => inject_text(&text, &config.inject).await,
+        None => println!("{text}"),
+    }
+}
+
+/// Run an archived recording from history through another provider or
+/// model and show how the text differs from the original.
+pub async fn retranscribe(id: usize, provider: Option<&str>, model: Option<&str>) {
+    let entry = history::get(id);
+    let Some(path) = &entry.audio else {
+        eprintln!("History entry {id} has no archived audio; enable [archive] to keep recordings");
+        std::process::exit(1);
+    };
+    let mut config = cli_config(provider, None);
+    if let Some(model) = model {
+        config.model = model.to_string();
+    }
+    // Comparing models shouldn't add entries for the same recording
+    config.history = false;
+    let audio_data = read_audio(path, &config).await;
+
+    let start = std::time::Instant::now();
+    let transcript = match transcribe_audio(audio_data, &config).await {
+        Ok(transcript) => transcript,
         Err(e) => {
-            eprintln!("No audio file created: {e}");
-            std::process::exit(1);
+            eprintln!("Transcription failed: {e}");
+            std::process::exit(e.exit_code());
         }
-        Ok(meta) => {
-            debug!("audio bytes: {}", meta.len());
-            match tokio::fs::read(&audio_file).await {
-                Ok(data) => data,
-                Err(e) => {
-                    eprintln!("Failed to read audio file: {e}");
-                    std::process::exit(1);
-                }
+    };
+    let latency = start.elapsed();
+    let text = finish_text(transcript, latency, &config).await;
+
+    println!("original: {} ({})", entry.text, entry.provider);
+    println!(
+        "new:      {text} ({}, {}ms)",
+        config.provider.as_str(),
+        latency.as_millis()
+    );
+    println!("diff:     {}", word_diff(&entry.text, &text));
+}
+
+/// Send one recording to several providers and models in turn, printing
+/// each one's latency and transcript. By default that's the configured
+/// provider (with `draft_model` too, if set) and every other provider that
+/// has an API key or local model; `models` are tried on the configured
+/// provider.
+pub async fn bench(path: &Path, providers: &[String], models: &[String]) {
+    let mut config = cli_config(None, None);
+    // History would fill up with copies of the same clip
+    config.history = false;
+    // Each run should be timed on its own
+    config.race_provider.clear();
+    let asked = !providers.is_empty();
+    let providers: Vec<Provider> = if !asked {
+        let others = [
+            Provider::Groq,
+            Provider::Openai,
+            Provider::Deepgram,
+            Provider::WhisperCpp,
+            Provider::Vosk,
+        ];
+        std::iter::once(config.provider)
+            .chain(others.into_iter().filter(|&p| p != config.provider))
+            .collect()
+    } else {
+        providers
+            .iter()
+            .map(|name| {
+                Provider::from_name(name).unwrap_or_else(|| {
+                    let e = WayvoiceError::Config(format!("Unknown provider: {name}"));
+                    eprintln!("{e}");
+                    std::process::exit(e.exit_code());
+                })
+            })
+            .collect()
+    };
+    let mut runs: Vec<Config> = Vec::new();
+    for provider in providers {
+        if provider == config.provider {
+            runs.push(config.clone());
+            let extra = if !models.is_empty() {
+                models.to_vec()
+            } else if !config.draft_model.is_empty() {
+                vec![config.draft_model.clone()]
+            } else {
+                Vec::new()
+            };
+            for model in extra {
+                runs.push(Config {
+                    model,
+                    ..config.clone()
+                });
             }
+        } else {
+            // `model` and `base_url` belong to the configured provider
+            runs.push(Config {
+                provider,
+                model: String::new(),
+                base_url: String::new(),
+                ..config.clone()
+            });
         }
-    };
+    }
 
-    eprintln!("Transcribing...");
+    let mut rows = Vec::new();
+    for run in &runs {
+        let backend = match provider_for(run).await {
+            Ok(backend) => backend,
+            // Not set up; only worth saying when it was asked for
+            Err(e) => {
+                if asked || run.provider == config.provider {
+                    eprintln!("{}: {e}", run.provider.as_str());
+                }
+                continue;
+            }
+        };
+        let label = format!("{} {}", backend.name(), backend.model());
+        eprintln!("Transcribing with {label}...");
+        let audio_data = read_audio(path, run).await;
+        let start = std::time::Instant::now();
+        let result = transcribe_audio(audio_data, run).await;
+        let latency = start.elapsed();
+        let text = match result {
+            Ok(transcript) => transcript.text,
+            Err(e) => format!("(failed: {e})"),
+        };
+        rows.push((label, latency, text));
+    }
+    if rows.is_empty() {
+        let e = WayvoiceError::config("No provider is set up");
+        eprintln!("{e}");
+        std::process::exit(e.exit_code());
+    }
 
-    // Transcribe
-    let text = match transcribe_audio(audio_data, &config).await {
-        Ok(text) => text,
+    let width = rows
+        .iter()
+        .map(|(label, ..)| label.len())
+        .max()
+        .unwrap_or(0);
+    for (label, latency, text) in rows {
+        println!("{label:<width$} {:>7}ms  {text}", latency.as_millis());
+    }
+}
+
+/// Print the transcription models the provider offers, marking the one
+/// `model` is set to.
+pub async fn list_models(provider: Option<&str>) {
+    let config = cli_config(provider, None);
+    let models = match provider_for(&config).await {
+        Ok(backend) => backend.models().await,
+        Err(e) => Err(e),
+    };
+    let models = models.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(e.exit_code());
+    });
+    for model in models {
+        if model == config.model {
+            println!("{model} (configured)");
+        } else {
+            println!("{model}");
+        }
+    }
+}
+
+/// Read an audio file (or stdin for `-`), decoding it to WAV where the
+/// provider needs that.
+async fn read_audio(path: &Path, config: &Config) -> Vec<u8> {
+    let stdin = path == Path::new("-");
+    let read = if stdin {
+        let mut data = Vec::new();
+        let read = tokio::io::stdin().read_to_end(&mut data).await;
+        fix_wav_sizes(&mut data);
+        read.map(|_| data)
+    } else {
+        tokio::fs::read(path).await
+    };
+    let audio_data = match read {
+        Ok(data) => data,
         Err(e) => {
-            eprintln!("Transcription failed: {e}");
-            std::process::exit(1);
+            eprintln!("Failed to read {path:?}: {e}");
+            std::process::exit(WayvoiceError::audio(e).exit_code());
+        }
+    };
+    if audio_data.is_empty() {
+        let e = if stdin {
+            WayvoiceError::audio("No audio on stdin")
+        } else {
+            WayvoiceError::audio(format!("{} is empty", path.display()))
+        };
+        eprintln!("{e}");
+        std::process::exit(e.exit_code());
+    }
+
+    // Cloud providers take mp3/ogg/flac as-is; anything else (m4a, or any
+    // compressed file for the local recognizers) is decoded to WAV first
+    let local = matches!(config.provider, Provider::WhisperCpp | Provider::Vosk);
+    let sniffed_wav = upload_format(&audio_data).1 == "audio/wav";
+    let audio_data = if !is_wav(&audio_data) && (local || sniffed_wav) {
+        match decode_to_wav(&audio_data).await {
+            Ok(wav) => wav,
+            Err(e) => {
+                eprintln!("Failed to decode {path:?}: {e}");
+                std::process::exit(e.exit_code());
+            }
         }
+    } else {
+        audio_data
     };
+    debug!("audio bytes: {}", audio_data.len());
+    audio_data
+}
+
+/// Config for a command-line run, with `--provider` / `--profile` applied.
+pub(crate) fn cli_config(provider: Option<&str>, profile: Option<&str>) -> Config {
+    let mut config = load_config();
+    notify::configure(&config);
+    if let Some(name) = profile {
+        config = config.with_profile(name).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(e.exit_code());
+        });
+    }
+    if let Some(name) = provider {
+        match Provider::from_name(name) {
+            Some(provider) => config.provider = provider,
+            None => {
+                let e = WayvoiceError::Config(format!("Unknown provider: {name}"));
+                eprintln!("{e}");
+                std::process::exit(e.exit_code());
+            }
+        }
+    }
+    config
+}
 
-    // Apply replacements and print
-    debug!("raw: {text}");
-    let text = apply_replacements(&text, &config.replacements);
-    debug!("replaced: {text}");
-    println!("{text}");
+/// Post-process and apply replacements, recording the result in history.
+async fn finish_text(transcript: Transcript, latency: Duration, config: &Config) -> String {
+    let raw = transcript.text;
+    debug!("raw: {}", loggable(&raw, config.log_transcripts));
+    if raw.is_empty() {
+        eprintln!("No speech detected");
+        return raw;
+    }
+    let text = apply_postprocess(raw.clone(), config).await;
+    let text = apply_replacements(
+        &text,
+        config.replacements_for(transcript.language.as_deref()),
+    );
+    debug!("replaced: {}", loggable(&text, config.log_transcripts));
+    if config.history && !text.is_empty() {
+        let provider = config.provider.as_str();
+        history::record(&Entry::new(
+            provider,
+            Some(latency),
+            transcript.language.as_deref(),
+            &raw,
+            &text,
+        ))
+        .await;
+    }
+    text
 }
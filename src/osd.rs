@@ -0,0 +1,398 @@
+use crate::config::{OsdConfig, OsdPosition, load_config};
+use crate::daemon::Event;
+use crate::ipc::{Args, send_command, subscribe};
+use std::fs::File;
+use std::os::fd::{AsFd, FromRawFd};
+use std::os::unix::fs::FileExt;
+use std::sync::mpsc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::debug;
+use wayland_client::globals::{GlobalListContents, registry_queue_init};
+use wayland_client::protocol::{
+    wl_buffer, wl_compositor, wl_region, wl_registry, wl_shm, wl_shm_pool, wl_surface,
+};
+use wayland_client::{Connection, Dispatch, EventQueue, QueueHandle, delegate_noop};
+use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::{
+    Layer, ZwlrLayerShellV1,
+};
+use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1::{
+    self, Anchor, ZwlrLayerSurfaceV1,
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+/// How often elapsed time and level are refreshed while recording.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const WIDTH: i32 = 180;
+const HEIGHT: i32 = 40;
+const FRAME_BYTES: usize = (WIDTH * HEIGHT * 4) as usize;
+
+const BACKGROUND: u32 = 0xFF1E1E1E;
+const RECORDING: u32 = 0xFFE53935;
+const TEXT: u32 = 0xFFEEEEEE;
+const LEVEL_ON: u32 = 0xFF43A047;
+const LEVEL_OFF: u32 = 0xFF424242;
+
+/// What the overlay shows while recording.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Frame {
+    elapsed_secs: u64,
+    /// Absent when live streaming.
+    level_db: Option<f32>,
+}
+
+/// Show an always-on-top indicator with the elapsed time and input level
+/// while the daemon records. Runs until the compositor goes away.
+pub async fn run_osd() -> Result<(), String> {
+    let config = load_config();
+    let overlay = Overlay::connect(config.osd)?;
+    let (frames, rx) = mpsc::channel();
+    let drawing = std::thread::spawn(move || overlay.run(rx));
+
+    let (recording_tx, mut recording) = watch::channel(false);
+    tokio::spawn(track_recording(recording_tx));
+
+    let mut shown = None;
+    loop {
+        let is_recording = *recording.borrow_and_update();
+        let frame = if is_recording {
+            current_frame().await
+        } else {
+            None
+        };
+        if frame != shown {
+            if frames.send(frame).is_err() {
+                return match drawing.join() {
+                    Ok(Err(e)) => Err(e),
+                    _ => Ok(()),
+                };
+            }
+            shown = frame;
+        }
+
+        if is_recording {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        } else if recording.changed().await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Follow the daemon's state, reconnecting whenever it isn't running.
+async fn track_recording(recording: watch::Sender<bool>) {
+    loop {
+        let result = subscribe(|event| match event {
+            Event::Recording => {
+                recording.send_replace(true);
+            }
+            Event::Idle | Event::Transcribing => {
+                recording.send_replace(false);
+            }
+            // Live-streamed text arrives while still recording
+            Event::Text { .. } | Event::Error { .. } => {}
+        })
+        .await;
+        if let Err(e) = result {
+            debug!("osd: {e}");
+        }
+        recording.send_replace(false);
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn current_frame() -> Option<Frame> {
+    let status = send_command("status", Args::default()).await.ok()?.status?;
+    (status.state == "recording").then(|| Frame {
+        elapsed_secs: status.elapsed_secs.unwrap_or(0),
+        level_db: status.level_db,
+    })
+}
+
+/// A `wlr-layer-shell` surface on the overlay layer, created while there is
+/// something to show and destroyed in between so it never takes input.
+struct Overlay {
+    queue: EventQueue<State>,
+    state: State,
+    compositor: wl_compositor::WlCompositor,
+    layer_shell: ZwlrLayerShellV1,
+    /// Two buffers in one shm file, so one can be drawn while the
+    /// compositor still reads the other.
+    pool_file: File,
+    buffers: [wl_buffer::WlBuffer; 2],
+    surface: Option<(wl_surface::WlSurface, ZwlrLayerSurfaceV1)>,
+    config: OsdConfig,
+}
+
+#[derive(Default)]
+struct State {
+    busy: [bool; 2],
+    configured: bool,
+    closed: bool,
+}
+
+impl Overlay {
+    fn connect(config: OsdConfig) -> Result<Self, String> {
+        let conn = Connection::connect_to_env().map_err(|e| e.to_string())?;
+        let (globals, queue) = registry_queue_init::<State>(&conn).map_err(|e| e.to_string())?;
+        let qh = queue.handle();
+
+        let compositor: wl_compositor::WlCompositor =
+            globals.bind(&qh, 4..=6, ()).map_err(|e| e.to_string())?;
+        let shm: wl_shm::WlShm = globals.bind(&qh, 1..=1, ()).map_err(|e| e.to_string())?;
+        let layer_shell: ZwlrLayerShellV1 = globals
+            .bind(&qh, 1..=4, ())
+            .map_err(|_| "compositor does not support wlr-layer-shell".to_string())?;
+
+        let pool_file = shm_file(2 * FRAME_BYTES).map_err(|e| e.to_string())?;
+        let pool = shm.create_pool(pool_file.as_fd(), 2 * FRAME_BYTES as i32, &qh, ());
+        let buffers = [0, 1].map(|i| {
+            pool.create_buffer(
+                i * FRAME_BYTES as i32,
+                WIDTH,
+                HEIGHT,
+                WIDTH * 4,
+                wl_shm::Format::Argb8888,
+                &qh,
+                i as usize,
+            )
+        });
+        pool.destroy();
+
+        Ok(Self {
+            queue,
+            state: State::default(),
+            compositor,
+            layer_shell,
+            pool_file,
+            buffers,
+            surface: None,
+            config,
+        })
+    }
+
+    /// Draw every frame that comes in; `None` hides the overlay.
+    fn run(mut self, frames: mpsc::Receiver<Option<Frame>>) -> Result<(), String> {
+        while let Ok(frame) = frames.recv() {
+            match frame {
+                Some(frame) => self.show(&frame)?,
+                None => self.hide()?,
+            }
+        }
+        Ok(())
+    }
+
+    fn show(&mut self, frame: &Frame) -> Result<(), String> {
+        if self.state.closed {
+            self.hide()?;
+        }
+        if self.surface.is_none() {
+            self.create_surface()?;
+        }
+        let Some(i) = self.state.busy.iter().position(|busy| !busy) else {
+            // The compositor is behind; skip this frame
+            return Ok(());
+        };
+
+        let pixels: Vec<u8> = render(frame)
+            .into_iter()
+            .flat_map(u32::to_le_bytes)
+            .collect();
+        self.pool_file
+            .write_all_at(&pixels, (i * FRAME_BYTES) as u64)
+            .map_err(|e| e.to_string())?;
+
+        let (surface, _) = self.surface.as_ref().expect("created above");
+        surface.attach(Some(&self.buffers[i]), 0, 0);
+        surface.damage_buffer(0, 0, WIDTH, HEIGHT);
+        surface.commit();
+        self.state.busy[i] = true;
+        self.roundtrip()
+    }
+
+    fn create_surface(&mut self) -> Result<(), String> {
+        let qh = self.queue.handle();
+        let surface = self.compositor.create_surface(&qh, ());
+        // An empty input region lets clicks through to the window below
+        let region = self.compositor.create_region(&qh, ());
+        surface.set_input_region(Some(&region));
+        region.destroy();
+
+        let layer = self.layer_shell.get_layer_surface(
+            &surface,
+            None,
+            Layer::Overlay,
+            "wayvoice".to_string(),
+            &qh,
+            (),
+        );
+        layer.set_size(WIDTH as u32, HEIGHT as u32);
+        let margin = self.config.margin;
+        match self.config.position {
+            OsdPosition::Top => {
+                layer.set_anchor(Anchor::Top);
+                layer.set_margin(margin, 0, 0, 0);
+            }
+            OsdPosition::Bottom => {
+                layer.set_anchor(Anchor::Bottom);
+                layer.set_margin(0, 0, margin, 0);
+            }
+        }
+        surface.commit();
+
+        self.state.configured = false;
+        self.surface = Some((surface, layer));
+        while !self.state.configured && !self.state.closed {
+            self.queue
+                .blocking_dispatch(&mut self.state)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn hide(&mut self) -> Result<(), String> {
+        let Some((surface, layer)) = self.surface.take() else {
+            return Ok(());
+        };
+        layer.destroy();
+        surface.destroy();
+        self.state = State::default();
+        self.roundtrip()
+    }
+
+    fn roundtrip(&mut self) -> Result<(), String> {
+        self.queue
+            .roundtrip(&mut self.state)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Recording dot, `m:ss` timer and a level meter, as ARGB pixels.
+fn render(frame: &Frame) -> Vec<u32> {
+    let mut pixels = vec![BACKGROUND; (WIDTH * HEIGHT) as usize];
+    let mut fill = |x0: i32, y0: i32, w: i32, h: i32, color: u32| {
+        for y in y0..(y0 + h).min(HEIGHT) {
+            for x in x0..(x0 + w).min(WIDTH) {
+                pixels[(y * WIDTH + x) as usize] = color;
+            }
+        }
+    };
+
+    // Recording dot
+    for y in -8..=8 {
+        for x in -8..=8 {
+            if x * x + y * y <= 64 {
+                fill(20 + x, HEIGHT / 2 + y, 1, 1, RECORDING);
+            }
+        }
+    }
+
+    // Timer, in a 3x5 bitmap font scaled up 4x
+    let timer = format!("{}:{:02}", frame.elapsed_secs / 60, frame.elapsed_secs % 60);
+    let mut x = 40;
+    for c in timer.chars() {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 {
+                    fill(x + col * 4, 10 + row as i32 * 4, 4, 4, TEXT);
+                }
+            }
+        }
+        x += 16;
+    }
+
+    // Level meter: eight segments spanning -60..0 dBFS
+    if let Some(db) = frame.level_db {
+        let lit = (((db + 60.0) / 60.0).clamp(0.0, 1.0) * 8.0).round() as i32;
+        for segment in 0..8 {
+            let color = if segment < lit { LEVEL_ON } else { LEVEL_OFF };
+            fill(130 + segment * 5, 12, 4, 16, color);
+        }
+    }
+    pixels
+}
+
+/// Rows of a 3x5 glyph, most significant bit on the left.
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        _ => [0; 5],
+    }
+}
+
+/// Anonymous shared memory for the compositor to read pixels from.
+fn shm_file(len: usize) -> std::io::Result<File> {
+    // SAFETY: the name is a valid C string and the fd is owned by the File
+    let fd = unsafe { libc::memfd_create(c"wayvoice-osd".as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let file = unsafe { File::from_raw_fd(fd) };
+    file.set_len(len as u64)?;
+    Ok(file)
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_buffer::WlBuffer, usize> for State {
+    fn event(
+        state: &mut Self,
+        _: &wl_buffer::WlBuffer,
+        event: wl_buffer::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            state.busy[*index] = false;
+        }
+    }
+}
+
+impl Dispatch<ZwlrLayerSurfaceV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        layer: &ZwlrLayerSurfaceV1,
+        event: zwlr_layer_surface_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_layer_surface_v1::Event::Configure { serial, .. } => {
+                layer.ack_configure(serial);
+                state.configured = true;
+            }
+            zwlr_layer_surface_v1::Event::Closed => state.closed = true,
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(State: wl_compositor::WlCompositor);
+delegate_noop!(State: wl_region::WlRegion);
+delegate_noop!(State: wl_shm_pool::WlShmPool);
+delegate_noop!(State: ignore wl_shm::WlShm);
+delegate_noop!(State: ignore wl_surface::WlSurface);
+delegate_noop!(State: ZwlrLayerShellV1);
@@ -0,0 +1,118 @@
+use crate::config::{InjectConfig, OutputConfig, Sink, resolve_path};
+use crate::inject::{copy_to_clipboard, inject_text};
+use jiff::Zoned;
+use jiff::fmt::strtime;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, error, warn};
+
+/// Send finished text to every configured sink. `continues` marks a live
+/// segment following an earlier one, which is typed with a leading space.
+pub async fn deliver(text: &str, continues: bool, output: &OutputConfig, inject: &InjectConfig) {
+    if let Some(path) = &output.target {
+        write_target(path, text, continues).await;
+        return;
+    }
+    for sink in &output.sinks {
+        let start = std::time::Instant::now();
+        match sink {
+            Sink::Inject if !inject.enabled => continue,
+            Sink::Inject if continues => inject_text(&format!(" {text}"), inject).await,
+            Sink::Inject => inject_text(text, inject).await,
+            Sink::Clipboard => {
+                copy_to_clipboard(text).await;
+            }
+            Sink::File => append_line(&output.file, text).await,
+            Sink::Fifo => write_fifo(&output.fifo, text).await,
+            Sink::Notes => append_note(output, text).await,
+        }
+        debug!("output {sink:?}: {:?}", start.elapsed());
+    }
+}
+
+/// Replace the `--output` file with the text, or add a live segment to it.
+async fn write_target(path: &Path, text: &str, continues: bool) {
+    let written = if continues {
+        append(path, format!(" {text}").as_bytes()).await
+    } else {
+        tokio::fs::write(path, text).await
+    };
+    if let Err(e) = written {
+        error!("Failed to write {path:?}: {e}");
+    }
+}
+
+async fn append_line(path: &str, text: &str) {
+    if path.is_empty() {
+        warn!("output.file is not set");
+        return;
+    }
+    let path = resolve_path(path);
+    if let Err(e) = append(&path, format!("{text}\n").as_bytes()).await {
+        error!("Failed to append to {path:?}: {e}");
+    }
+}
+
+/// Both the path and the entry are strftime templates, so a new daily note
+/// starts each day.
+async fn append_note(output: &OutputConfig, text: &str) {
+    if output.notes.is_empty() {
+        warn!("output.notes is not set");
+        return;
+    }
+    let now = Zoned::now();
+    let (path, entry) = match (
+        strtime::format(output.notes.as_str(), &now),
+        strtime::format(output.notes_entry.as_str(), &now),
+    ) {
+        (Ok(path), Ok(entry)) => (resolve_path(&path), entry.replace("{text}", text)),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Invalid output.notes or output.notes_entry: {e}");
+            return;
+        }
+    };
+    if let Err(e) = append(&path, format!("{entry}\n").as_bytes()).await {
+        error!("Failed to append to {path:?}: {e}");
+    }
+}
+
+async fn append(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(data).await
+}
+
+/// Opened non-blocking, so a pipe without a reader is skipped instead of
+/// stalling the daemon.
+async fn write_fifo(path: &str, text: &str) {
+    if path.is_empty() {
+        warn!("output.fifo is not set");
+        return;
+    }
+    let path = resolve_path(path);
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&path)
+        .await;
+    let mut file = match file {
+        Ok(file) => file,
+        Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+            debug!("no reader on {path:?}");
+            return;
+        }
+        Err(e) => {
+            error!("Failed to open {path:?}: {e}");
+            return;
+        }
+    };
+    if let Err(e) = file.write_all(format!("{text}\n").as_bytes()).await {
+        error!("Failed to write to {path:?}: {e}");
+    }
+}
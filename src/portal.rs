@@ -0,0 +1,103 @@
+use crate::error::WayvoiceError;
+use ashpd::desktop::PersistMode;
+use ashpd::desktop::remote_desktop::{DeviceType, KeyState, RemoteDesktop};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{debug, error};
+
+const XK_RETURN: i32 = 0xff0d;
+const XK_TAB: i32 = 0xff09;
+const XK_BACKSPACE: i32 = 0xff08;
+
+/// Type `text` through the xdg-desktop-portal RemoteDesktop interface.
+///
+/// The first run shows the portal's permission dialog; the restore token it
+/// hands back is saved so later sessions start without asking again.
+pub async fn type_text(text: &str, key_delay: Duration) -> Result<(), WayvoiceError> {
+    let start = std::time::Instant::now();
+    let portal = RemoteDesktop::new().await.map_err(WayvoiceError::inject)?;
+    let session = portal
+        .create_session()
+        .await
+        .map_err(WayvoiceError::inject)?;
+
+    let token = load_restore_token().await;
+    portal
+        .select_devices(
+            &session,
+            DeviceType::Keyboard.into(),
+            token.as_deref(),
+            PersistMode::ExplicitlyRevoked,
+        )
+        .await
+        .map_err(WayvoiceError::inject)?
+        .response()
+        .map_err(WayvoiceError::inject)?;
+    let devices = portal
+        .start(&session, None)
+        .await
+        .map_err(WayvoiceError::inject)?
+        .response()
+        .map_err(|e| {
+            WayvoiceError::Inject(format!("RemoteDesktop session was not granted: {e}"))
+        })?;
+    if let Some(new_token) = devices.restore_token()
+        && token.as_deref() != Some(new_token)
+    {
+        save_restore_token(new_token).await;
+    }
+    debug!("portal_session: {:?}", start.elapsed());
+
+    for c in text.chars() {
+        let keysym = keysym(c);
+        portal
+            .notify_keyboard_keysym(&session, keysym, KeyState::Pressed)
+            .await
+            .map_err(WayvoiceError::inject)?;
+        portal
+            .notify_keyboard_keysym(&session, keysym, KeyState::Released)
+            .await
+            .map_err(WayvoiceError::inject)?;
+        if !key_delay.is_zero() {
+            tokio::time::sleep(key_delay).await;
+        }
+    }
+
+    session.close().await.map_err(WayvoiceError::inject)?;
+    Ok(())
+}
+
+/// Latin-1 keysyms equal their code point; everything else uses the
+/// Unicode keysym range.
+fn keysym(c: char) -> i32 {
+    match c {
+        '\n' => XK_RETURN,
+        '\t' => XK_TAB,
+        '\u{8}' => XK_BACKSPACE,
+        ' '..='~' | '\u{a0}'..='\u{ff}' => c as i32,
+        _ => 0x0100_0000 | c as i32,
+    }
+}
+
+fn restore_token_path() -> Option<PathBuf> {
+    Some(dirs::state_dir()?.join("wayvoice").join("portal-token"))
+}
+
+async fn load_restore_token() -> Option<String> {
+    let path = restore_token_path()?;
+    let token = tokio::fs::read_to_string(path).await.ok()?;
+    let token = token.trim();
+    (!token.is_empty()).then(|| token.to_string())
+}
+
+async fn save_restore_token(token: &str) {
+    let Some(path) = restore_token_path() else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = tokio::fs::create_dir_all(dir).await;
+    }
+    if let Err(e) = tokio::fs::write(&path, token).await {
+        error!("Failed to save portal restore token: {e}");
+    }
+}
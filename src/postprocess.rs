@@ -0,0 +1,138 @@
+use crate::config::{Config, Provider, loggable};
+use crate::error::WayvoiceError;
+use crate::http::send_with_retry;
+use crate::text::format_commit_message;
+use crate::transcription::{api_key_for, default_base_url};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, warn};
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Deserialize)]
+struct ChatChoice {
+    message: ChatMessage,
+}
+
+#[derive(Deserialize)]
+struct ChatMessage {
+    content: String,
+}
+
+/// System prompt for `commit_message`.
+const COMMIT_PROMPT: &str = "Rewrite this dictated description of a code change as a \
+     conventional commit message: a subject line like `type(scope): summary` in the \
+     imperative mood and at most 72 characters, then, if there is more to say, a blank \
+     line and a short body explaining what changed and why. Reply with the message only.";
+
+/// Rewrite `text` with a chat completion using `prompt` as the system
+/// prompt.
+async fn postprocess(text: &str, prompt: &str, config: &Config) -> Result<String, WayvoiceError> {
+    let settings = &config.postprocess;
+    let provider = settings.provider.unwrap_or(match config.provider {
+        Provider::Openai => Provider::Openai,
+        _ => Provider::Groq,
+    });
+
+    let base_url = if settings.base_url.is_empty() {
+        default_base_url(provider)
+    } else {
+        &settings.base_url
+    };
+    let endpoint = format!("{}/chat/completions", base_url.trim_end_matches('/'));
+    let api_key = match api_key_for(config, provider).await {
+        Ok(key) => Some(key),
+        Err(_) if !settings.base_url.is_empty() => None,
+        Err(e) => return Err(e),
+    };
+    let model = if settings.model.is_empty() {
+        default_model(provider)
+    } else {
+        &settings.model
+    };
+
+    let body = json!({
+        "model": model,
+        "temperature": 0,
+        "messages": [
+            {"role": "system", "content": prompt},
+            {"role": "user", "content": text},
+        ],
+    });
+
+    debug!("postprocess endpoint={endpoint} model={model}");
+    let start = std::time::Instant::now();
+    let response = send_with_retry("Post-processing", config.request_retries, || {
+        let mut request = config.http.post(&endpoint).json(&body);
+        if let Some(api_key) = &api_key {
+            request = request.bearer_auth(api_key);
+        }
+        Ok(request)
+    })
+    .await?;
+    debug!("postprocess_call: {:?}", start.elapsed());
+
+    if !response.status().is_success() {
+        return Err(WayvoiceError::from_response("Post-processing", response).await);
+    }
+
+    let result: ChatResponse = response.json().await?;
+    let content = result
+        .choices
+        .into_iter()
+        .next()
+        .ok_or_else(|| WayvoiceError::provider("Post-processing returned no choices"))?
+        .message
+        .content;
+    Ok(content.trim().to_string())
+}
+
+fn default_model(provider: Provider) -> &'static str {
+    match provider {
+        Provider::Openai => "gpt-4o-mini",
+        _ => "llama-3.3-70b-versatile",
+    }
+}
+
+/// Post-process `text` if a prompt is configured, keeping the original
+/// transcript when the request fails.
+pub async fn apply_postprocess(text: String, config: &Config) -> String {
+    if config.commit_message {
+        return commit_message(text, config).await;
+    }
+    if config.postprocess.prompt.is_empty() || text.is_empty() {
+        return text;
+    }
+    match postprocess(&text, &config.postprocess.prompt, config).await {
+        Ok(processed) => {
+            debug!(
+                "postprocessed: {}",
+                loggable(&processed, config.log_transcripts)
+            );
+            processed
+        }
+        Err(e) => {
+            warn!("{e}, using the raw transcript");
+            text
+        }
+    }
+}
+
+/// Have the chat model write a commit message from `text`, then enforce the
+/// subject length and body wrapping it may have ignored.
+async fn commit_message(text: String, config: &Config) -> String {
+    if text.is_empty() {
+        return text;
+    }
+    let message = match postprocess(&text, COMMIT_PROMPT, config).await {
+        Ok(message) => message,
+        Err(e) => {
+            warn!("{e}, using the raw transcript");
+            text
+        }
+    };
+    format_commit_message(&message)
+}
@@ -0,0 +1,631 @@
+use crate::config::{Config, RecorderBackend};
+use crate::error::WayvoiceError;
+use std::collections::VecDeque;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::process::{Child, ChildStdout, Command};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+
+/// How long pw-record gets to flush its output after SIGINT.
+const STOP_GRACE: Duration = Duration::from_millis(500);
+
+pub const SAMPLE_RATE: u32 = 16000;
+
+/// Floor for reported input levels, so digital silence isn't `-inf`.
+const SILENCE_DB: f32 = -99.0;
+
+/// Captures 16 kHz mono audio into memory between `start()` and `stop()`.
+pub struct Recorder {
+    backend: Backend,
+}
+
+enum Backend {
+    PwRecord(PwRecord),
+    #[cfg(feature = "pipewire")]
+    Native(native::Native),
+}
+
+/// Where capture backends deliver samples. Outside a recording the most
+/// recent `preroll_len` samples are kept so `start()` can prepend them.
+struct CaptureBuffer {
+    recording: bool,
+    level_db: f32,
+    samples: Vec<i16>,
+    preroll: VecDeque<i16>,
+    preroll_len: usize,
+}
+
+type SharedBuffer = Arc<Mutex<CaptureBuffer>>;
+
+/// Which PipeWire node to capture.
+#[derive(Debug, Clone, Default)]
+struct Target {
+    /// Node name or serial; empty for the default.
+    device: String,
+    /// Record what a sink plays instead of a source.
+    monitor: bool,
+}
+
+impl CaptureBuffer {
+    fn new(preroll_len: usize) -> Self {
+        Self {
+            recording: false,
+            level_db: SILENCE_DB,
+            samples: Vec::new(),
+            preroll: VecDeque::with_capacity(preroll_len),
+            preroll_len,
+        }
+    }
+
+    fn push(&mut self, chunk: &[i16]) {
+        self.level_db = level_db(chunk);
+        if self.recording {
+            self.samples.extend_from_slice(chunk);
+        } else if self.preroll_len > 0 {
+            self.preroll.extend(chunk);
+            let excess = self.preroll.len().saturating_sub(self.preroll_len);
+            self.preroll.drain(..excess);
+        }
+    }
+
+    fn start(&mut self) {
+        self.samples.clear();
+        self.samples.extend(self.preroll.drain(..));
+        self.recording = true;
+    }
+
+    fn take(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+
+    fn stop(&mut self) -> Vec<i16> {
+        self.recording = false;
+        self.take()
+    }
+}
+
+/// Capture through a `pw-record` child streaming raw samples over stdout.
+/// With pre-roll enabled the child stays alive between recordings.
+struct PwRecord {
+    buffer: SharedBuffer,
+    process: Option<PwRecordProcess>,
+    persistent: bool,
+    target: Target,
+}
+
+struct PwRecordProcess {
+    child: Child,
+    reader: JoinHandle<()>,
+}
+
+impl PwRecord {
+    fn new(preroll_len: usize, target: Target) -> Self {
+        let mut pw_record = Self {
+            buffer: Arc::new(Mutex::new(CaptureBuffer::new(preroll_len))),
+            process: None,
+            persistent: preroll_len > 0,
+            target,
+        };
+        if pw_record.persistent
+            && let Err(e) = pw_record.ensure_running()
+        {
+            error!("{e}");
+        }
+        pw_record
+    }
+
+    /// Spawn pw-record unless a live child is already feeding the buffer.
+    fn ensure_running(&mut self) -> Result<(), WayvoiceError> {
+        if let Some(process) = &mut self.process {
+            if matches!(process.child.try_wait(), Ok(None)) {
+                return Ok(());
+            }
+            debug!("pw-record exited, respawning");
+        }
+
+        let mut cmd = Command::new("pw-record");
+        cmd.args([
+            "--format",
+            "s16",
+            "--rate",
+            &SAMPLE_RATE.to_string(),
+            "--channels",
+            "1",
+            "--raw",
+        ]);
+        if !self.target.device.is_empty() {
+            cmd.args(["--target", &self.target.device]);
+        }
+        if self.target.monitor {
+            cmd.args(["-P", "{ stream.capture.sink = true }"]);
+        }
+        let mut child = cmd
+            .arg("-")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| WayvoiceError::Audio(format!("Failed to start pw-record: {e}")))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| WayvoiceError::audio("pw-record has no stdout"))?;
+        let reader = tokio::spawn(read_samples(stdout, self.buffer.clone()));
+        self.process = Some(PwRecordProcess { child, reader });
+        Ok(())
+    }
+
+    async fn kill(&mut self) {
+        if let Some(mut process) = self.process.take() {
+            let _ = process.child.kill().await;
+            process.reader.abort();
+        }
+    }
+
+    async fn shutdown(&mut self) {
+        if let Some(process) = self.process.take() {
+            stop_gracefully(process.child).await;
+            let _ = process.reader.await;
+        }
+    }
+}
+
+impl Recorder {
+    /// Record from `audio_device`, or the default microphone.
+    pub fn new(config: &Config) -> Self {
+        Self::for_device(config, &config.audio_device)
+    }
+
+    /// Record from `device` (a source; empty for the default one).
+    pub fn for_device(config: &Config, device: &str) -> Self {
+        let preroll_len = (config.preroll_ms * SAMPLE_RATE as u64 / 1000) as usize;
+        let target = Target {
+            device: device.to_string(),
+            monitor: false,
+        };
+        Self::open(config, preroll_len, target)
+    }
+
+    /// Record what `device` plays (a sink; empty for the default one), i.e.
+    /// system audio.
+    pub fn monitor(config: &Config, device: &str) -> Self {
+        let target = Target {
+            device: device.to_string(),
+            monitor: true,
+        };
+        Self::open(config, 0, target)
+    }
+
+    fn open(config: &Config, preroll_len: usize, target: Target) -> Self {
+        let backend = match config.recorder {
+            RecorderBackend::PwRecord => Backend::PwRecord(PwRecord::new(preroll_len, target)),
+            RecorderBackend::Pipewire => native_backend(preroll_len, target),
+        };
+        Self { backend }
+    }
+
+    fn buffer(&self) -> &SharedBuffer {
+        match &self.backend {
+            Backend::PwRecord(pw_record) => &pw_record.buffer,
+            #[cfg(feature = "pipewire")]
+            Backend::Native(native) => &native.buffer,
+        }
+    }
+
+    /// Input level of the most recently captured chunk, in dBFS.
+    pub fn level_db(&self) -> f32 {
+        self.buffer().lock().unwrap().level_db
+    }
+
+    pub async fn start(&mut self) -> Result<(), WayvoiceError> {
+        match &mut self.backend {
+            Backend::PwRecord(pw_record) => pw_record.ensure_running()?,
+            #[cfg(feature = "pipewire")]
+            Backend::Native(native) => native.ensure_running().map_err(WayvoiceError::Audio)?,
+        }
+        self.buffer().lock().unwrap().start();
+        Ok(())
+    }
+
+    /// Stop capturing and return the recording as a WAV file.
+    pub async fn stop(&mut self) -> Vec<u8> {
+        wav_bytes(&self.stop_samples().await)
+    }
+
+    /// Stop capturing and return the samples since the last `take_samples`.
+    pub async fn stop_samples(&mut self) -> Vec<i16> {
+        match &mut self.backend {
+            // Let pw-record flush what it has buffered before we collect it
+            Backend::PwRecord(pw_record) if !pw_record.persistent => pw_record.shutdown().await,
+            #[cfg(feature = "pipewire")]
+            Backend::Native(native) if !native.persistent => native.close(),
+            _ => {}
+        }
+        self.buffer().lock().unwrap().stop()
+    }
+
+    /// The samples captured so far, leaving the recording running, so long
+    /// recordings can be handled in chunks.
+    pub fn take_samples(&self) -> Vec<i16> {
+        self.buffer().lock().unwrap().take()
+    }
+
+    /// Stop capturing for good, releasing the device.
+    pub async fn close(mut self) {
+        match &mut self.backend {
+            Backend::PwRecord(pw_record) => pw_record.kill().await,
+            #[cfg(feature = "pipewire")]
+            Backend::Native(native) => native.close(),
+        }
+    }
+
+    /// Stop capturing and throw the audio away.
+    pub async fn cancel(&mut self) {
+        match &mut self.backend {
+            Backend::PwRecord(pw_record) if !pw_record.persistent => pw_record.kill().await,
+            #[cfg(feature = "pipewire")]
+            Backend::Native(native) if !native.persistent => native.close(),
+            _ => {}
+        }
+        self.buffer().lock().unwrap().stop();
+    }
+}
+
+/// The first of `preferred_devices` that is plugged in, or else
+/// `audio_device`. Entries match a source's node name or serial exactly, or
+/// part of its description case-insensitively, e.g. "H390".
+pub async fn pick_device(config: &Config) -> String {
+    if config.preferred_devices.is_empty() {
+        return config.audio_device.clone();
+    }
+    let sources = list_sources().await;
+    config
+        .preferred_devices
+        .iter()
+        .find_map(|wanted| {
+            sources
+                .iter()
+                .find(|source| source.matches(wanted))
+                .map(|source| source.name.clone())
+        })
+        .unwrap_or_else(|| config.audio_device.clone())
+}
+
+/// A PipeWire capture device, as `pw-dump` describes it.
+struct Source {
+    name: String,
+    serial: String,
+    description: String,
+}
+
+impl Source {
+    fn matches(&self, wanted: &str) -> bool {
+        self.name == wanted
+            || self.serial == wanted
+            || self
+                .description
+                .to_lowercase()
+                .contains(&wanted.to_lowercase())
+    }
+}
+
+/// Audio sources PipeWire currently has, or none if `pw-dump` fails.
+async fn list_sources() -> Vec<Source> {
+    let output = match Command::new("pw-dump").output().await {
+        Ok(output) if output.status.success() => output.stdout,
+        Ok(output) => {
+            warn!("pw-dump failed ({})", output.status);
+            return Vec::new();
+        }
+        Err(e) => {
+            warn!("Failed to run pw-dump: {e}");
+            return Vec::new();
+        }
+    };
+    let objects: Vec<serde_json::Value> = serde_json::from_slice(&output).unwrap_or_default();
+    objects
+        .iter()
+        .filter_map(|object| object.pointer("/info/props"))
+        .filter(|props| props["media.class"] == "Audio/Source")
+        .map(|props| {
+            let text = |key: &str| match &props[key] {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Number(n) => n.to_string(),
+                _ => String::new(),
+            };
+            Source {
+                name: text("node.name"),
+                serial: text("object.serial"),
+                description: text("node.description"),
+            }
+        })
+        .collect()
+}
+
+/// Whether WirePlumber reports `device` (empty for the default source) as
+/// muted. Unknown, e.g. without `wpctl`, counts as not muted.
+pub async fn is_muted(device: &str) -> bool {
+    let target = if device.is_empty() {
+        "@DEFAULT_AUDIO_SOURCE@"
+    } else {
+        device
+    };
+    match Command::new("wpctl")
+        .args(["get-volume", target])
+        .output()
+        .await
+    {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).contains("[MUTED]")
+        }
+        Ok(output) => {
+            debug!("wpctl get-volume {target}: {}", output.status);
+            false
+        }
+        Err(e) => {
+            debug!("wpctl: {e}");
+            false
+        }
+    }
+}
+
+async fn read_samples(mut stdout: ChildStdout, buffer: SharedBuffer) {
+    let mut buf = vec![0u8; 4096];
+    let mut samples = Vec::with_capacity(buf.len() / 2);
+    // A read can end mid-sample; carry the odd byte over to the next one
+    let mut carry: Option<u8> = None;
+    loop {
+        let n = match stdout.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        let mut bytes = &buf[..n];
+        samples.clear();
+        if let Some(low) = carry.take() {
+            samples.push(i16::from_le_bytes([low, bytes[0]]));
+            bytes = &bytes[1..];
+        }
+        let chunks = bytes.chunks_exact(2);
+        carry = chunks.remainder().first().copied();
+        samples.extend(chunks.map(|b| i16::from_le_bytes([b[0], b[1]])));
+        buffer.lock().unwrap().push(&samples);
+    }
+}
+
+/// RMS level of `samples` in dBFS.
+pub(crate) fn level_db(samples: &[i16]) -> f32 {
+    if samples.is_empty() {
+        return SILENCE_DB;
+    }
+    let sum: f64 = samples.iter().map(|&s| (s as f64).powi(2)).sum();
+    let rms = (sum / samples.len() as f64).sqrt() / i16::MAX as f64;
+    (20.0 * rms.log10()).max(SILENCE_DB as f64) as f32
+}
+
+/// Interrupt pw-record so it flushes its buffers, killing it if it hangs.
+async fn stop_gracefully(mut child: Child) {
+    if let Some(pid) = child.id() {
+        // SAFETY: pid belongs to a child we spawned and haven't reaped yet
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGINT);
+        }
+        if tokio::time::timeout(STOP_GRACE, child.wait()).await.is_ok() {
+            return;
+        }
+        debug!("pw-record ignored SIGINT, killing");
+    }
+    let _ = child.kill().await;
+    let _ = child.wait().await;
+}
+
+/// Wrap 16 kHz mono s16 samples in a WAV container.
+pub fn wav_bytes(samples: &[i16]) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // byte rate
+    wav.extend_from_slice(&2u16.to_le_bytes()); // block align
+    wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+#[cfg(feature = "pipewire")]
+fn native_backend(preroll_len: usize, target: Target) -> Backend {
+    match native::Native::new(preroll_len, target.clone()) {
+        Ok(native) => Backend::Native(native),
+        Err(e) => {
+            warn!("PipeWire capture unavailable ({e}), falling back to pw-record");
+            Backend::PwRecord(PwRecord::new(preroll_len, target))
+        }
+    }
+}
+
+#[cfg(not(feature = "pipewire"))]
+fn native_backend(preroll_len: usize, target: Target) -> Backend {
+    warn!("Built without the `pipewire` feature, falling back to pw-record");
+    Backend::PwRecord(PwRecord::new(preroll_len, target))
+}
+
+#[cfg(feature = "pipewire")]
+mod native {
+    use super::{CaptureBuffer, SharedBuffer, Target};
+    use pipewire as pw;
+    use pw::{properties::properties, spa};
+    use std::sync::{Arc, Mutex};
+    use std::thread::JoinHandle;
+
+    /// Capture through libpipewire. With pre-roll enabled the stream stays
+    /// open between recordings, so starting one doesn't pay connection
+    /// setup; otherwise it only runs while recording.
+    pub struct Native {
+        pub buffer: SharedBuffer,
+        pub persistent: bool,
+        capture: Option<Capture>,
+        target: Target,
+    }
+
+    impl Native {
+        pub fn new(preroll_len: usize, target: Target) -> Result<Self, String> {
+            let mut native = Self {
+                buffer: Arc::new(Mutex::new(CaptureBuffer::new(preroll_len))),
+                persistent: preroll_len > 0,
+                capture: None,
+                target,
+            };
+            if native.persistent {
+                native.ensure_running()?;
+            }
+            Ok(native)
+        }
+
+        pub fn ensure_running(&mut self) -> Result<(), String> {
+            if self.capture.is_none() {
+                self.capture = Some(Capture::spawn(self.buffer.clone(), self.target.clone())?);
+            }
+            Ok(())
+        }
+
+        /// Close the stream, releasing the microphone.
+        pub fn close(&mut self) {
+            self.capture = None;
+        }
+    }
+
+    /// A PipeWire input stream on its own thread, closed on drop.
+    struct Capture {
+        quit: pw::channel::Sender<()>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl Capture {
+        fn spawn(buffer: SharedBuffer, target: Target) -> Result<Self, String> {
+            let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+            let (quit, quit_rx) = pw::channel::channel();
+
+            let thread = std::thread::Builder::new()
+                .name("wayvoice-capture".to_string())
+                .spawn(move || {
+                    if let Err(e) = run(buffer, &target, &ready_tx, quit_rx) {
+                        let _ = ready_tx.send(Err(e.to_string()));
+                    }
+                })
+                .map_err(|e| e.to_string())?;
+
+            ready_rx
+                .recv()
+                .map_err(|_| "capture thread exited".to_string())??;
+            Ok(Self {
+                quit,
+                thread: Some(thread),
+            })
+        }
+    }
+
+    impl Drop for Capture {
+        fn drop(&mut self) {
+            let _ = self.quit.send(());
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    fn run(
+        buffer: SharedBuffer,
+        target: &Target,
+        ready: &std::sync::mpsc::Sender<Result<(), String>>,
+        quit: pw::channel::Receiver<()>,
+    ) -> Result<(), pw::Error> {
+        pw::init();
+
+        let mainloop = pw::main_loop::MainLoopRc::new(None)?;
+        let _quit = quit.attach(mainloop.loop_(), {
+            let mainloop = mainloop.clone();
+            move |()| mainloop.quit()
+        });
+        let context = pw::context::ContextRc::new(&mainloop, None)?;
+        let core = context.connect_rc(None)?;
+
+        let mut props = properties! {
+            *pw::keys::MEDIA_TYPE => "Audio",
+            *pw::keys::MEDIA_CATEGORY => "Capture",
+            *pw::keys::MEDIA_ROLE => "Communication",
+        };
+        if !target.device.is_empty() {
+            props.insert(*pw::keys::TARGET_OBJECT, target.device.as_str());
+        }
+        if target.monitor {
+            props.insert(*pw::keys::STREAM_CAPTURE_SINK, "true");
+        }
+        let stream = pw::stream::StreamBox::new(&core, "wayvoice", props)?;
+
+        let _listener = stream
+            .add_local_listener_with_user_data(buffer)
+            .process(|stream, capture| {
+                let Some(mut buffer) = stream.dequeue_buffer() else {
+                    return;
+                };
+                let datas = buffer.datas_mut();
+                let Some(data) = datas.first_mut() else {
+                    return;
+                };
+                let size = data.chunk().size() as usize;
+                if let Some(bytes) = data.data() {
+                    let bytes = &bytes[..size.min(bytes.len())];
+                    let samples: Vec<i16> = bytes
+                        .chunks_exact(2)
+                        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                        .collect();
+                    capture.lock().unwrap().push(&samples);
+                }
+            })
+            .register()?;
+
+        // Ask PipeWire to convert to what the transcription APIs expect
+        let mut audio_info = spa::param::audio::AudioInfoRaw::new();
+        audio_info.set_format(spa::param::audio::AudioFormat::S16LE);
+        audio_info.set_rate(super::SAMPLE_RATE);
+        audio_info.set_channels(1);
+        let obj = spa::pod::Object {
+            type_: spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+            id: spa::param::ParamType::EnumFormat.as_raw(),
+            properties: audio_info.into(),
+        };
+        let values: Vec<u8> = spa::pod::serialize::PodSerializer::serialize(
+            std::io::Cursor::new(Vec::new()),
+            &spa::pod::Value::Object(obj),
+        )
+        .map_err(|_| pw::Error::CreationFailed)?
+        .0
+        .into_inner();
+        let mut params = [spa::pod::Pod::from_bytes(&values).ok_or(pw::Error::CreationFailed)?];
+
+        stream.connect(
+            spa::utils::Direction::Input,
+            None,
+            pw::stream::StreamFlags::AUTOCONNECT
+                | pw::stream::StreamFlags::MAP_BUFFERS
+                | pw::stream::StreamFlags::RT_PROCESS,
+            &mut params,
+        )?;
+
+        let _ = ready.send(Ok(()));
+        mainloop.run();
+        Ok(())
+    }
+}
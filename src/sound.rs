@@ -0,0 +1,43 @@
+use crate::config::{SoundConfig, resolve_path};
+use tokio::process::Command;
+use tracing::{debug, error};
+
+/// Moments in a dictation that can have an earcon.
+#[derive(Debug, Clone, Copy)]
+pub enum Cue {
+    Start,
+    Stop,
+    Error,
+}
+
+/// Play the sound configured for `cue` without waiting for it to finish.
+pub fn play(config: &SoundConfig, cue: Cue) {
+    if !config.enabled {
+        return;
+    }
+    let sound = match cue {
+        Cue::Start => &config.start,
+        Cue::Stop => &config.stop,
+        Cue::Error => &config.error,
+    };
+    if sound.is_empty() {
+        return;
+    }
+    debug!("sound {cue:?}: {sound}");
+
+    let mut cmd = if sound.contains('/') {
+        let mut cmd = Command::new("pw-play");
+        cmd.arg(resolve_path(sound));
+        cmd
+    } else {
+        let mut cmd = Command::new("canberra-gtk-play");
+        cmd.args(["--id", sound, "--description", "wayvoice"]);
+        cmd
+    };
+    match cmd.spawn() {
+        Ok(mut child) => {
+            tokio::spawn(async move { child.wait().await });
+        }
+        Err(e) => error!("Failed to play {sound:?}: {e}"),
+    }
+}
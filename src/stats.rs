@@ -0,0 +1,200 @@
+use crate::config::CostConfig;
+use jiff::tz::TimeZone;
+use jiff::{Timestamp, Zoned};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::error;
+
+/// Serializes read-modify-write of the stats file.
+static LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Counters for one provider.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Totals {
+    pub dictations: u64,
+    pub words: u64,
+    pub audio_secs: f64,
+    /// Sum of end-to-end latencies, over `timed` dictations.
+    pub latency_ms: u64,
+    pub timed: u64,
+}
+
+/// What `stats.json` holds: the current daemon run, the calendar month and
+/// all time, by provider.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stats {
+    pub session_start: Option<Timestamp>,
+    pub session: BTreeMap<String, Totals>,
+    /// `YYYY-MM` that `this_month` counts; an older one is stale.
+    pub month: String,
+    pub this_month: BTreeMap<String, Totals>,
+    pub lifetime: BTreeMap<String, Totals>,
+}
+
+impl Stats {
+    /// This month's totals, or nothing if none were recorded yet.
+    pub fn current_month(&self) -> Option<&BTreeMap<String, Totals>> {
+        (self.month == current_month()).then_some(&self.this_month)
+    }
+}
+
+fn current_month() -> String {
+    Zoned::now().strftime("%Y-%m").to_string()
+}
+
+/// Estimated USD for the audio sent to each provider.
+pub fn cost(by_provider: &BTreeMap<String, Totals>, prices: &CostConfig) -> f64 {
+    by_provider
+        .iter()
+        .map(|(provider, totals)| totals.audio_secs / 60.0 * prices.price(provider))
+        .sum()
+}
+
+/// Estimated USD spent this calendar month.
+pub fn month_cost(prices: &CostConfig) -> f64 {
+    load()
+        .current_month()
+        .map_or(0.0, |month| cost(month, prices))
+}
+
+fn stats_path() -> Option<PathBuf> {
+    Some(dirs::state_dir()?.join("wayvoice").join("stats.json"))
+}
+
+pub fn load() -> Stats {
+    stats_path()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save(stats: &Stats) -> std::io::Result<()> {
+    let Some(path) = stats_path() else {
+        return Ok(());
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_vec_pretty(stats)?)
+}
+
+/// Reset the session counters; called when the daemon starts.
+pub fn start_session() {
+    let mut stats = load();
+    stats.session_start = Some(Timestamp::now());
+    stats.session.clear();
+    if let Err(e) = save(&stats) {
+        error!("Failed to write stats: {e}");
+    }
+}
+
+/// Count one dictation. `latency` is from stopping the recording to the text
+/// being delivered; absent for live streaming.
+pub async fn record(provider: &str, words: usize, audio_secs: f64, latency: Option<Duration>) {
+    let _guard = LOCK.lock().await;
+    let mut stats = load();
+    let month = current_month();
+    if stats.month != month {
+        stats.month = month;
+        stats.this_month.clear();
+    }
+    for totals in [
+        &mut stats.session,
+        &mut stats.this_month,
+        &mut stats.lifetime,
+    ] {
+        let totals = totals.entry(provider.to_string()).or_default();
+        totals.dictations += 1;
+        totals.words += words as u64;
+        totals.audio_secs += audio_secs;
+        if let Some(latency) = latency {
+            totals.latency_ms += latency.as_millis() as u64;
+            totals.timed += 1;
+        }
+    }
+    if let Err(e) = save(&stats) {
+        error!("Failed to write stats: {e}");
+    }
+}
+
+/// Length of a WAV recording, from its byte rate.
+pub fn wav_secs(wav: &[u8]) -> f64 {
+    let Some(rate) = wav.get(28..32) else {
+        return 0.0;
+    };
+    let byte_rate = u32::from_le_bytes(rate.try_into().expect("4 bytes"));
+    if byte_rate == 0 {
+        return 0.0;
+    }
+    wav.len().saturating_sub(44) as f64 / byte_rate as f64
+}
+
+/// Print the session, month and lifetime tables, with what the audio cost
+/// at `prices`.
+pub fn show(prices: &CostConfig) {
+    let stats = load();
+    match stats.session_start {
+        Some(start) => println!(
+            "Session (since {})",
+            start
+                .to_zoned(TimeZone::system())
+                .strftime("%Y-%m-%d %H:%M:%S")
+        ),
+        None => println!("Session"),
+    }
+    print_table(&stats.session, prices);
+    println!();
+    println!("This month");
+    let empty = BTreeMap::new();
+    let month = stats.current_month().unwrap_or(&empty);
+    print_table(month, prices);
+    if prices.monthly_budget > 0.0 {
+        println!(
+            "  ${:.2} of the ${:.2} budget spent",
+            cost(month, prices),
+            prices.monthly_budget
+        );
+    }
+    println!();
+    println!("Lifetime");
+    print_table(&stats.lifetime, prices);
+}
+
+fn print_table(by_provider: &BTreeMap<String, Totals>, prices: &CostConfig) {
+    if by_provider.is_empty() {
+        println!("  no dictations yet");
+        return;
+    }
+    println!(
+        "  {:<12} {:>10} {:>8} {:>10} {:>12} {:>9}",
+        "provider", "dictations", "words", "audio", "avg latency", "cost"
+    );
+    for (provider, totals) in by_provider {
+        let latency = match totals.latency_ms.checked_div(totals.timed) {
+            Some(ms) => format!("{ms}ms"),
+            None => "-".to_string(),
+        };
+        let cost = totals.audio_secs / 60.0 * prices.price(provider);
+        println!(
+            "  {provider:<12} {:>10} {:>8} {:>10} {latency:>12} {:>9}",
+            totals.dictations,
+            totals.words,
+            format_secs(totals.audio_secs),
+            format!("${cost:.2}"),
+        );
+    }
+}
+
+fn format_secs(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
@@ -0,0 +1,259 @@
+use crate::config::{Config, HooksConfig, InjectConfig, OutputConfig, loggable};
+use crate::daemon::Event;
+use crate::error::WayvoiceError;
+use crate::history::{self, Entry};
+use crate::hooks::{self, Hook};
+use crate::output;
+use crate::stats;
+use crate::text::{Replacements, apply_replacements};
+use crate::transcription::deepgram_api_key;
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
+use tokio::process::{Child, ChildStdout, Command};
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tracing::debug;
+
+const DEEPGRAM_LISTEN_URL: &str = "wss://api.deepgram.com/v1/listen";
+
+#[derive(Deserialize)]
+struct LiveResponse {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    is_final: bool,
+    channel: Option<LiveChannel>,
+}
+
+#[derive(Deserialize)]
+struct LiveChannel {
+    alternatives: Vec<LiveAlternative>,
+}
+
+#[derive(Deserialize)]
+struct LiveAlternative {
+    transcript: String,
+}
+
+/// A live dictation: raw PCM from `pw-record` is forwarded to Deepgram's
+/// WebSocket API and finalized segments are injected as they arrive.
+pub struct StreamingSession {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<Result<usize, WayvoiceError>>,
+}
+
+impl StreamingSession {
+    /// Record from `device` (empty for the default source). Finalized
+    /// segments are also announced on `events`.
+    pub async fn start(
+        config: &Config,
+        device: &str,
+        events: broadcast::Sender<Event>,
+    ) -> Result<Self, WayvoiceError> {
+        let api_key = deepgram_api_key(config).await?;
+
+        let mut url = reqwest::Url::parse(DEEPGRAM_LISTEN_URL).map_err(WayvoiceError::provider)?;
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("encoding", "linear16")
+                .append_pair("sample_rate", "16000")
+                .append_pair("channels", "1")
+                .append_pair("punctuate", "true")
+                .append_pair("smart_format", "true")
+                .append_pair("interim_results", "false");
+            if !config.model.is_empty() {
+                query.append_pair("model", &config.model);
+            }
+            if !config.language.is_empty() {
+                query.append_pair("language", &config.language);
+            }
+        }
+
+        let mut request = url
+            .as_str()
+            .into_client_request()
+            .map_err(WayvoiceError::provider)?;
+        let auth = format!("Token {api_key}")
+            .parse()
+            .map_err(WayvoiceError::config)?;
+        request.headers_mut().insert("Authorization", auth);
+
+        let connect_start = std::time::Instant::now();
+        let (ws, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .map_err(|e| WayvoiceError::Provider(format!("Could not connect to Deepgram: {e}")))?;
+        debug!("stream_connect: {:?}", connect_start.elapsed());
+
+        let mut recorder = Command::new("pw-record");
+        recorder.args([
+            "--format",
+            "s16",
+            "--rate",
+            "16000",
+            "--channels",
+            "1",
+            "--raw",
+        ]);
+        if !device.is_empty() {
+            recorder.args(["--target", device]);
+        }
+        let mut recorder = recorder
+            .arg("-")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| WayvoiceError::Audio(format!("Failed to start pw-record: {e}")))?;
+        let stdout = recorder
+            .stdout
+            .take()
+            .ok_or_else(|| WayvoiceError::audio("pw-record has no stdout"))?;
+
+        let (stop, stop_rx) = oneshot::channel();
+        let output = Output {
+            replacements: config.replacements_for(None).clone(),
+            inject: config.inject.clone(),
+            output: config.output.clone(),
+            events,
+            history: config.history,
+            log_transcripts: config.log_transcripts,
+            hooks: config.hooks.clone(),
+        };
+        let task = tokio::spawn(run_session(ws, recorder, stdout, stop_rx, output));
+
+        Ok(Self { stop, task })
+    }
+
+    /// Stop capturing and wait for the provider to flush remaining segments.
+    /// Returns how many words were delivered.
+    pub async fn finish(self) -> Result<usize, WayvoiceError> {
+        let _ = self.stop.send(());
+        self.task.await.map_err(WayvoiceError::provider)?
+    }
+
+    /// Drop the session without injecting anything further.
+    pub fn abort(self) {
+        self.task.abort();
+    }
+}
+
+/// Where finalized segments go.
+struct Output {
+    replacements: Replacements,
+    inject: InjectConfig,
+    output: OutputConfig,
+    events: broadcast::Sender<Event>,
+    history: bool,
+    log_transcripts: bool,
+    hooks: HooksConfig,
+}
+
+async fn run_session<S>(
+    ws: tokio_tungstenite::WebSocketStream<S>,
+    mut recorder: Child,
+    mut stdout: ChildStdout,
+    mut stop: oneshot::Receiver<()>,
+    output: Output,
+) -> Result<usize, WayvoiceError>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let (mut sink, mut stream) = ws.split();
+    let mut words = 0;
+    let mut audio_bytes = 0;
+    let mut buf = vec![0u8; 3200];
+
+    loop {
+        tokio::select! {
+            _ = &mut stop => break,
+            read = stdout.read(&mut buf) => {
+                let n = read.map_err(WayvoiceError::audio)?;
+                if n == 0 {
+                    break;
+                }
+                audio_bytes += n;
+                sink.send(Message::Binary(buf[..n].to_vec().into()))
+                    .await
+                    .map_err(WayvoiceError::provider)?;
+            }
+            msg = stream.next() => match msg {
+                Some(msg) => {
+                    let msg = msg.map_err(WayvoiceError::provider)?;
+                    handle_message(msg, &output, &mut words).await;
+                }
+                None => return Err(WayvoiceError::provider("Stream closed by provider")),
+            },
+        }
+    }
+
+    let _ = recorder.kill().await;
+    sink.send(Message::Text(r#"{"type":"CloseStream"}"#.into()))
+        .await
+        .map_err(WayvoiceError::provider)?;
+
+    // Deepgram sends the remaining finals, then closes the socket
+    while let Some(msg) = stream.next().await {
+        let msg = msg.map_err(WayvoiceError::provider)?;
+        if msg.is_close() {
+            break;
+        }
+        handle_message(msg, &output, &mut words).await;
+    }
+    if words > 0 {
+        // 16 kHz mono s16
+        stats::record("deepgram", words, audio_bytes as f64 / 32000.0, None).await;
+    }
+    Ok(words)
+}
+
+/// Deliver a finalized segment, adding its words to `words`.
+async fn handle_message(msg: Message, output: &Output, words: &mut usize) {
+    let Message::Text(payload) = msg else {
+        return;
+    };
+    let response: LiveResponse = match serde_json::from_str(&payload) {
+        Ok(r) => r,
+        Err(e) => {
+            debug!("unparsed stream message: {e}");
+            return;
+        }
+    };
+    if response.kind != "Results" || !response.is_final {
+        return;
+    }
+    let Some(alternative) = response
+        .channel
+        .and_then(|c| c.alternatives.into_iter().next())
+    else {
+        return;
+    };
+
+    let raw = alternative.transcript.trim();
+    if raw.is_empty() {
+        return;
+    }
+    debug!("segment raw: {}", loggable(raw, output.log_transcripts));
+    let text = apply_replacements(raw, &output.replacements);
+    debug!(
+        "segment replaced: {}",
+        loggable(&text, output.log_transcripts)
+    );
+    if output.history {
+        history::record(&Entry::new("deepgram", None, None, raw, &text)).await;
+    }
+
+    // Separate consecutive segments so words don't run together
+    output::deliver(&text, *words > 0, &output.output, &output.inject).await;
+    *words += text.split_whitespace().count();
+    hooks::run(&output.hooks, Hook::Text, &text);
+    let _ = output.events.send(Event::Text {
+        text,
+        language: None,
+    });
+}
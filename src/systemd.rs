@@ -0,0 +1,174 @@
+use std::os::fd::FromRawFd;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::debug;
+
+/// Tell systemd about the service state (`READY=1`, `STOPPING=1`, ...) when
+/// running as a `Type=notify` unit; does nothing otherwise.
+pub(crate) fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let path = path.to_string_lossy();
+    // A leading '@' is an abstract socket
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(path.as_ref()),
+    };
+    let sent = addr.and_then(|addr| UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr));
+    if let Err(e) = sent {
+        debug!("sd_notify {state}: {e}");
+    }
+}
+
+/// The listening socket systemd passed in (`LISTEN_FDS`), if this process
+/// was socket-activated.
+pub(crate) fn activated_listener() -> std::io::Result<Option<std::os::unix::net::UnixListener>> {
+    let pid = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok());
+    let fds = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<u32>().ok());
+    if pid != Some(std::process::id()) || fds.unwrap_or(0) < 1 {
+        return Ok(None);
+    }
+    // SAFETY: systemd hands over fd 3 (SD_LISTEN_FDS_START) and nothing
+    // else in this process owns it
+    let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(LISTEN_FDS_START) };
+    // Hooks, prompts and commands mustn't inherit the socket, or think it
+    // was passed to them
+    // SAFETY: fcntl on an fd we own
+    if unsafe { libc::fcntl(LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: called once while binding, before anything else reads the
+    // environment from another thread
+    unsafe {
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+    }
+    listener.set_nonblocking(true)?;
+    Ok(Some(listener))
+}
+
+const LISTEN_FDS_START: i32 = 3;
+
+const SERVICE: &str = "wayvoice.service";
+const SOCKET: &str = "wayvoice.socket";
+
+fn unit_dir() -> Result<PathBuf, String> {
+    dirs::config_dir()
+        .map(|dir| dir.join("systemd").join("user"))
+        .ok_or_else(|| "Could not find the config directory".to_string())
+}
+
+/// Write a user unit for `wayvoice serve` (plus a socket unit unless
+/// `no_socket`), then enable and start it.
+pub async fn install(config: Option<&Path>, no_socket: bool) -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Could not find wayvoice: {e}"))?;
+    let mut exec = quote(&exe);
+    if let Some(config) = config {
+        let config = std::path::absolute(config).map_err(|e| e.to_string())?;
+        exec.push_str(&format!(" --config {}", quote(&config)));
+    }
+    exec.push_str(" serve");
+
+    let service = format!(
+        "[Unit]\n\
+         Description=wayvoice voice-to-text daemon\n\
+         PartOf=graphical-session.target\n\
+         After=graphical-session.target\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart={exec}\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=graphical-session.target\n"
+    );
+    let socket = "[Unit]\n\
+         Description=wayvoice daemon socket\n\
+         \n\
+         [Socket]\n\
+         ListenStream=%t/wayvoice.sock\n\
+         SocketMode=0600\n\
+         \n\
+         [Install]\n\
+         WantedBy=sockets.target\n";
+
+    let dir = unit_dir()?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {dir:?}: {e}"))?;
+    let mut written = vec![(SERVICE, service.as_str())];
+    if !no_socket {
+        written.push((SOCKET, socket));
+    }
+    for (name, content) in &written {
+        let path = dir.join(name);
+        std::fs::write(&path, content).map_err(|e| format!("Failed to write {path:?}: {e}"))?;
+        println!("Wrote {}", path.display());
+    }
+
+    systemctl(&["daemon-reload"]).await?;
+    // With the socket unit the daemon starts on the first command
+    let unit = if no_socket { SERVICE } else { SOCKET };
+    systemctl(&["enable", "--now", unit]).await?;
+    println!("Enabled {unit}");
+    Ok(())
+}
+
+/// Quote a path for `ExecStart=` (see systemd.syntax), so spaces, quotes,
+/// `%` specifiers and `$` variables are taken literally.
+fn quote(path: &Path) -> String {
+    let mut quoted = String::from("\"");
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '%' => quoted.push_str("%%"),
+            '$' => quoted.push_str("$$"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Stop and disable the units and remove their files.
+pub async fn uninstall() -> Result<(), String> {
+    let dir = unit_dir()?;
+    // Either unit may be missing; disabling those is harmless
+    let _ = systemctl(&["disable", "--now", SOCKET, SERVICE]).await;
+    for name in [SOCKET, SERVICE] {
+        let path = dir.join(name);
+        match std::fs::remove_file(&path) {
+            Ok(()) => println!("Removed {}", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to remove {path:?}: {e}")),
+        }
+    }
+    systemctl(&["daemon-reload"]).await
+}
+
+async fn systemctl(args: &[&str]) -> Result<(), String> {
+    let status = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()
+        .await
+        .map_err(|e| format!("Failed to run systemctl: {e}"))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "systemctl --user {} failed ({status})",
+            args.join(" ")
+        ))
+    }
+}
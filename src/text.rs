@@ -1,14 +1,753 @@
+use crate::transcription::Segment;
+use jiff::Zoned;
+use jiff::fmt::strtime;
+use regex::{Captures, Regex};
 use std::collections::HashMap;
+use std::fmt::Write;
+use tracing::error;
 
-pub fn apply_replacements(text: &str, replacements: &HashMap<String, String>) -> String {
+/// Replacement rules, compiled once when the config is loaded.
+#[derive(Debug, Clone, Default)]
+pub struct Replacements {
+    /// Longest key first, so "lazy vim" wins over "vim".
+    literals: Vec<(String, String)>,
+    /// Sorted by pattern so the order is the same on every run.
+    patterns: Vec<(Regex, String)>,
+    /// Spoken trigger -> template, longest trigger first.
+    snippets: Vec<(Regex, String)>,
+}
+
+impl Replacements {
+    /// Invalid patterns are reported and skipped rather than failing the
+    /// whole config.
+    pub fn new(literals: &HashMap<String, String>, patterns: &HashMap<String, String>) -> Self {
+        let mut literals: Vec<(String, String)> = literals
+            .iter()
+            .map(|(from, to)| (from.clone(), to.clone()))
+            .collect();
+        literals.sort_by(|(a, _), (b, _)| {
+            b.chars()
+                .count()
+                .cmp(&a.chars().count())
+                .then_with(|| a.cmp(b))
+        });
+
+        let mut patterns: Vec<(&String, &String)> = patterns.iter().collect();
+        patterns.sort();
+        let patterns = patterns
+            .into_iter()
+            .filter_map(|(pattern, to)| match Regex::new(pattern) {
+                Ok(re) => Some((re, to.clone())),
+                Err(e) => {
+                    error!("Invalid regex replacement {pattern:?}: {e}");
+                    None
+                }
+            })
+            .collect();
+        Self {
+            literals,
+            patterns,
+            snippets: Vec::new(),
+        }
+    }
+
+    /// Also expand `[snippets]` once the replacements are done.
+    pub fn with_snippets(mut self, snippets: &HashMap<String, String>) -> Self {
+        let mut snippets: Vec<(&String, &String)> = snippets.iter().collect();
+        snippets.sort_by(|(a, _), (b, _)| {
+            b.chars()
+                .count()
+                .cmp(&a.chars().count())
+                .then_with(|| a.cmp(b))
+        });
+        self.snippets = snippets
+            .into_iter()
+            .filter_map(|(trigger, template)| {
+                let words: Vec<String> = trigger.split_whitespace().map(regex::escape).collect();
+                if words.is_empty() {
+                    return None;
+                }
+                // Whisper punctuates and capitalizes the trigger like any
+                // other sentence, so both are ignored
+                let pattern = format!(r"(?i)\b{}\b[.!?,]?", words.join(r"[\s,]+"));
+                Regex::new(&pattern)
+                    .map(|re| (re, template.clone()))
+                    .inspect_err(|e| error!("Invalid snippet trigger {trigger:?}: {e}"))
+                    .ok()
+            })
+            .collect();
+        self
+    }
+}
+
+/// Apply literal replacements, then regex ones. Regex replacements may use
+/// `$1`-style references to capture groups.
+pub fn apply_replacements(text: &str, replacements: &Replacements) -> String {
+    let mut result = replace_literals(text, &replacements.literals);
+    for (re, to) in &replacements.patterns {
+        result = re.replace_all(&result, to.as_str()).into_owned();
+    }
+    expand_snippets(&result, &replacements.snippets, &Zoned::now())
+}
+
+fn expand_snippets(text: &str, snippets: &[(Regex, String)], now: &Zoned) -> String {
     let mut result = text.to_string();
-    for (from, to) in replacements {
-        let mut i = 0;
-        while let Some(pos) = result[i..].to_lowercase().find(&from.to_lowercase()) {
-            let abs_pos = i + pos;
-            result.replace_range(abs_pos..abs_pos + from.len(), to);
-            i = abs_pos + to.len();
+    for (re, template) in snippets {
+        if re.is_match(&result) {
+            let expansion = fill_placeholders(template, now);
+            result = re
+                .replace_all(&result, |_: &Captures| expansion.as_str())
+                .into_owned();
         }
     }
     result
 }
+
+/// `{date}`, `{time}` and `{now:<strftime>}` in a snippet template.
+fn fill_placeholders(template: &str, now: &Zoned) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let placeholder = &rest[start + 1..start + len];
+        let format = match placeholder {
+            "date" => Some("%Y-%m-%d"),
+            "time" => Some("%H:%M"),
+            _ => placeholder.strip_prefix("now:"),
+        };
+        match format.map(|format| strtime::format(format, now)) {
+            Some(Ok(formatted)) => out.push_str(&formatted),
+            // Anything else is literal text
+            _ => out.push_str(&rest[start..=start + len]),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Case-insensitive, whole-word literal replacement in a single pass, so
+/// replaced text is never matched again.
+fn replace_literals(text: &str, literals: &[(String, String)]) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut pos = 0;
+    'scan: while let Some(c) = text[pos..].chars().next() {
+        let prev = text[..pos].chars().next_back();
+        for (from, to) in literals {
+            if let Some(len) = match_at(&text[pos..], prev, from) {
+                let matched = &text[pos..pos + len];
+                let cased = match_case(to, from, matched, at_sentence_start(&result));
+                result.push_str(&cased);
+                pos += len;
+                continue 'scan;
+            }
+        }
+        result.push(c);
+        pos += c.len_utf8();
+    }
+    result
+}
+
+/// Byte length of `from` matched at the start of `rest`, unless the match
+/// would begin or end in the middle of a word ("jus" must not hit "justice").
+fn match_at(rest: &str, prev: Option<char>, from: &str) -> Option<usize> {
+    let first = from.chars().next()?;
+    if is_word_char(first) && prev.is_some_and(is_word_char) {
+        return None;
+    }
+
+    let mut chars = rest.char_indices();
+    let mut len = 0;
+    for expected in from.chars() {
+        let (i, c) = chars.next()?;
+        if c != expected && !c.to_lowercase().eq(expected.to_lowercase()) {
+            return None;
+        }
+        len = i + c.len_utf8();
+    }
+
+    let last = from.chars().next_back()?;
+    if is_word_char(last) && rest[len..].chars().next().is_some_and(is_word_char) {
+        return None;
+    }
+    Some(len)
+}
+
+/// Carry the casing of the spoken text over to the replacement: an all-caps
+/// match of a key that isn't itself all caps stays all caps, and a
+/// sentence-initial replacement is capitalized unless it has its own mixed
+/// case ("iPhone").
+fn match_case(to: &str, from: &str, matched: &str, sentence_start: bool) -> String {
+    let mut letters = matched.chars().filter(|c| c.is_alphabetic());
+    let shouting = letters.clone().count() > 1
+        && letters.all(char::is_uppercase)
+        && from.chars().any(char::is_lowercase);
+    if shouting {
+        return to.to_uppercase();
+    }
+
+    let mut chars = to.chars();
+    match chars.next() {
+        Some(first) if sentence_start && !chars.clone().any(char::is_uppercase) => {
+            first.to_uppercase().chain(chars).collect()
+        }
+        _ => to.to_string(),
+    }
+}
+
+/// Whether the next word in `output` would begin a sentence.
+fn at_sentence_start(output: &str) -> bool {
+    match output.trim_end().chars().next_back() {
+        None => true,
+        Some(c) => matches!(c, '.' | '!' | '?'),
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Rewrite each word in `words` (as the provider reported them, with a
+/// probability) that falls below `threshold` as `format`, where `{word}` is
+/// the word. Words that can't be found in `text` are left alone.
+pub fn mark_uncertain(text: &str, words: &[(String, f64)], threshold: f64, format: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut cursor = 0;
+    for (word, probability) in words {
+        // Some servers include the surrounding spaces and punctuation
+        let word = word.trim_matches(|c: char| !is_word_char(c));
+        if word.is_empty() {
+            continue;
+        }
+        let rest = &text[cursor..];
+        let found = rest.match_indices(word).map(|(i, _)| i).find(|&i| {
+            let before = text[..cursor + i].chars().next_back();
+            let after = rest[i + word.len()..].chars().next();
+            !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+        });
+        let Some(i) = found else {
+            continue;
+        };
+        output.push_str(&rest[..i]);
+        if *probability < threshold {
+            output.push_str(&format.replace("{word}", word));
+        } else {
+            output.push_str(word);
+        }
+        cursor += i + word.len();
+    }
+    output.push_str(&text[cursor..]);
+    output
+}
+
+/// What Whisper says over silence, from the subtitles it was trained on.
+const HALLUCINATIONS: &[&str] = &[
+    "you",
+    "thank you",
+    "thank you very much",
+    "thanks for watching",
+    "thank you for watching",
+    "thank you so much for watching",
+    "thanks for watching and see you next time",
+    "please subscribe",
+    "like and subscribe",
+    "subtitles by the amara org community",
+];
+
+/// Whether `text` is nothing but one of Whisper's stock phrases for
+/// silence, maybe repeated, or the same word over and over.
+pub fn is_hallucination(text: &str) -> bool {
+    let words: Vec<String> = text
+        .split(|c: char| !is_word_char(c) && c != '\'')
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    if words.is_empty() {
+        return false;
+    }
+    if words.len() >= 4 && words.iter().all(|w| *w == words[0]) {
+        return true;
+    }
+    HALLUCINATIONS.iter().any(|phrase| {
+        let phrase: Vec<&str> = phrase.split(' ').collect();
+        words.len().is_multiple_of(phrase.len())
+            && words.chunks(phrase.len()).all(|chunk| chunk == phrase)
+    })
+}
+
+/// Subtitles from timed segments, as SubRip or (with `vtt`) WebVTT.
+pub fn format_subtitles(segments: &[Segment], vtt: bool) -> String {
+    let mut output = if vtt {
+        "WEBVTT\n\n".to_string()
+    } else {
+        String::new()
+    };
+    let cues = segments.iter().filter(|s| !s.text.is_empty());
+    for (i, segment) in cues.enumerate() {
+        if !vtt {
+            let _ = writeln!(output, "{}", i + 1);
+        }
+        let _ = writeln!(
+            output,
+            "{} --> {}\n{}\n",
+            timestamp(segment.start, vtt),
+            timestamp(segment.end, vtt),
+            segment.text
+        );
+    }
+    output
+}
+
+/// `HH:MM:SS,mmm` for SubRip, `HH:MM:SS.mmm` for WebVTT.
+fn timestamp(secs: f64, vtt: bool) -> String {
+    let ms = (secs.max(0.0) * 1000.0).round() as u64;
+    let separator = if vtt { '.' } else { ',' };
+    format!(
+        "{:02}:{:02}:{:02}{separator}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Word-level diff in `git diff --word-diff=plain` style: removed words as
+/// `[-...-]`, added ones as `{+...+}`.
+pub fn word_diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.split_whitespace().collect();
+    let new: Vec<&str> = new.split_whitespace().collect();
+
+    // lcs[i][j]: longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = Vec::new();
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let flush = |out: &mut Vec<String>, removed: &mut Vec<&str>, added: &mut Vec<&str>| {
+        if !removed.is_empty() {
+            out.push(format!("[-{}-]", removed.join(" ")));
+            removed.clear();
+        }
+        if !added.is_empty() {
+            out.push(format!("{{+{}+}}", added.join(" ")));
+            added.clear();
+        }
+    };
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            flush(&mut out, &mut removed, &mut added);
+            out.push(old[i].to_string());
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            removed.push(old[i]);
+            i += 1;
+        } else {
+            added.push(new[j]);
+            j += 1;
+        }
+    }
+    flush(&mut out, &mut removed, &mut added);
+    out.join(" ")
+}
+
+/// Copied text made safe for a transcription prompt: whitespace and control
+/// characters collapsed to single spaces and cut to `max_chars`. Returns
+/// `None` for empty text and for a lone long token mixing upper and lower
+/// case with digits, which is more likely a generated password or API key
+/// than a name.
+pub fn prompt_context(text: &str, max_chars: usize) -> Option<String> {
+    let words: Vec<&str> = text
+        .split(|c: char| c.is_whitespace() || c.is_control())
+        .filter(|word| !word.is_empty())
+        .collect();
+    if let [word] = words.as_slice()
+        && word.chars().count() >= 16
+        && word.chars().any(char::is_uppercase)
+        && word.chars().any(char::is_lowercase)
+        && word.chars().any(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let context: String = words.join(" ").chars().take(max_chars).collect();
+    (!context.is_empty()).then_some(context)
+}
+
+/// Git's conventional limit for the subject line and body width.
+const COMMIT_WIDTH: usize = 72;
+
+/// Shape `text` as a git commit message: the first line becomes a subject
+/// of at most 72 characters (words that don't fit start the body), then a
+/// blank line and the rest wrapped at 72 columns. `- ` and `* ` lines stay
+/// separate list items.
+pub fn format_commit_message(text: &str) -> String {
+    let text = text.trim();
+    let (first, rest) = text.split_once('\n').unwrap_or((text, ""));
+
+    let mut subject = String::new();
+    let mut overflow = Vec::new();
+    for word in first.split_whitespace() {
+        let fits = subject.chars().count() + 1 + word.chars().count() <= COMMIT_WIDTH;
+        if overflow.is_empty() && (subject.is_empty() || fits) {
+            if !subject.is_empty() {
+                subject.push(' ');
+            }
+            subject.push_str(word);
+        } else {
+            overflow.push(word);
+        }
+    }
+
+    let body = format!("{}\n{rest}", overflow.join(" "));
+    let mut paragraphs = Vec::new();
+    for paragraph in body.split("\n\n") {
+        let mut items: Vec<String> = Vec::new();
+        for line in paragraph.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            match items.last_mut() {
+                Some(item) if !line.starts_with("- ") && !line.starts_with("* ") => {
+                    item.push(' ');
+                    item.push_str(line);
+                }
+                _ => items.push(line.to_string()),
+            }
+        }
+        if !items.is_empty() {
+            let wrapped: Vec<String> = items.iter().map(|item| wrap(item)).collect();
+            paragraphs.push(wrapped.join("\n"));
+        }
+    }
+
+    if paragraphs.is_empty() {
+        subject
+    } else {
+        format!("{subject}\n\n{}", paragraphs.join("\n\n"))
+    }
+}
+
+/// Wrap at `COMMIT_WIDTH`, indenting continuations of a list item.
+fn wrap(item: &str) -> String {
+    let indent = if item.starts_with("- ") || item.starts_with("* ") {
+        "  "
+    } else {
+        ""
+    };
+    let mut out = String::new();
+    let mut width = 0;
+    for word in item.split_whitespace() {
+        let len = word.chars().count();
+        if width > 0 && width + 1 + len > COMMIT_WIDTH {
+            out.push('\n');
+            out.push_str(indent);
+            width = indent.len();
+        } else if width > 0 {
+            out.push(' ');
+            width += 1;
+        }
+        out.push_str(word);
+        width += len;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(literals: &[(&str, &str)], patterns: &[(&str, &str)]) -> Replacements {
+        let to_map = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+        Replacements::new(&to_map(literals), &to_map(patterns))
+    }
+
+    #[test]
+    fn longest_key_wins_on_overlap() {
+        let r = rules(
+            &[
+                ("vim", "Vim"),
+                ("lazy vim", "LazyVim"),
+                ("neo vim", "Neovim"),
+            ],
+            &[],
+        );
+        assert_eq!(
+            apply_replacements("lazy vim and neo vim and vim", &r),
+            "LazyVim and Neovim and Vim"
+        );
+    }
+
+    #[test]
+    fn overlapping_rules_are_deterministic() {
+        let literals = [
+            ("home", "Home"),
+            ("home manager", "Home Manager"),
+            ("manager", "mgr"),
+        ];
+        for _ in 0..20 {
+            let r = rules(&literals, &[]);
+            assert_eq!(
+                apply_replacements("home manager config", &r),
+                "Home Manager config"
+            );
+        }
+    }
+
+    #[test]
+    fn replaced_text_is_not_matched_again() {
+        let r = rules(&[("jus", "just"), ("just", "JUST")], &[]);
+        assert_eq!(apply_replacements("so jus do it", &r), "so just do it");
+    }
+
+    #[test]
+    fn matches_whole_words_only() {
+        let r = rules(&[("jus", "just")], &[]);
+        assert_eq!(apply_replacements("justice jus", &r), "justice just");
+    }
+
+    #[test]
+    fn keys_starting_with_punctuation_match_inside_words() {
+        let r = rules(&[(".file", "dotfile")], &[]);
+        assert_eq!(apply_replacements("my .file", &r), "my dotfile");
+    }
+
+    #[test]
+    fn matches_case_insensitively() {
+        let r = rules(&[("neovim", "Neovim")], &[]);
+        assert_eq!(apply_replacements("use neoVim", &r), "use Neovim");
+    }
+
+    #[test]
+    fn capitalizes_at_sentence_start() {
+        let r = rules(&[("jus", "just"), ("iphone", "iPhone")], &[]);
+        assert_eq!(
+            apply_replacements("jus do it. ok jus go! iphone", &r),
+            "Just do it. ok just go! iPhone"
+        );
+    }
+
+    #[test]
+    fn keeps_all_caps() {
+        let r = rules(&[("neovim", "Neovim"), ("a", "b")], &[]);
+        assert_eq!(
+            apply_replacements("I LOVE NEOVIM A lot", &r),
+            "I LOVE NEOVIM b lot"
+        );
+    }
+
+    #[test]
+    fn all_caps_keys_are_not_shouting() {
+        let r = Replacements::new(&crate::config::default_replacements(), &HashMap::new());
+        assert_eq!(
+            apply_replacements("switch with LTAB", &r),
+            "switch with Alt Tab"
+        );
+        assert_eq!(apply_replacements("I USE GHOSTY", &r), "I USE GHOSTTY");
+    }
+
+    #[test]
+    fn handles_multibyte_text() {
+        let r = rules(&[("über", "uber"), ("café", "coffee")], &[]);
+        assert_eq!(
+            apply_replacements("Über das café — naïve", &r),
+            "Uber das coffee — naïve"
+        );
+    }
+
+    #[test]
+    fn regex_runs_after_literals() {
+        let r = rules(
+            &[("get hub", "x")],
+            &[(r"(?i)\bx\b", "GitHub"), (r"(\d+) percent", "$1%")],
+        );
+        assert_eq!(
+            apply_replacements("get hub at 5 percent", &r),
+            "GitHub at 5%"
+        );
+    }
+
+    #[test]
+    fn word_diff_marks_changed_words() {
+        assert_eq!(
+            word_diff("hello neo vim world", "hello Neovim world"),
+            "hello [-neo vim-] {+Neovim+} world"
+        );
+        assert_eq!(word_diff("a b", "a b c"), "a b {+c+}");
+        assert_eq!(word_diff("x a", "a"), "[-x-] a");
+        assert_eq!(word_diff("same  text", "same text"), "same text");
+    }
+
+    #[test]
+    fn commit_message_limits_subject_and_wraps_body() {
+        assert_eq!(
+            format_commit_message("fix(ipc): drop stale sockets\n\nThey broke startup."),
+            "fix(ipc): drop stale sockets\n\nThey broke startup."
+        );
+        assert_eq!(
+            format_commit_message("  feat: add undo  "),
+            "feat: add undo"
+        );
+
+        let long = format!("feat: {}", "word ".repeat(20));
+        let message = format_commit_message(&long);
+        let (subject, body) = message.split_once("\n\n").unwrap();
+        assert!(subject.chars().count() <= 72);
+        assert!(body.starts_with("word"));
+
+        let body = format!("fix: x\n\n- {}", "long ".repeat(20));
+        let message = format_commit_message(&body);
+        let lines: Vec<&str> = message.lines().collect();
+        assert!(lines.iter().all(|line| line.chars().count() <= 72));
+        assert!(lines[2].starts_with("- long"));
+        assert!(lines[3].starts_with("  long"));
+    }
+
+    #[test]
+    fn snippets_expand_after_replacements() {
+        let now: Zoned = "2026-03-04T09:05:00[UTC]".parse().unwrap();
+        let snippets = Replacements::new(&HashMap::new(), &HashMap::new())
+            .with_snippets(&HashMap::from([
+                ("sign off".to_string(), "Best,\nJo".to_string()),
+                (
+                    "sign off formal".to_string(),
+                    "Kind regards,\nJo".to_string(),
+                ),
+                (
+                    "today".to_string(),
+                    "{date} {time} {now:%A} {x}".to_string(),
+                ),
+            ]))
+            .snippets;
+        assert_eq!(
+            expand_snippets("Thanks. Sign off formal.", &snippets, &now),
+            "Thanks. Kind regards,\nJo"
+        );
+        assert_eq!(expand_snippets("sign, off", &snippets, &now), "Best,\nJo");
+        assert_eq!(
+            expand_snippets("today", &snippets, &now),
+            "2026-03-04 09:05 Wednesday {x}"
+        );
+        assert_eq!(expand_snippets("todays", &snippets, &now), "todays");
+    }
+
+    #[test]
+    fn unclosed_placeholder_is_literal() {
+        let now: Zoned = "2026-03-04T09:05:00[UTC]".parse().unwrap();
+        assert_eq!(fill_placeholders("a{b", &now), "a{b");
+        assert_eq!(fill_placeholders("{date} a{b", &now), "2026-03-04 a{b");
+    }
+
+    #[test]
+    fn prompt_context_collapses_and_skips_secrets() {
+        assert_eq!(
+            prompt_context("  feature/login-redirect\n", 100),
+            Some("feature/login-redirect".to_string())
+        );
+        assert_eq!(
+            prompt_context("Error:\tconnection\u{0}refused", 100),
+            Some("Error: connection refused".to_string())
+        );
+        assert_eq!(prompt_context("ghp_Ab12Cd34Ef56Gh78Ij90", 100), None);
+        assert_eq!(
+            prompt_context("JIRA-123-fix", 100),
+            Some("JIRA-123-fix".to_string())
+        );
+        assert_eq!(prompt_context(" \n ", 100), None);
+        assert_eq!(prompt_context("abcdef", 3), Some("abc".to_string()));
+    }
+
+    #[test]
+    fn marks_uncertain_words_in_place() {
+        let words = |list: &[(&str, f64)]| -> Vec<(String, f64)> {
+            list.iter().map(|(w, p)| (w.to_string(), *p)).collect()
+        };
+        assert_eq!(
+            mark_uncertain(
+                "Deploy it to Kubernetes, then I'll check.",
+                &words(&[
+                    (" Deploy", 0.9),
+                    (" it", 0.95),
+                    (" to", 0.9),
+                    (" Kubernetes,", 0.3),
+                    (" then", 0.9),
+                    (" I'll", 0.4),
+                    (" check.", 0.8),
+                ]),
+                0.5,
+                "{word}[?]"
+            ),
+            "Deploy it to Kubernetes[?], then I'll[?] check."
+        );
+        // "a" must not match inside "data"
+        assert_eq!(
+            mark_uncertain(
+                "the data is a mess",
+                &words(&[("the", 0.9), ("a", 0.1)]),
+                0.5,
+                "[{word}]"
+            ),
+            "the data is [a] mess"
+        );
+        assert_eq!(
+            mark_uncertain("hello", &words(&[("goodbye", 0.1)]), 0.5, "[{word}]"),
+            "hello"
+        );
+    }
+
+    #[test]
+    fn formats_srt_and_vtt() {
+        let segments = [
+            Segment {
+                start: 0.0,
+                end: 2.5,
+                text: "Hello there.".to_string(),
+            },
+            Segment {
+                start: 2.5,
+                end: 2.5,
+                text: String::new(),
+            },
+            Segment {
+                start: 3661.0,
+                end: 3662.0004,
+                text: "Bye.".to_string(),
+            },
+        ];
+        assert_eq!(
+            format_subtitles(&segments, false),
+            "1\n00:00:00,000 --> 00:00:02,500\nHello there.\n\n\
+             2\n01:01:01,000 --> 01:01:02,000\nBye.\n\n"
+        );
+        assert_eq!(
+            format_subtitles(&segments, true),
+            "WEBVTT\n\n\
+             00:00:00.000 --> 00:00:02.500\nHello there.\n\n\
+             01:01:01.000 --> 01:01:02.000\nBye.\n\n"
+        );
+    }
+
+    #[test]
+    fn spots_silence_hallucinations() {
+        assert!(is_hallucination("Thank you."));
+        assert!(is_hallucination(" Thanks for watching!"));
+        assert!(is_hallucination("Thank you. Thank you. Thank you."));
+        assert!(is_hallucination("you you you you"));
+        assert!(!is_hallucination(""));
+        assert!(!is_hallucination("Thank you for the review"));
+        assert!(!is_hallucination("no no no"));
+    }
+}
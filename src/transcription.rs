@@ -1,92 +1,336 @@
-use crate::config::{Config, Provider};
-use log::debug;
-use serde::Deserialize;
+mod deepgram;
+mod language;
+mod openai;
+mod vosk;
+mod whisper_cpp;
 
-#[derive(Deserialize)]
-struct TranscriptionResponse {
-    text: String,
+use crate::audio::{denoise, encode_opus, is_wav, normalize};
+use crate::config::{Config, LanguageMismatch, Provider, UploadFormat, loggable, runtime_dir};
+use crate::error::WayvoiceError;
+use crate::notify::notify;
+use crate::text::{is_hallucination, mark_uncertain};
+use serde::Serialize;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+pub use deepgram::{Deepgram, api_key as deepgram_api_key};
+pub use language::language_code;
+pub use openai::{OpenAiCompatible, api_key_for, default_base_url};
+pub use vosk::Vosk;
+pub use whisper_cpp::WhisperCpp;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// What a backend heard.
+#[derive(Debug, Clone, Default)]
+pub struct Transcript {
+    pub text: String,
+    /// Detected language as a code like "en", if the backend reports one.
+    pub language: Option<String>,
+    /// How sure the backend is of the whole transcript, from 0 to 1.
+    pub confidence: Option<f64>,
+    /// Each word with its probability, for backends that report them.
+    pub words: Vec<(String, f64)>,
+    /// Timed segments, for backends that report them.
+    pub segments: Vec<Segment>,
+}
+
+/// A stretch of the transcript with its timing, in seconds from the start
+/// of the audio.
+#[derive(Debug, Clone, Serialize)]
+pub struct Segment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// A speech-to-text backend that turns a WAV recording into text.
+pub trait TranscriptionProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// The model in use, as named in the config.
+    fn model(&self) -> &str;
+
+    /// Whether the backend accepts Ogg/Opus uploads in addition to WAV.
+    fn accepts_opus(&self) -> bool {
+        false
+    }
+
+    fn transcribe(&self, audio_data: Vec<u8>) -> BoxFuture<'_, Result<Transcript, WayvoiceError>>;
+
+    /// Values `model` can take, from the provider's models endpoint.
+    fn models(&self) -> BoxFuture<'_, Result<Vec<String>, WayvoiceError>> {
+        Box::pin(async {
+            Err(WayvoiceError::config(format!(
+                "{} runs a local model file; there is nothing to list",
+                self.name()
+            )))
+        })
+    }
 }
 
+/// Build the backend selected by `config.provider`.
+pub async fn provider_for(
+    config: &Config,
+) -> Result<Box<dyn TranscriptionProvider>, WayvoiceError> {
+    Ok(match config.provider {
+        Provider::Openai | Provider::Groq => Box::new(OpenAiCompatible::from_config(config).await?),
+        Provider::Deepgram => Box::new(Deepgram::from_config(config).await?),
+        Provider::WhisperCpp => Box::new(WhisperCpp::from_config(config)?),
+        Provider::Vosk => Box::new(Vosk::from_config(config)?),
+    })
+}
+
+#[tracing::instrument(
+    name = "transcribe",
+    level = "debug",
+    skip_all,
+    fields(provider = config.provider.as_str())
+)]
 pub async fn transcribe_audio(
     audio_data: Vec<u8>,
     config: &Config,
-) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    let api_key = resolve_api_key(config)?;
+) -> Result<Transcript, WayvoiceError> {
+    let provider = provider_for(config).await?;
+    let rival = match race_config(config) {
+        Some(rival_config) => match provider_for(&rival_config).await {
+            Ok(rival) => Some((rival, rival_config)),
+            Err(e) => {
+                warn!("{e}, not racing {}", rival_config.provider.as_str());
+                None
+            }
+        },
+        None => None,
+    };
 
-    let file_part = reqwest::multipart::Part::bytes(audio_data)
-        .file_name("audio.wav")
-        .mime_str("audio/wav")?;
+    let audio_data = if config.noise_suppression {
+        match denoise(&audio_data, &config.rnnoise_model).await {
+            Ok(clean) => clean,
+            Err(e) => {
+                warn!("{e}, skipping noise suppression");
+                audio_data
+            }
+        }
+    } else {
+        audio_data
+    };
 
-    let model = if config.model.is_empty() {
-        default_model(config.provider)
+    let audio_data = if config.normalize {
+        normalize(
+            &audio_data,
+            config.normalize_target_db as f32,
+            config.compress,
+        )
+        .unwrap_or(audio_data)
     } else {
-        &config.model
+        audio_data
     };
 
-    let mut form = reqwest::multipart::Form::new()
-        .part("file", file_part)
-        .text("model", model.to_string());
+    let Some((rival, rival_config)) = rival else {
+        return send(provider.as_ref(), audio_data, config).await;
+    };
+    race(
+        send(provider.as_ref(), audio_data.clone(), config),
+        send(rival.as_ref(), audio_data, &rival_config),
+    )
+    .await
+}
 
-    if !config.language.is_empty() {
-        form = form.text("language", config.language.clone());
+/// The config for `race_provider`. `model` and `base_url` belong to the
+/// main provider, so a different one gets its defaults.
+fn race_config(config: &Config) -> Option<Config> {
+    if config.race_provider.is_empty() {
+        return None;
     }
-
-    if !config.prompt.is_empty() {
-        form = form.text("prompt", config.prompt.clone());
+    let Some(provider) = Provider::from_name(&config.race_provider) else {
+        warn!("Unknown race_provider: {}", config.race_provider);
+        return None;
+    };
+    let mut rival = config.clone();
+    rival.race_provider.clear();
+    rival.model = config.race_model.clone();
+    if provider != config.provider {
+        rival.provider = provider;
+        rival.base_url.clear();
     }
+    Some(rival)
+}
 
-    let endpoint = api_endpoint(config.provider);
-    debug!("provider={:?} endpoint={endpoint}", config.provider);
-
-    let client = reqwest::Client::new();
-    let api_start = std::time::Instant::now();
-    let response = client
-        .post(endpoint)
-        .bearer_auth(api_key)
-        .multipart(form)
-        .send()
-        .await?;
-    debug!("api_call: {:?}", api_start.elapsed());
-
-    if !response.status().is_success() {
-        let status = response.status();
-        let body = response.text().await.unwrap_or_default();
-        return Err(format!("API error {status}: {body}").into());
+/// The first transcript with text in it; the slower request is dropped,
+/// which cancels it. A failure or empty result waits for the other one.
+async fn race(
+    first: impl Future<Output = Result<Transcript, WayvoiceError>>,
+    second: impl Future<Output = Result<Transcript, WayvoiceError>>,
+) -> Result<Transcript, WayvoiceError> {
+    tokio::pin!(first, second);
+    let (result, won_first) = tokio::select! {
+        result = &mut first => (result, true),
+        result = &mut second => (result, false),
+    };
+    if matches!(&result, Ok(transcript) if !transcript.text.is_empty()) {
+        debug!(
+            "race won by the {} provider",
+            if won_first { "main" } else { "racing" }
+        );
+        return result;
+    }
+    let other = if won_first { second.await } else { first.await };
+    match (result, other) {
+        (_, Ok(transcript)) if !transcript.text.is_empty() => Ok(transcript),
+        (Ok(transcript), _) => Ok(transcript),
+        (Err(_), other) => other,
     }
-
-    let result: TranscriptionResponse = response.json().await?;
-    Ok(result.text.trim().to_string())
 }
 
-fn resolve_api_key(config: &Config) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    match config.provider {
-        Provider::Openai => {
-            if !config.openai_api_key.is_empty() {
-                return Ok(config.openai_api_key.clone());
-            }
-            std::env::var("OPENAI_API_KEY")
-                .map_err(|_| "OPENAI_API_KEY not set and no openai_api_key in voice.toml".into())
-        }
-        Provider::Groq => {
-            if !config.groq_api_key.is_empty() {
-                return Ok(config.groq_api_key.clone());
+/// Upload (as Opus where the backend takes it) and check what comes back.
+async fn send(
+    provider: &dyn TranscriptionProvider,
+    audio_data: Vec<u8>,
+    config: &Config,
+) -> Result<Transcript, WayvoiceError> {
+    debug!("transcribing with {}", provider.name());
+    let audio_data = if config.upload_format == UploadFormat::Opus
+        && provider.accepts_opus()
+        && is_wav(&audio_data)
+    {
+        match encode_opus(&audio_data, config.opus_bitrate).await {
+            Ok(opus) => opus,
+            Err(e) => {
+                warn!("{e}, uploading WAV instead");
+                audio_data
             }
-            std::env::var("GROQ_API_KEY")
-                .map_err(|_| "GROQ_API_KEY not set and no groq_api_key in voice.toml".into())
         }
+    } else {
+        audio_data
+    };
+
+    let mut transcript = provider.transcribe(audio_data).await?;
+    debug!("detected language: {:?}", transcript.language);
+    debug!("confidence: {:?}", transcript.confidence);
+    check_language(&transcript, config).await?;
+    check_confidence(&transcript, config)?;
+    if config.filter_hallucinations && is_hallucination(&transcript.text) {
+        debug!(
+            "dropping hallucination: {:?}",
+            loggable(&transcript.text, config.log_transcripts)
+        );
+        return Ok(Transcript {
+            language: transcript.language,
+            ..Transcript::default()
+        });
+    }
+    if config.min_word_confidence > 0.0 {
+        transcript.text = mark_uncertain(
+            &transcript.text,
+            &transcript.words,
+            config.min_word_confidence,
+            &config.uncertain_word_format,
+        );
     }
+    Ok(transcript)
 }
 
-fn api_endpoint(provider: Provider) -> &'static str {
-    match provider {
-        Provider::Openai => "https://api.openai.com/v1/audio/transcriptions",
-        Provider::Groq => "https://api.groq.com/openai/v1/audio/transcriptions",
+/// Refuse a transcript below `min_confidence` rather than typing garbage.
+fn check_confidence(transcript: &Transcript, config: &Config) -> Result<(), WayvoiceError> {
+    match transcript.confidence {
+        Some(confidence) if confidence < config.min_confidence => Err(WayvoiceError::Provider(
+            format!("Low confidence ({:.0}%), try again", confidence * 100.0),
+        )),
+        _ => Ok(()),
     }
 }
 
-fn default_model(provider: Provider) -> &'static str {
-    match provider {
-        Provider::Openai => "whisper-1",
-        Provider::Groq => "whisper-large-v3-turbo",
+/// Apply `language_mismatch` when the detected language isn't one of
+/// `expected_languages`. Whisper likes to "translate" short clips.
+async fn check_language(transcript: &Transcript, config: &Config) -> Result<(), WayvoiceError> {
+    let Some(detected) = &transcript.language else {
+        return Ok(());
+    };
+    let expected: Vec<String> = if config.expected_languages.is_empty() {
+        std::iter::once(&config.language)
+            .filter(|l| !l.is_empty())
+            .map(|l| language_code(l))
+            .collect()
+    } else {
+        config
+            .expected_languages
+            .iter()
+            .map(|l| language_code(l))
+            .collect()
+    };
+    if expected.is_empty() || expected.contains(detected) {
+        return Ok(());
     }
+
+    let message = format!(
+        "Detected language {detected}, expected {}",
+        expected.join(", ")
+    );
+    match config.language_mismatch {
+        LanguageMismatch::Ignore => Ok(()),
+        LanguageMismatch::Flag => {
+            warn!("{message}");
+            notify(&message).await;
+            Ok(())
+        }
+        LanguageMismatch::Reject => Err(WayvoiceError::Provider(format!(
+            "{message}, transcript discarded"
+        ))),
+    }
+}
+
+/// Where to hand audio to the offline recognizer `name`. Numbered per call,
+/// so transcriptions running side by side don't overwrite each other's.
+fn audio_file(name: &str) -> Result<PathBuf, WayvoiceError> {
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+    let n = COUNT.fetch_add(1, Ordering::Relaxed);
+    Ok(runtime_dir()?.join(format!("wayvoice-{name}-{}-{n}.wav", std::process::id())))
+}
+
+/// Run an offline recognizer on `audio_file` and collect its plain-text stdout.
+async fn run_local_transcriber(
+    mut cmd: Command,
+    bin: &str,
+    audio_file: &Path,
+) -> Result<Transcript, WayvoiceError> {
+    debug!("local transcriber bin={bin}");
+
+    let start = std::time::Instant::now();
+    let output = cmd.output().await;
+    let _ = tokio::fs::remove_file(audio_file).await;
+    let output =
+        output.map_err(|e| WayvoiceError::Provider(format!("Failed to run {bin}: {e}")))?;
+    debug!("local_transcribe: {:?}", start.elapsed());
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(WayvoiceError::Provider(format!(
+            "{bin} failed ({}): {}",
+            output.status,
+            stderr.trim()
+        )));
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    let text: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    Ok(Transcript {
+        text: text.join(" "),
+        ..Default::default()
+    })
+}
+
+/// A local model's file or directory name, without the path to it.
+fn model_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
 }
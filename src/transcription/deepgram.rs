@@ -0,0 +1,312 @@
+use super::{BoxFuture, Segment, Transcript, TranscriptionProvider, language_code};
+use crate::audio::upload_format;
+use crate::config::{Config, Provider, command_api_key};
+use crate::error::WayvoiceError;
+use crate::http::send_with_retry;
+use serde::Deserialize;
+use tracing::{Instrument, debug, debug_span};
+
+const LISTEN_URL: &str = "https://api.deepgram.com/v1/listen";
+const MODELS_URL: &str = "https://api.deepgram.com/v1/models";
+
+#[derive(Deserialize)]
+struct ListenResponse {
+    results: Results,
+}
+
+#[derive(Deserialize)]
+struct Results {
+    channels: Vec<Channel>,
+    #[serde(default)]
+    utterances: Vec<Utterance>,
+}
+
+#[derive(Deserialize)]
+struct Channel {
+    #[serde(default)]
+    detected_language: Option<String>,
+    alternatives: Vec<Alternative>,
+}
+
+#[derive(Deserialize)]
+struct Alternative {
+    transcript: String,
+    #[serde(default)]
+    confidence: Option<f64>,
+    #[serde(default)]
+    words: Vec<Word>,
+}
+
+#[derive(Deserialize)]
+struct Word {
+    word: String,
+    #[serde(default)]
+    punctuated_word: Option<String>,
+    confidence: f64,
+}
+
+/// Speech-to-text models are under `stt`, once per language and version.
+#[derive(Deserialize)]
+struct ModelList {
+    #[serde(default)]
+    stt: Vec<Model>,
+}
+
+#[derive(Deserialize)]
+struct Model {
+    canonical_name: String,
+}
+
+#[derive(Deserialize)]
+struct Utterance {
+    start: f64,
+    end: f64,
+    transcript: String,
+    #[serde(default)]
+    speaker: Option<u32>,
+}
+
+/// Deepgram's pre-recorded audio API, which unlike Whisper can tell
+/// speakers apart.
+pub struct Deepgram {
+    client: reqwest::Client,
+    retries: u32,
+    url: reqwest::Url,
+    models_url: String,
+    model: String,
+    api_key: String,
+    diarize: bool,
+}
+
+impl Deepgram {
+    pub async fn from_config(config: &Config) -> Result<Self, WayvoiceError> {
+        // Self-hosted Deepgram serves the same API under its own base URL
+        let (url, models_url) = if config.base_url.is_empty() {
+            (LISTEN_URL.to_string(), MODELS_URL.to_string())
+        } else {
+            let base_url = config.base_url.trim_end_matches('/');
+            (format!("{base_url}/listen"), format!("{base_url}/models"))
+        };
+        let mut url = reqwest::Url::parse(&url)
+            .map_err(|e| WayvoiceError::Config(format!("Invalid base_url {url:?}: {e}")))?;
+        let model = if config.model.is_empty() {
+            "nova-3".to_string()
+        } else {
+            config.model.clone()
+        };
+        {
+            // Encoded, so a model or language with `&` or spaces can't
+            // break the query
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("punctuate", "true")
+                .append_pair("smart_format", "true")
+                .append_pair("utterances", "true")
+                .append_pair("model", &model);
+            if config.language.is_empty() {
+                query.append_pair("detect_language", "true");
+            } else {
+                query.append_pair("language", &config.language);
+            }
+            if config.diarize {
+                query.append_pair("diarize", "true");
+            }
+        }
+        Ok(Self {
+            client: config.http.clone(),
+            retries: config.request_retries,
+            url,
+            models_url,
+            model,
+            api_key: api_key(config).await?,
+            diarize: config.diarize,
+        })
+    }
+
+    async fn request(&self, audio_data: Vec<u8>) -> Result<Transcript, WayvoiceError> {
+        debug!("provider=deepgram url={}", self.url);
+        let mime = upload_format(&audio_data).1;
+
+        let upload = debug_span!("upload", bytes = audio_data.len());
+        let response = send_with_retry("deepgram", self.retries, || {
+            Ok(self
+                .client
+                .post(self.url.clone())
+                .header("Authorization", format!("Token {}", self.api_key))
+                .header("Content-Type", mime)
+                .body(audio_data.clone()))
+        })
+        .instrument(upload)
+        .await?;
+
+        if !response.status().is_success() {
+            return Err(WayvoiceError::from_response("deepgram", response).await);
+        }
+
+        let result: ListenResponse = response.json().await?;
+        let channel = result.results.channels.into_iter().next();
+        let language = channel
+            .as_ref()
+            .and_then(|c| c.detected_language.as_deref())
+            .map(language_code);
+        let Some(alternative) = channel.and_then(|c| c.alternatives.into_iter().next()) else {
+            return Ok(Transcript::default());
+        };
+
+        let segments: Vec<Segment> = result
+            .results
+            .utterances
+            .iter()
+            .map(|u| Segment {
+                start: u.start,
+                end: u.end,
+                text: match u.speaker {
+                    Some(speaker) if self.diarize => {
+                        format!("{}: {}", speaker_label(speaker), u.transcript.trim())
+                    }
+                    _ => u.transcript.trim().to_string(),
+                },
+            })
+            .collect();
+        let text = if self.diarize && !result.results.utterances.is_empty() {
+            by_speaker(&result.results.utterances)
+        } else {
+            alternative.transcript.trim().to_string()
+        };
+        let words = alternative
+            .words
+            .into_iter()
+            .map(|w| (w.punctuated_word.unwrap_or(w.word), w.confidence))
+            .collect();
+        Ok(Transcript {
+            text,
+            language,
+            confidence: alternative.confidence,
+            words,
+            segments,
+        })
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, WayvoiceError> {
+        debug!("provider=deepgram models={}", self.models_url);
+        let response = send_with_retry("deepgram", self.retries, || {
+            Ok(self
+                .client
+                .get(&self.models_url)
+                .header("Authorization", format!("Token {}", self.api_key)))
+        })
+        .await?;
+
+        if !response.status().is_success() {
+            return Err(WayvoiceError::from_response("deepgram", response).await);
+        }
+
+        let list: ModelList = response.json().await?;
+        let mut names: Vec<String> = list.stt.into_iter().map(|m| m.canonical_name).collect();
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+}
+
+impl TranscriptionProvider for Deepgram {
+    fn name(&self) -> &'static str {
+        "deepgram"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn accepts_opus(&self) -> bool {
+        true
+    }
+
+    fn transcribe(&self, audio_data: Vec<u8>) -> BoxFuture<'_, Result<Transcript, WayvoiceError>> {
+        Box::pin(self.request(audio_data))
+    }
+
+    fn models(&self) -> BoxFuture<'_, Result<Vec<String>, WayvoiceError>> {
+        Box::pin(self.list_models())
+    }
+}
+
+/// "Speaker 1" for Deepgram's speaker 0.
+fn speaker_label(speaker: u32) -> String {
+    format!("Speaker {}", speaker + 1)
+}
+
+/// One `Speaker N: ...` line per turn, joining consecutive utterances by
+/// the same speaker.
+fn by_speaker(utterances: &[Utterance]) -> String {
+    let mut lines: Vec<(Option<u32>, String)> = Vec::new();
+    for utterance in utterances {
+        let text = utterance.transcript.trim();
+        match lines.last_mut() {
+            Some((speaker, line)) if *speaker == utterance.speaker => {
+                line.push(' ');
+                line.push_str(text);
+            }
+            _ => lines.push((utterance.speaker, text.to_string())),
+        }
+    }
+    lines
+        .into_iter()
+        .map(|(speaker, line)| match speaker {
+            Some(speaker) => format!("{}: {line}", speaker_label(speaker)),
+            None => line,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The Deepgram key from the config file, `api_key_command` or environment.
+pub async fn api_key(config: &Config) -> Result<String, WayvoiceError> {
+    if !config.deepgram_api_key.is_empty() {
+        return Ok(config.deepgram_api_key.clone());
+    }
+    if let Some(key) = command_api_key(config, Provider::Deepgram).await {
+        return Ok(key);
+    }
+    std::env::var("DEEPGRAM_API_KEY").map_err(|_| {
+        WayvoiceError::config("DEEPGRAM_API_KEY not set and no deepgram_api_key in voice.toml")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utterances(json: &str) -> Vec<Utterance> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn joins_turns_by_speaker() {
+        let utterances = utterances(
+            r#"[
+                {"start": 0.0, "end": 1.0, "transcript": "Hi there.", "speaker": 0},
+                {"start": 1.0, "end": 2.0, "transcript": " How are you? ", "speaker": 0},
+                {"start": 2.0, "end": 3.0, "transcript": "Good.", "speaker": 1},
+                {"start": 3.0, "end": 4.0, "transcript": "Great.", "speaker": 0}
+            ]"#,
+        );
+        assert_eq!(
+            by_speaker(&utterances),
+            "Speaker 1: Hi there. How are you?\nSpeaker 2: Good.\nSpeaker 1: Great."
+        );
+    }
+
+    #[test]
+    fn unlabelled_utterances_stay_unlabelled() {
+        let utterances = utterances(
+            r#"[
+                {"start": 0.0, "end": 1.0, "transcript": "One."},
+                {"start": 1.0, "end": 2.0, "transcript": "Two."},
+                {"start": 2.0, "end": 3.0, "transcript": "Three.", "speaker": 2}
+            ]"#,
+        );
+        assert_eq!(by_speaker(&utterances), "One. Two.\nSpeaker 3: Three.");
+        assert_eq!(by_speaker(&[]), "");
+    }
+}
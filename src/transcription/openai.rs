@@ -0,0 +1,366 @@
+use super::{BoxFuture, Segment, Transcript, TranscriptionProvider, language_code};
+use crate::audio::upload_format;
+use crate::config::{Config, Provider, command_api_key};
+use crate::error::WayvoiceError;
+use crate::http::send_with_retry;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::{Instrument, debug, debug_span};
+
+/// Covers both `json` and `verbose_json`; only the latter has `language`
+/// and segments.
+#[derive(Deserialize)]
+struct TranscriptionResponse {
+    text: String,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    segments: Option<Vec<VerboseSegment>>,
+    /// Only with word timestamps; some servers put them in the segments
+    /// instead.
+    #[serde(default)]
+    words: Option<Vec<Word>>,
+}
+
+#[derive(Deserialize)]
+struct VerboseSegment {
+    #[serde(default)]
+    start: f64,
+    #[serde(default)]
+    end: f64,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    avg_logprob: Option<f64>,
+    #[serde(default)]
+    words: Option<Vec<Word>>,
+}
+
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<Model>,
+}
+
+#[derive(Deserialize)]
+struct Model {
+    id: String,
+}
+
+/// OpenAI and Groq leave out `probability`; faster-whisper servers have it.
+#[derive(Deserialize)]
+struct Word {
+    word: String,
+    #[serde(default)]
+    probability: Option<f64>,
+}
+
+impl TranscriptionResponse {
+    /// Words that came with a probability, in order.
+    fn word_probabilities(&self) -> Vec<(String, f64)> {
+        let segments = self.segments.iter().flatten();
+        let words = match &self.words {
+            Some(words) if !words.is_empty() => words.iter().collect::<Vec<_>>(),
+            _ => segments.flat_map(|s| s.words.iter().flatten()).collect(),
+        };
+        words
+            .into_iter()
+            .filter_map(|w| Some((w.word.clone(), w.probability?)))
+            .collect()
+    }
+
+    fn timed_segments(&self) -> Vec<Segment> {
+        self.segments
+            .iter()
+            .flatten()
+            .map(|s| Segment {
+                start: s.start,
+                end: s.end,
+                text: s.text.trim().to_string(),
+            })
+            .collect()
+    }
+
+    /// Mean word probability, or failing that the segments' mean
+    /// `avg_logprob` as a probability.
+    fn confidence(&self, words: &[(String, f64)]) -> Option<f64> {
+        if !words.is_empty() {
+            return Some(words.iter().map(|(_, p)| p).sum::<f64>() / words.len() as f64);
+        }
+        let logprobs: Vec<f64> = self
+            .segments
+            .iter()
+            .flatten()
+            .filter_map(|s| s.avg_logprob)
+            .collect();
+        if logprobs.is_empty() {
+            return None;
+        }
+        Some((logprobs.iter().sum::<f64>() / logprobs.len() as f64).exp())
+    }
+}
+
+/// Any server speaking the OpenAI `/audio/transcriptions` API: OpenAI
+/// itself, Groq, or a self-hosted instance behind `base_url`.
+pub struct OpenAiCompatible {
+    name: &'static str,
+    client: reqwest::Client,
+    retries: u32,
+    endpoint: String,
+    models_url: String,
+    api_key: Option<String>,
+    model: String,
+    language: String,
+    prompt: String,
+    /// Ask for word timestamps, which carry per-word probabilities.
+    word_timestamps: bool,
+    /// `[provider_options]` as form fields, arrays spread over `key[]`.
+    options: Vec<(String, String)>,
+    /// `response_format` asks for text, srt or vtt rather than JSON.
+    plain_text: bool,
+}
+
+impl OpenAiCompatible {
+    pub async fn from_config(config: &Config) -> Result<Self, WayvoiceError> {
+        // Self-hosted OpenAI-compatible servers usually don't need a key
+        let api_key = match resolve_api_key(config).await {
+            Ok(key) => Some(key),
+            Err(_) if !config.base_url.is_empty() => None,
+            Err(e) => return Err(e),
+        };
+
+        let model = if config.model.is_empty() {
+            default_model(config.provider).to_string()
+        } else {
+            config.model.clone()
+        };
+
+        Ok(Self {
+            name: if config.provider == Provider::Groq {
+                "groq"
+            } else {
+                "openai"
+            },
+            client: config.http.clone(),
+            retries: config.request_retries,
+            endpoint: api_endpoint(config),
+            models_url: format!("{}/models", base_url(config)),
+            api_key,
+            model,
+            language: config.language.clone(),
+            prompt: config.prompt.clone(),
+            word_timestamps: config.min_word_confidence > 0.0,
+            options: form_options(&config.provider_options),
+            plain_text: config
+                .provider_options
+                .get("response_format")
+                .and_then(|format| format.as_str())
+                .is_some_and(|format| !format.contains("json")),
+        })
+    }
+
+    async fn request(&self, audio_data: Vec<u8>) -> Result<Transcript, WayvoiceError> {
+        debug!("provider={} endpoint={}", self.name, self.endpoint);
+
+        let upload = debug_span!("upload", bytes = audio_data.len());
+        let response = send_with_retry(self.name, self.retries, || {
+            let form = self.form(audio_data.clone())?;
+            let mut request = self.client.post(&self.endpoint).multipart(form);
+            if let Some(api_key) = &self.api_key {
+                request = request.bearer_auth(api_key);
+            }
+            Ok(request)
+        })
+        .instrument(upload)
+        .await?;
+
+        if !response.status().is_success() {
+            return Err(WayvoiceError::from_response(self.name, response).await);
+        }
+
+        if self.plain_text {
+            return Ok(Transcript {
+                text: response.text().await?.trim().to_string(),
+                ..Transcript::default()
+            });
+        }
+        let result: TranscriptionResponse = response.json().await?;
+        let words = result.word_probabilities();
+        Ok(Transcript {
+            text: result.text.trim().to_string(),
+            language: result.language.as_deref().map(language_code),
+            confidence: result.confidence(&words),
+            words,
+            segments: result.timed_segments(),
+        })
+    }
+
+    /// The speech-to-text models; the endpoint lists chat models too.
+    async fn list_models(&self) -> Result<Vec<String>, WayvoiceError> {
+        debug!("provider={} models={}", self.name, self.models_url);
+        let response = send_with_retry(self.name, self.retries, || {
+            let mut request = self.client.get(&self.models_url);
+            if let Some(api_key) = &self.api_key {
+                request = request.bearer_auth(api_key);
+            }
+            Ok(request)
+        })
+        .await?;
+
+        if !response.status().is_success() {
+            return Err(WayvoiceError::from_response(self.name, response).await);
+        }
+
+        let list: ModelList = response.json().await?;
+        let mut ids: Vec<String> = list.data.into_iter().map(|m| m.id).collect();
+        let speech: Vec<String> = ids
+            .iter()
+            .filter(|id| id.contains("whisper") || id.contains("transcribe"))
+            .cloned()
+            .collect();
+        // Self-hosted servers may name their models anything
+        if !speech.is_empty() {
+            ids = speech;
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn has_option(&self, key: &str) -> bool {
+        self.options.iter().any(|(k, _)| k == key)
+    }
+
+    fn form(&self, audio_data: Vec<u8>) -> Result<reqwest::multipart::Form, WayvoiceError> {
+        let (file_name, mime) = upload_format(&audio_data);
+        let file_part = reqwest::multipart::Part::bytes(audio_data)
+            .file_name(file_name)
+            .mime_str(mime)?;
+
+        let mut form = reqwest::multipart::Form::new()
+            .part("file", file_part)
+            .text("model", self.model.clone());
+
+        if !self.language.is_empty() {
+            form = form.text("language", self.language.clone());
+        }
+
+        // gpt-4o-transcribe and friends only do `json`, without the language
+        if !self.model.starts_with("gpt-4o") {
+            if !self.has_option("response_format") {
+                form = form.text("response_format", "verbose_json");
+            }
+            if self.word_timestamps && !self.has_option("timestamp_granularities[]") {
+                form = form
+                    .text("timestamp_granularities[]", "word")
+                    .text("timestamp_granularities[]", "segment");
+            }
+        }
+
+        if !self.prompt.is_empty() {
+            form = form.text("prompt", self.prompt.clone());
+        }
+        for (key, value) in &self.options {
+            form = form.text(key.clone(), value.clone());
+        }
+        Ok(form)
+    }
+}
+
+impl TranscriptionProvider for OpenAiCompatible {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn accepts_opus(&self) -> bool {
+        true
+    }
+
+    fn transcribe(&self, audio_data: Vec<u8>) -> BoxFuture<'_, Result<Transcript, WayvoiceError>> {
+        Box::pin(self.request(audio_data))
+    }
+
+    fn models(&self) -> BoxFuture<'_, Result<Vec<String>, WayvoiceError>> {
+        Box::pin(self.list_models())
+    }
+}
+
+async fn resolve_api_key(config: &Config) -> Result<String, WayvoiceError> {
+    api_key_for(config, config.provider).await
+}
+
+/// The Groq or OpenAI key from the config file, `api_key_command` or
+/// environment.
+pub async fn api_key_for(config: &Config, provider: Provider) -> Result<String, WayvoiceError> {
+    if provider == Provider::Groq {
+        if !config.groq_api_key.is_empty() {
+            return Ok(config.groq_api_key.clone());
+        }
+        if let Some(key) = command_api_key(config, provider).await {
+            return Ok(key);
+        }
+        return std::env::var("GROQ_API_KEY").map_err(|_| {
+            WayvoiceError::config("GROQ_API_KEY not set and no groq_api_key in voice.toml")
+        });
+    }
+
+    if !config.openai_api_key.is_empty() {
+        return Ok(config.openai_api_key.clone());
+    }
+    if let Some(key) = command_api_key(config, Provider::Openai).await {
+        return Ok(key);
+    }
+    std::env::var("OPENAI_API_KEY").map_err(|_| {
+        WayvoiceError::config("OPENAI_API_KEY not set and no openai_api_key in voice.toml")
+    })
+}
+
+/// Form fields for `[provider_options]`: a list becomes one `key[]` field
+/// per item, the way the API takes `timestamp_granularities[]`.
+fn form_options(options: &HashMap<String, toml::Value>) -> Vec<(String, String)> {
+    let text = |value: &toml::Value| match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    let mut fields = Vec::new();
+    for (key, value) in options {
+        match value {
+            toml::Value::Array(items) => {
+                let key = format!("{}[]", key.trim_end_matches("[]"));
+                fields.extend(items.iter().map(|item| (key.clone(), text(item))));
+            }
+            value => fields.push((key.clone(), text(value))),
+        }
+    }
+    fields.sort();
+    fields
+}
+
+fn base_url(config: &Config) -> &str {
+    let base_url = if config.base_url.is_empty() {
+        default_base_url(config.provider)
+    } else {
+        &config.base_url
+    };
+    base_url.trim_end_matches('/')
+}
+
+fn api_endpoint(config: &Config) -> String {
+    format!("{}/audio/transcriptions", base_url(config))
+}
+
+pub fn default_base_url(provider: Provider) -> &'static str {
+    match provider {
+        Provider::Groq => "https://api.groq.com/openai/v1",
+        _ => "https://api.openai.com/v1",
+    }
+}
+
+fn default_model(provider: Provider) -> &'static str {
+    match provider {
+        Provider::Groq => "whisper-large-v3-turbo",
+        _ => "whisper-1",
+    }
+}
@@ -0,0 +1,58 @@
+use super::{
+    BoxFuture, Transcript, TranscriptionProvider, audio_file, model_name, run_local_transcriber,
+};
+use crate::config::Config;
+use crate::error::WayvoiceError;
+use tokio::process::Command;
+
+/// Offline transcription through the `vosk-transcriber` CLI.
+pub struct Vosk {
+    bin: String,
+    model: String,
+}
+
+impl Vosk {
+    pub fn from_config(config: &Config) -> Result<Self, WayvoiceError> {
+        if config.vosk_model.is_empty() {
+            return Err(WayvoiceError::config("vosk_model not set in wayvoice.toml"));
+        }
+
+        Ok(Self {
+            bin: if config.vosk_bin.is_empty() {
+                "vosk-transcriber".to_string()
+            } else {
+                config.vosk_bin.clone()
+            },
+            model: config.vosk_model.clone(),
+        })
+    }
+
+    async fn run(&self, audio_data: Vec<u8>) -> Result<Transcript, WayvoiceError> {
+        let audio_file = audio_file("vosk")?;
+        tokio::fs::write(&audio_file, audio_data)
+            .await
+            .map_err(|e| WayvoiceError::Audio(format!("Failed to write {audio_file:?}: {e}")))?;
+
+        let mut cmd = Command::new(&self.bin);
+        cmd.args(["--model", &self.model])
+            .args(["--output-type", "txt", "--log-level", "error"])
+            .arg("--input")
+            .arg(&audio_file);
+
+        run_local_transcriber(cmd, &self.bin, &audio_file).await
+    }
+}
+
+impl TranscriptionProvider for Vosk {
+    fn name(&self) -> &'static str {
+        "vosk"
+    }
+
+    fn model(&self) -> &str {
+        model_name(&self.model)
+    }
+
+    fn transcribe(&self, audio_data: Vec<u8>) -> BoxFuture<'_, Result<Transcript, WayvoiceError>> {
+        Box::pin(self.run(audio_data))
+    }
+}
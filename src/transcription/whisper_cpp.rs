@@ -0,0 +1,77 @@
+use super::{
+    BoxFuture, Transcript, TranscriptionProvider, audio_file, model_name, run_local_transcriber,
+};
+use crate::config::Config;
+use crate::error::WayvoiceError;
+use tokio::process::Command;
+
+/// Offline transcription through a whisper.cpp `whisper-cli` binary.
+pub struct WhisperCpp {
+    bin: String,
+    model: String,
+    threads: u32,
+    language: String,
+    prompt: String,
+}
+
+impl WhisperCpp {
+    pub fn from_config(config: &Config) -> Result<Self, WayvoiceError> {
+        if config.whisper_cpp_model.is_empty() {
+            return Err(WayvoiceError::config(
+                "whisper_cpp_model not set in wayvoice.toml",
+            ));
+        }
+
+        Ok(Self {
+            bin: if config.whisper_cpp_bin.is_empty() {
+                "whisper-cli".to_string()
+            } else {
+                config.whisper_cpp_bin.clone()
+            },
+            model: config.whisper_cpp_model.clone(),
+            threads: config.whisper_cpp_threads,
+            language: if config.language.is_empty() {
+                "auto".to_string()
+            } else {
+                config.language.clone()
+            },
+            prompt: config.prompt.clone(),
+        })
+    }
+
+    async fn run(&self, audio_data: Vec<u8>) -> Result<Transcript, WayvoiceError> {
+        // whisper.cpp only reads from files, so hand it a copy of the audio
+        let audio_file = audio_file("whisper-cpp")?;
+        tokio::fs::write(&audio_file, audio_data)
+            .await
+            .map_err(|e| WayvoiceError::Audio(format!("Failed to write {audio_file:?}: {e}")))?;
+
+        let mut cmd = Command::new(&self.bin);
+        cmd.args(["--model", &self.model])
+            .args(["--language", &self.language])
+            .args(["--no-timestamps", "--no-prints"]);
+        if self.threads > 0 {
+            cmd.args(["--threads", &self.threads.to_string()]);
+        }
+        if !self.prompt.is_empty() {
+            cmd.args(["--prompt", &self.prompt]);
+        }
+        cmd.arg("--file").arg(&audio_file);
+
+        run_local_transcriber(cmd, &self.bin, &audio_file).await
+    }
+}
+
+impl TranscriptionProvider for WhisperCpp {
+    fn name(&self) -> &'static str {
+        "whisper-cpp"
+    }
+
+    fn model(&self) -> &str {
+        model_name(&self.model)
+    }
+
+    fn transcribe(&self, audio_data: Vec<u8>) -> BoxFuture<'_, Result<Transcript, WayvoiceError>> {
+        Box::pin(self.run(audio_data))
+    }
+}
@@ -0,0 +1,157 @@
+use crate::error::WayvoiceError;
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::{AsFd, FromRawFd};
+use std::time::{Duration, Instant};
+use wayland_client::globals::{GlobalListContents, registry_queue_init};
+use wayland_client::protocol::{wl_registry, wl_seat};
+use wayland_client::{Connection, Dispatch, QueueHandle};
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1;
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
+
+const KEYMAP_FORMAT_XKB_V1: u32 = 1;
+const KEY_RELEASED: u32 = 0;
+const KEY_PRESSED: u32 = 1;
+
+/// Type `text` through the compositor's `zwp_virtual_keyboard_v1` protocol.
+///
+/// Every distinct character gets its own keycode in a throwaway keymap, so
+/// any Unicode text can be typed regardless of the user's layout. This is
+/// blocking; call it from `spawn_blocking`.
+pub fn type_text(text: &str, key_delay: Duration) -> Result<(), WayvoiceError> {
+    let conn = Connection::connect_to_env().map_err(WayvoiceError::inject)?;
+    let (globals, mut queue) =
+        registry_queue_init::<State>(&conn).map_err(WayvoiceError::inject)?;
+    let qh = queue.handle();
+
+    let seat: wl_seat::WlSeat = globals
+        .bind(&qh, 1..=1, ())
+        .map_err(WayvoiceError::inject)?;
+    let manager: ZwpVirtualKeyboardManagerV1 = globals.bind(&qh, 1..=1, ()).map_err(|_| {
+        WayvoiceError::inject("compositor does not support zwp_virtual_keyboard_v1")
+    })?;
+    let keyboard = manager.create_virtual_keyboard(&seat, &qh, ());
+
+    let mut chars: Vec<char> = text.chars().collect();
+    chars.sort_unstable();
+    chars.dedup();
+
+    let keymap = build_keymap(&chars);
+    let keymap_file = keymap_fd(&keymap).map_err(WayvoiceError::inject)?;
+    keyboard.keymap(
+        KEYMAP_FORMAT_XKB_V1,
+        keymap_file.as_fd(),
+        keymap.len() as u32 + 1,
+    );
+    keyboard.modifiers(0, 0, 0, 0);
+    queue.roundtrip(&mut State).map_err(WayvoiceError::inject)?;
+
+    let start = Instant::now();
+    for c in text.chars() {
+        // Keycodes are evdev codes; xkb adds 8, matching `build_keymap`
+        let key = chars.binary_search(&c).unwrap_or_default() as u32 + 1;
+        let time = start.elapsed().as_millis() as u32;
+        keyboard.key(time, key, KEY_PRESSED);
+        keyboard.key(time, key, KEY_RELEASED);
+        queue.roundtrip(&mut State).map_err(WayvoiceError::inject)?;
+        if !key_delay.is_zero() {
+            std::thread::sleep(key_delay);
+        }
+    }
+
+    keyboard.destroy();
+    queue.roundtrip(&mut State).map_err(WayvoiceError::inject)?;
+    Ok(())
+}
+
+fn build_keymap(chars: &[char]) -> String {
+    let mut keycodes = String::new();
+    let mut symbols = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        let key = i + 1;
+        keycodes.push_str(&format!("<K{key}> = {};\n", key + 8));
+        symbols.push_str(&format!("key <K{key}> {{[{}]}};\n", keysym_name(*c)));
+    }
+
+    format!(
+        "xkb_keymap {{\n\
+         xkb_keycodes \"(unnamed)\" {{\nminimum = 8;\nmaximum = {};\n{keycodes}}};\n\
+         xkb_types \"(unnamed)\" {{ include \"complete\" }};\n\
+         xkb_compatibility \"(unnamed)\" {{ include \"complete\" }};\n\
+         xkb_symbols \"(unnamed)\" {{\n{symbols}}};\n\
+         }};\n",
+        chars.len() + 8
+    )
+}
+
+fn keysym_name(c: char) -> String {
+    match c {
+        '\n' => "Return".to_string(),
+        '\t' => "Tab".to_string(),
+        '\u{8}' => "BackSpace".to_string(),
+        _ => format!("U{:04X}", c as u32),
+    }
+}
+
+/// The keymap is handed to the compositor as a NUL-terminated memfd.
+fn keymap_fd(keymap: &str) -> std::io::Result<File> {
+    // SAFETY: the name is a valid C string and the fd is owned by the File
+    let fd = unsafe { libc::memfd_create(c"wayvoice-keymap".as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(keymap.as_bytes())?;
+    file.write_all(b"\0")?;
+    Ok(file)
+}
+
+struct State;
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_seat::WlSeat, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_seat::WlSeat,
+        _: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwpVirtualKeyboardManagerV1, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &ZwpVirtualKeyboardManagerV1,
+        _: <ZwpVirtualKeyboardManagerV1 as wayland_client::Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwpVirtualKeyboardV1, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &ZwpVirtualKeyboardV1,
+        _: <ZwpVirtualKeyboardV1 as wayland_client::Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
@@ -0,0 +1,80 @@
+use crate::daemon::Event;
+use crate::ipc::subscribe;
+use serde::Serialize;
+use std::time::Duration;
+use tracing::debug;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// One line of Waybar custom-module output (`"return-type": "json"`).
+#[derive(Serialize)]
+struct Output<'a> {
+    text: &'a str,
+    /// Lets `format-icons` pick an icon per state.
+    alt: &'a str,
+    class: &'a str,
+    tooltip: String,
+}
+
+/// Print Waybar JSON on every state change, reconnecting whenever the
+/// daemon isn't running so the module survives daemon restarts.
+pub async fn run_waybar() {
+    let mut offline_shown = false;
+    let mut last_text = String::new();
+    loop {
+        let mut state = "idle";
+        let result = subscribe(|event| {
+            offline_shown = false;
+            match event {
+                Event::Idle => state = "idle",
+                Event::Recording => state = "recording",
+                Event::Transcribing => state = "transcribing",
+                Event::Text { text, .. } => last_text = text,
+                Event::Error { message } => {
+                    print_output(&Output {
+                        text: "error",
+                        alt: "error",
+                        class: "error",
+                        tooltip: format!("wayvoice: {message}"),
+                    });
+                    return;
+                }
+            }
+            print_output(&Output {
+                text: state,
+                alt: state,
+                class: state,
+                tooltip: tooltip(state, &last_text),
+            });
+        })
+        .await;
+
+        if let Err(e) = result {
+            debug!("waybar: {e}");
+        }
+        if !offline_shown {
+            print_output(&Output {
+                text: "offline",
+                alt: "offline",
+                class: "offline",
+                tooltip: "wayvoice: daemon not running".to_string(),
+            });
+            offline_shown = true;
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+fn tooltip(state: &str, last_text: &str) -> String {
+    if last_text.is_empty() {
+        format!("wayvoice: {state}")
+    } else {
+        format!("wayvoice: {state}\nLast: {last_text}")
+    }
+}
+
+fn print_output(output: &Output) {
+    if let Ok(line) = serde_json::to_string(output) {
+        println!("{line}");
+    }
+}